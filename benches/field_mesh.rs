@@ -0,0 +1,71 @@
+//! フィールドのメッシュを、毎回すべて描き直すのと変わった区画だけ描き直すのとを比べる
+//!
+//! `cargo bench --bench field_mesh`で実行する。
+//! 対戦中のように、1tickごとに2人のプレイヤーがいたマスが1段ずつ高くなるフィールドを描く。
+
+use blocking_io_client::types::*;
+use blocking_io_client::world::World;
+use blocking_io_client::FIELD_SIZE;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// 描くフィールドの数。1分の対戦のtick数
+const TICKS: usize = 300;
+
+fn fields() -> Vec<FieldMatrix> {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut field = FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 1);
+    (0..TICKS)
+        .map(|_| {
+            for _ in 0..2 {
+                let x = rng.gen_range(0..FIELD_SIZE);
+                let z = rng.gen_range(0..FIELD_SIZE);
+                field[(x, z)] = (field[(x, z)] + 1).min(8);
+            }
+            field.clone()
+        })
+        .collect()
+}
+
+/// `fields`を順に描き、GPUに送る頂点の数を返す
+fn render(world: &mut World, fields: &[FieldMatrix], whole: bool) -> usize {
+    let mut vertices = 0;
+    for field in fields {
+        world.update(field.clone());
+        if whole {
+            world.invalidate_field();
+        }
+        world.render_field();
+        vertices += world
+            .changed_field_vertices()
+            .iter()
+            .map(|range| range.len())
+            .sum::<usize>();
+    }
+    vertices
+}
+
+fn field_mesh(c: &mut Criterion) {
+    let fields = fields();
+    let mut world = World::new();
+    world.render_field();
+
+    let whole = render(&mut world, &fields, true);
+    let changed = render(&mut world, &fields, false);
+    println!(
+        "vertices sent per tick: whole {}, changed chunks only {}",
+        whole / TICKS,
+        changed / TICKS
+    );
+
+    let mut group = c.benchmark_group("field_mesh");
+    group.bench_function("whole", |b| b.iter(|| render(&mut world, &fields, true)));
+    group.bench_function("changed_chunks", |b| {
+        b.iter(|| render(&mut world, &fields, false))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, field_mesh);
+criterion_main!(benches);
@@ -1,4 +1,6 @@
 use crate::gui::layout::{Origin, Position, Rect};
+use crate::text::GlyphAtlas;
+use crate::types::Vector3;
 use crate::{texture::dynamic_texture_atlas::DynamicTextureUV, vao::VaoBuffer};
 
 pub trait VaoBuilder2DGui {
@@ -22,6 +24,37 @@ pub trait VaoBuilder2DGui {
         inner_width: u32,
         inner_height: u32,
     );
+    /// `text`を描く。`\n`で改行し、各行は左に揃える
+    ///
+    /// * `x`, `y` - 1行目の左上の位置
+    /// * `size` - 文字の大きさ(ピクセル)。`atlas.px()`と同じなら拡大縮小しない
+    ///
+    /// 色は法線の代わりに頂点に入れるので、法線を色として使うシェーダーで描く。
+    fn add_text(
+        &mut self,
+        atlas: &mut GlyphAtlas,
+        text: &str,
+        x: f32,
+        y: f32,
+        size: f32,
+        color: &Vector3,
+    );
+    /// `text`を、`add_layout_rectangle`と同じように親の長方形の中に置いて描く
+    ///
+    /// 文字列全体の大きさは`GlyphAtlas::measure`で測る。
+    /// 複数行のときは、`origin`の左右の位置に合わせて各行を揃える。
+    fn add_layout_text(
+        &mut self,
+        atlas: &mut GlyphAtlas,
+        text: &str,
+        parent_width: u32,
+        parent_height: u32,
+        origin: &Origin,
+        position_x: &Position<i32>,
+        position_y: &Position<i32>,
+        size: f32,
+        color: &Vector3,
+    );
 }
 
 impl VaoBuilder2DGui for VaoBuffer {
@@ -81,4 +114,79 @@ impl VaoBuilder2DGui for VaoBuffer {
         );
         self.add_rectangle(texture, &dst);
     }
+
+    fn add_text(
+        &mut self,
+        atlas: &mut GlyphAtlas,
+        text: &str,
+        x: f32,
+        y: f32,
+        size: f32,
+        color: &Vector3,
+    ) {
+        let scale = size / atlas.px();
+        let mut baseline = y + atlas.ascent() * scale;
+        let mut pen_x = x;
+        for c in text.chars() {
+            if c == '\n' {
+                baseline += atlas.line_height() * scale;
+                pen_x = x;
+                continue;
+            }
+            let glyph = atlas.glyph(c);
+            if let Some(ref texture) = glyph.uv {
+                // 画素の境界に合わせないと文字がぼやける
+                let x = (pen_x + glyph.left * scale).round();
+                let y = (baseline + glyph.top * scale).round();
+                let w = glyph.width * scale;
+                let h = glyph.height * scale;
+                let (r, g, b) = (color.x, color.y, color.z);
+
+                #[rustfmt::skip]
+                let mut vert: Vec<f32> = vec![
+                    x  , y  , 0.0,  r, g, b,  texture.begin_u, texture.begin_v,
+                    x  , y+h, 0.0,  r, g, b,  texture.begin_u, texture.end_v  ,
+                    x+w, y+h, 0.0,  r, g, b,  texture.end_u  , texture.end_v  ,
+
+                    x  , y  , 0.0,  r, g, b,  texture.begin_u, texture.begin_v,
+                    x+w, y+h, 0.0,  r, g, b,  texture.end_u  , texture.end_v  ,
+                    x+w, y  , 0.0,  r, g, b,  texture.end_u  , texture.begin_v,
+                ];
+                self.append(&mut vert);
+            }
+            pen_x += glyph.advance * scale;
+        }
+    }
+
+    fn add_layout_text(
+        &mut self,
+        atlas: &mut GlyphAtlas,
+        text: &str,
+        parent_width: u32,
+        parent_height: u32,
+        origin: &Origin,
+        position_x: &Position<i32>,
+        position_y: &Position<i32>,
+        size: f32,
+        color: &Vector3,
+    ) {
+        let scale = size / atlas.px();
+        let (width, height) = atlas.measure(text);
+        let width = (width * scale).ceil() as u32;
+        let dst = Rect::new_in_rect(
+            &Rect::new(0_i32, 0_i32, parent_width, parent_height),
+            origin,
+            position_x,
+            position_y,
+            width,
+            (height * scale).ceil() as u32,
+        );
+        let line_height = atlas.line_height() * scale;
+        for (i, line) in text.split('\n').enumerate() {
+            let line_width = (atlas.text_width(line) * scale).ceil() as u32;
+            let x = dst.origin_x() + origin.x_diff(width) - origin.x_diff(line_width);
+            let y = *dst.origin_y() as f32 + i as f32 * line_height;
+            self.add_text(atlas, line, x as f32, y, size, color);
+        }
+    }
 }
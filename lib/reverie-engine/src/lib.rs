@@ -4,6 +4,7 @@ pub use interpolation::Interpolation;
 pub mod shader;
 pub use shader::{Program, Shader};
 pub mod gui;
+pub mod text;
 pub mod texture;
 pub mod types;
 pub mod vao;
@@ -0,0 +1,52 @@
+//! 文字を描くのに使うフォントの一覧
+
+use ab_glyph::{Font, FontVec, GlyphId, InvalidFont};
+
+/// 文字を描くのに使うフォントの一覧
+///
+/// 先に追加したフォントほど優先する。あるフォントにない文字は、次のフォントで描く。
+#[derive(Default)]
+pub struct FontSet {
+    fonts: Vec<FontVec>,
+}
+
+impl FontSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// フォントファイルの中身を追加する
+    ///
+    /// * `index` - フォントコレクション(.ttc)の何番目のフォントを使うか。.ttfなら0
+    pub fn add(&mut self, data: Vec<u8>, index: u32) -> Result<(), InvalidFont> {
+        self.fonts.push(FontVec::try_from_vec_and_index(data, index)?);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.fonts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fonts.is_empty()
+    }
+
+    /// `c`を持っているフォントがあるかどうか
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.fonts.iter().any(|font| font.glyph_id(c).0 != 0)
+    }
+
+    /// 行の高さなどを決めるフォント
+    pub(crate) fn primary(&self) -> Option<&FontVec> {
+        self.fonts.first()
+    }
+
+    /// `c`を持っている最初のフォント。どのフォントにもなければ、1番目のフォントの「.notdef」
+    pub(crate) fn find(&self, c: char) -> Option<(&FontVec, GlyphId)> {
+        self.fonts
+            .iter()
+            .map(|font| (font, font.glyph_id(c)))
+            .find(|(_, id)| id.0 != 0)
+            .or_else(|| self.primary().map(|font| (font, GlyphId(0))))
+    }
+}
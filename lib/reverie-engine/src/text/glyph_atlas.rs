@@ -0,0 +1,334 @@
+//! 文字の画像を、必要になったときに作って1枚のテクスチャに詰める
+
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_void;
+
+use ab_glyph::{point, Font, PxScale, ScaleFont};
+
+use super::FontSet;
+use crate::gl;
+use crate::gl::Gl;
+use crate::gui::layout::Rect;
+use crate::texture::dynamic_texture_atlas::DynamicTextureUV;
+use crate::texture::image_manager::ImageLoadInfo;
+
+/// 文字の画像どうしの間に空けるピクセル数。線形補間で隣の文字がにじまないようにする
+const PADDING: u32 = 1;
+
+/// アトラスに詰めた1文字
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Glyph {
+    /// テクスチャの中の位置。空白のように描く部分がない文字は`None`
+    pub uv: Option<DynamicTextureUV>,
+    /// ベースラインの左端から見た、画像の左端の位置(ピクセル)
+    pub left: f32,
+    /// ベースラインから見た、画像の上端の位置(ピクセル)。下が正なので、ふつうは負になる
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+    /// 次の文字までの距離(ピクセル)
+    pub advance: f32,
+}
+
+/// 左上から順に、行ごとに画像を並べていく
+struct Shelf {
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    row_height: u32,
+}
+
+impl Shelf {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            x: 0,
+            y: 0,
+            row_height: 0,
+        }
+    }
+
+    /// `width`x`height`の画像を置く場所。いっぱいなら`None`
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if width > self.width {
+            return None;
+        }
+        if self.x + width > self.width {
+            self.x = 0;
+            self.y += self.row_height + PADDING;
+            self.row_height = 0;
+        }
+        if self.y + height > self.height {
+            return None;
+        }
+        let pos = (self.x, self.y);
+        self.x += width + PADDING;
+        self.row_height = self.row_height.max(height);
+        Some(pos)
+    }
+}
+
+/// 文字の画像を詰めたテクスチャ
+///
+/// 文字は`px`ピクセルの大きさでラスタライズしておき、描くときに拡大縮小する。
+/// テクスチャは1ピクセル1バイトで、文字の濃さだけが入っている。
+///
+/// テクスチャがいっぱいになったら、入らない文字はそのフレームでは描かない。
+/// 次のフレームの初めの`begin_frame`で、前のフレームで使わなかった文字を捨てて詰め直す。
+/// フレームの途中では、すでに詰めた文字の位置は変わらない。
+pub struct GlyphAtlas {
+    fonts: FontSet,
+    px: f32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    shelf: Shelf,
+    glyphs: HashMap<char, Glyph>,
+    /// このフレームで使った文字
+    used: HashSet<char>,
+    /// このフレームで、いっぱいで詰められなかった文字がある
+    overflowed: bool,
+    /// `pixels`を書き換えたが、まだテクスチャに送っていない
+    dirty: bool,
+    texture: Option<ImageLoadInfo<'static>>,
+}
+
+impl GlyphAtlas {
+    /// * `px` - ラスタライズするときの文字の大きさ(ピクセル)
+    /// * `width`, `height` - テクスチャの大きさ
+    pub fn new(fonts: FontSet, px: f32, width: u32, height: u32) -> Self {
+        Self {
+            fonts,
+            px,
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+            shelf: Shelf::new(width, height),
+            glyphs: HashMap::new(),
+            used: HashSet::new(),
+            overflowed: false,
+            dirty: true,
+            texture: None,
+        }
+    }
+
+    /// ラスタライズするときの文字の大きさ(ピクセル)
+    pub fn px(&self) -> f32 {
+        self.px
+    }
+
+    /// 行の上端からベースラインまでの距離(ピクセル)
+    pub fn ascent(&self) -> f32 {
+        match self.fonts.primary() {
+            Some(font) => font.as_scaled(PxScale::from(self.px)).ascent(),
+            None => self.px,
+        }
+    }
+
+    /// 行の高さ(ピクセル)
+    pub fn line_height(&self) -> f32 {
+        match self.fonts.primary() {
+            Some(font) => {
+                let font = font.as_scaled(PxScale::from(self.px));
+                font.height() + font.line_gap()
+            }
+            None => self.px,
+        }
+    }
+
+    /// `c`の画像をアトラスに詰めて返す。すでに詰めてあればそれを返す
+    ///
+    /// いっぱいで詰められなければ、大きさと位置だけを返す(`uv`は`None`)。
+    pub fn glyph(&mut self, c: char) -> Glyph {
+        self.used.insert(c);
+        if let Some(glyph) = self.glyphs.get(&c) {
+            return *glyph;
+        }
+        match self.rasterize(c) {
+            Some(glyph) => {
+                self.glyphs.insert(c, glyph);
+                glyph
+            }
+            None => {
+                self.overflowed = true;
+                Glyph {
+                    uv: None,
+                    ..self.measure_glyph(c)
+                }
+            }
+        }
+    }
+
+    /// フレームの初めに呼ぶ
+    ///
+    /// 前のフレームで詰められなかった文字があれば、前のフレームで使った文字だけを詰め直す。
+    pub fn begin_frame(&mut self) {
+        let used = std::mem::take(&mut self.used);
+        if !self.overflowed {
+            return;
+        }
+        self.overflowed = false;
+        self.clear();
+        let mut used: Vec<char> = used.into_iter().collect();
+        used.sort_unstable();
+        for c in used {
+            if let Some(glyph) = self.rasterize(c) {
+                self.glyphs.insert(c, glyph);
+            }
+        }
+    }
+
+    /// 1行の文字列の幅(ピクセル)
+    pub fn text_width(&mut self, text: &str) -> f32 {
+        text.chars().map(|c| self.glyph(c).advance).sum()
+    }
+
+    /// 文字列を描いたときの幅と高さ(ピクセル)。`\n`で改行する
+    ///
+    /// 幅は一番長い行の幅、高さは行の高さと行数の積。
+    pub fn measure(&mut self, text: &str) -> (f32, f32) {
+        let width = text
+            .split('\n')
+            .map(|line| self.text_width(line))
+            .fold(0.0, f32::max);
+        let lines = text.split('\n').count();
+        (width, self.line_height() * lines as f32)
+    }
+
+    /// テクスチャ。詰めた文字が増えていれば、OpenGLに送り直す
+    pub fn texture(&mut self, gl: &Gl) -> &ImageLoadInfo<'static> {
+        let width = self.width;
+        let height = self.height;
+        let texture = self.texture.get_or_insert_with(|| {
+            let mut texture = 0;
+            unsafe {
+                gl.GenTextures(1, &mut texture);
+                gl.BindTexture(gl::TEXTURE_2D, texture);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+                gl.BindTexture(gl::TEXTURE_2D, 0);
+            }
+            ImageLoadInfo {
+                gl_id: texture,
+                id: "glyph_atlas",
+                width,
+                height,
+            }
+        });
+        if self.dirty {
+            unsafe {
+                gl.BindTexture(gl::TEXTURE_2D, texture.gl_id);
+                gl.PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+                gl.TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::R8 as i32,
+                    width as i32,
+                    height as i32,
+                    0,
+                    gl::RED,
+                    gl::UNSIGNED_BYTE,
+                    self.pixels.as_ptr() as *const c_void,
+                );
+                gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+                gl.BindTexture(gl::TEXTURE_2D, 0);
+            }
+            self.dirty = false;
+        }
+        texture
+    }
+
+    /// 詰めた文字をすべて捨てる
+    fn clear(&mut self) {
+        self.glyphs.clear();
+        self.shelf = Shelf::new(self.width, self.height);
+        self.pixels.iter_mut().for_each(|pixel| *pixel = 0);
+        self.dirty = true;
+    }
+
+    /// 画像を作らずに、大きさと位置だけを調べる
+    fn measure_glyph(&self, c: char) -> Glyph {
+        let (font, id) = match self.fonts.find(c) {
+            Some(found) => found,
+            None => {
+                return Glyph {
+                    uv: None,
+                    left: 0.0,
+                    top: 0.0,
+                    width: 0.0,
+                    height: 0.0,
+                    advance: 0.0,
+                }
+            }
+        };
+        let scale = PxScale::from(self.px);
+        let advance = font.as_scaled(scale).h_advance(id);
+        match font.outline_glyph(id.with_scale_and_position(scale, point(0.0, 0.0))) {
+            Some(outlined) => {
+                let bounds = outlined.px_bounds();
+                Glyph {
+                    uv: None,
+                    left: bounds.min.x,
+                    top: bounds.min.y,
+                    width: bounds.width(),
+                    height: bounds.height(),
+                    advance,
+                }
+            }
+            None => Glyph {
+                uv: None,
+                left: 0.0,
+                top: 0.0,
+                width: 0.0,
+                height: 0.0,
+                advance,
+            },
+        }
+    }
+
+    /// `c`を描いてアトラスに詰める。場所が足りなければ`None`
+    fn rasterize(&mut self, c: char) -> Option<Glyph> {
+        let glyph = self.measure_glyph(c);
+        if glyph.width == 0.0 || glyph.height == 0.0 {
+            return Some(glyph);
+        }
+        let (font, id) = self.fonts.find(c)?;
+        let scale = PxScale::from(self.px);
+        let outlined = font.outline_glyph(id.with_scale_and_position(scale, point(0.0, 0.0)))?;
+        let (x, y) = self.shelf.allocate(glyph.width as u32, glyph.height as u32)?;
+
+        let atlas_width = self.width;
+        let pixels = &mut self.pixels;
+        outlined.draw(|dx, dy, coverage| {
+            let index = ((y + dy) * atlas_width + x + dx) as usize;
+            pixels[index] = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+        });
+        self.dirty = true;
+
+        let rect = Rect::new(x as i32, y as i32, glyph.width as u32, glyph.height as u32);
+        Some(Glyph {
+            uv: Some(DynamicTextureUV::new(&rect, self.width, self.height)),
+            ..glyph
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shelf_wraps_rows() {
+        let mut shelf = Shelf::new(10, 10);
+        assert_eq!(shelf.allocate(4, 3), Some((0, 0)));
+        assert_eq!(shelf.allocate(4, 2), Some((5, 0)));
+        // 行に入らないので次の行へ。行の高さは一番高い画像に合わせる
+        assert_eq!(shelf.allocate(4, 4), Some((0, 4)));
+        assert_eq!(shelf.allocate(6, 4), None);
+        assert_eq!(shelf.allocate(11, 1), None);
+    }
+}
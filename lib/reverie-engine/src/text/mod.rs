@@ -0,0 +1,9 @@
+//! 文字列を描画するためのモジュール
+//!
+//! フォントから文字を1つずつラスタライズして`GlyphAtlas`のテクスチャに詰め、
+//! 文字ごとに長方形を並べて描く。
+
+pub mod font_set;
+pub use font_set::FontSet;
+pub mod glyph_atlas;
+pub use glyph_atlas::{Glyph, GlyphAtlas};
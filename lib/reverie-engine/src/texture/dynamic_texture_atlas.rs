@@ -1,5 +1,6 @@
 use crate::gui::layout::Rect;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DynamicTextureUV {
     pub begin_u: f32,
     pub begin_v: f32,
@@ -0,0 +1,101 @@
+//! インスタンスごとの情報を並べるバッファ
+
+use crate::types::*;
+
+/// 1つのインスタンスは`INSTANCE_SIZE`個のf32から成る。
+///
+/// * 頂点に足すx, y, z座標
+/// * 頂点に掛けるx, y, z方向の倍率
+/// * テクスチャのu, v座標に足す値
+pub const INSTANCE_SIZE: usize = 8;
+
+/// インスタンスの情報を受け取るシェーダーの属性の、最初のlocation
+///
+/// 座標、倍率、テクスチャのずれの順に、`INSTANCE_LOCATION`から続けて割り当てる。
+pub const INSTANCE_LOCATION: u32 = 3;
+
+/// 同じ形を位置や大きさを変えて何度も描くための、インスタンスごとの情報のバッファ
+///
+/// `Vao::set_instances`で渡すと、`Vao`の頂点をインスタンスの数だけ描く。
+pub struct InstanceBuffer {
+    buffer: Vec<f32>,
+    instance_num: i32,
+}
+
+impl Default for InstanceBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstanceBuffer {
+    /// 空の`InstanceBuffer`を作る
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            instance_num: 0,
+        }
+    }
+
+    /// インスタンスを追加する
+    ///
+    /// 頂点の座標は`scale`倍してから`translation`だけ動かし、テクスチャのUV座標には`tex_offset`を足す。
+    pub fn add(&mut self, translation: &Vector3, scale: &Vector3, tex_offset: (f32, f32)) {
+        self.buffer.extend_from_slice(&[
+            translation.x,
+            translation.y,
+            translation.z,
+            scale.x,
+            scale.y,
+            scale.z,
+            tex_offset.0,
+            tex_offset.1,
+        ]);
+        self.instance_num += 1;
+    }
+
+    /// インスタンスの数
+    pub fn instance_num(&self) -> usize {
+        self.instance_num as usize
+    }
+
+    /// インスタンスの情報。インスタンスごとに`INSTANCE_SIZE`個のf32が並ぶ
+    pub fn instances(&self) -> &[f32] {
+        &self.buffer
+    }
+
+    /// すべてのインスタンスを削除する
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.instance_num = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_and_clear() {
+        let mut instances = InstanceBuffer::new();
+        instances.add(
+            &Vector3::new(1.0, 2.0, 3.0),
+            &Vector3::new(1.0, 1.0, 1.0),
+            (0.0, 0.0),
+        );
+        instances.add(
+            &Vector3::new(4.0, 5.0, 6.0),
+            &Vector3::new(1.0, 2.0, 1.0),
+            (0.25, -0.5),
+        );
+        assert_eq!(instances.instance_num(), 2);
+        assert_eq!(
+            &instances.instances()[INSTANCE_SIZE..],
+            &[4.0, 5.0, 6.0, 1.0, 2.0, 1.0, 0.25, -0.5]
+        );
+
+        instances.clear();
+        assert_eq!(instances.instance_num(), 0);
+        assert!(instances.instances().is_empty());
+    }
+}
@@ -2,12 +2,16 @@
 
 use std::mem;
 use std::os::raw::c_void;
+use std::ptr;
 
 use crate::gl;
-use crate::gl::types::{GLenum, GLfloat, GLint, GLsizei, GLsizeiptr};
+use crate::gl::types::{GLenum, GLfloat, GLint, GLintptr, GLsizei, GLsizeiptr};
 use crate::gl::Gl;
 use crate::shader::UniformVariables;
+use instance_buffer::{INSTANCE_LOCATION, INSTANCE_SIZE};
 
+pub mod instance_buffer;
+pub use instance_buffer::InstanceBuffer;
 pub mod vao_buffer;
 pub use vao_buffer::VaoBuffer;
 mod vao_builder;
@@ -15,17 +19,29 @@ pub use vao_builder::{CuboidTextures, VaoBuilder3DGeometry};
 mod vao_config;
 pub use vao_config::{VaoConfig, VaoConfigBuilder};
 
-/// OpenGLのVertex Array ObjectとVertex Buffer Objectに対応する構造体
+/// OpenGLのVertex Array ObjectとVertex Buffer Object、Element Buffer Objectに対応する構造体
+///
+/// `set_instances`でインスタンスごとの情報を渡すと、同じ頂点をインスタンスの数だけ描く。
+/// シェーダーは`instance_buffer::INSTANCE_LOCATION`からの属性でインスタンスの情報を受け取る。
 pub struct Vao<'a> {
     gl: Gl,
     vao: u32,
     vbo: u32,
+    /// インデックスを使わずに描くなら0
+    ebo: u32,
     vertex_num: i32,
+    /// `ebo`のインデックスの数
+    index_num: i32,
+    /// インスタンスごとの情報のバッファ。`set_instances`を呼ぶまでは0
+    instance_vbo: u32,
+    instance_num: i32,
     config: &'a VaoConfig<'a>,
 }
 
 impl<'a> Vao<'a> {
     /// 代わりに`VaoBuilder`を使うことを推奨
+    ///
+    /// `indices`を渡すと、頂点をそのインデックスの順に`glDrawElements`で描く。
     pub fn new(
         gl: Gl,
         size: GLsizeiptr,
@@ -36,13 +52,15 @@ impl<'a> Vao<'a> {
         attribute_size_vec: std::vec::Vec<GLint>,
         stride: GLsizei,
         vertex_num: i32,
+        indices: Option<&[u32]>,
         config: &'a VaoConfig<'a>,
-    ) -> Vao {
+    ) -> Vao<'a> {
         debug_assert_eq!(num_attributes, attribute_type_vec.len());
         debug_assert_eq!(num_attributes, attribute_size_vec.len());
 
         let mut vao = 0;
         let mut vbo = 0;
+        let mut ebo = 0;
 
         unsafe {
             // create vertex array object and vertex buffer object
@@ -68,6 +86,18 @@ impl<'a> Vao<'a> {
                 offset += attribute_size_vec[i] as usize;
             }
 
+            // EBOの割り当てはVAOに記録されるので、VAOを外すまでバインドしたままにする
+            if let Some(indices) = indices {
+                gl.GenBuffers(1, &mut ebo);
+                gl.BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+                gl.BufferData(
+                    gl::ELEMENT_ARRAY_BUFFER,
+                    mem::size_of_val(indices) as GLsizeiptr,
+                    indices.as_ptr() as *const c_void,
+                    usage,
+                );
+            }
+
             // unbind
             gl.BindBuffer(gl::ARRAY_BUFFER, 0);
             gl.BindVertexArray(0);
@@ -77,11 +107,68 @@ impl<'a> Vao<'a> {
             gl,
             vao,
             vbo,
+            ebo,
             vertex_num,
+            index_num: indices.map_or(0, |indices| indices.len() as i32),
+            instance_vbo: 0,
+            instance_num: 0,
             config,
         }
     }
 
+    /// 頂点を`instances`のインスタンスの数だけ、それぞれの位置や大きさで描くようにする
+    ///
+    /// 一度呼ぶと、以後はインスタンスが0個なら何も描かない。
+    pub fn set_instances(&mut self, instances: &InstanceBuffer) {
+        unsafe {
+            if self.instance_vbo == 0 {
+                self.gl.GenBuffers(1, &mut self.instance_vbo);
+                self.gl.BindVertexArray(self.vao);
+                self.gl.BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+                let stride = (INSTANCE_SIZE * mem::size_of::<GLfloat>()) as GLsizei;
+                let mut offset = 0;
+                for (i, &size) in [3, 3, 2].iter().enumerate() {
+                    let location = INSTANCE_LOCATION + i as u32;
+                    self.gl.EnableVertexAttribArray(location);
+                    self.gl.VertexAttribPointer(
+                        location,
+                        size,
+                        gl::FLOAT,
+                        gl::FALSE,
+                        stride,
+                        (offset * mem::size_of::<GLfloat>()) as *const c_void,
+                    );
+                    // 頂点ごとではなく、インスタンスごとに次の値に進む
+                    self.gl.VertexAttribDivisor(location, 1);
+                    offset += size as usize;
+                }
+                self.gl.BindVertexArray(0);
+            } else {
+                self.gl.BindBuffer(gl::ARRAY_BUFFER, self.instance_vbo);
+            }
+            let data = instances.instances();
+            self.gl.BufferData(
+                gl::ARRAY_BUFFER,
+                mem::size_of_val(data) as GLsizeiptr,
+                data.as_ptr() as *const c_void,
+                gl::DYNAMIC_DRAW,
+            );
+            self.gl.BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.instance_num = instances.instance_num() as i32;
+    }
+
+    /// 頂点バッファの`offset`バイト目から`size`バイトを`data`で書き換える
+    ///
+    /// 何度も書き換えるなら、`GL_DYNAMIC_DRAW`で作っておく(`VaoBuffer::build_dynamic`)。
+    pub fn update(&self, offset: GLintptr, size: GLsizeiptr, data: *const c_void) {
+        unsafe {
+            self.gl.BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            self.gl.BufferSubData(gl::ARRAY_BUFFER, offset, size, data);
+            self.gl.BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
     pub fn draw(&self, uniforms: &UniformVariables, draw_mode: GLenum) {
         unsafe {
             if let Some(texture) = self.config.texture {
@@ -133,7 +220,31 @@ impl<'a> Vao<'a> {
             self.config.program.set_used();
             self.config.program.set_uniforms(uniforms);
             self.gl.BindVertexArray(self.vao);
-            self.gl.DrawArrays(draw_mode, 0, self.vertex_num);
+            if self.instance_vbo == 0 {
+                // インスタンスの属性を読まないので、動かさず、大きさも変えずに描く
+                let location = INSTANCE_LOCATION;
+                self.gl.VertexAttrib3f(location, 0.0, 0.0, 0.0);
+                self.gl.VertexAttrib3f(location + 1, 1.0, 1.0, 1.0);
+                self.gl.VertexAttrib2f(location + 2, 0.0, 0.0);
+            }
+            match (self.ebo > 0, self.instance_vbo > 0) {
+                (true, true) => self.gl.DrawElementsInstanced(
+                    draw_mode,
+                    self.index_num,
+                    gl::UNSIGNED_INT,
+                    ptr::null(),
+                    self.instance_num,
+                ),
+                (true, false) => {
+                    self.gl
+                        .DrawElements(draw_mode, self.index_num, gl::UNSIGNED_INT, ptr::null())
+                }
+                (false, true) => {
+                    self.gl
+                        .DrawArraysInstanced(draw_mode, 0, self.vertex_num, self.instance_num)
+                }
+                (false, false) => self.gl.DrawArrays(draw_mode, 0, self.vertex_num),
+            }
             self.gl.BindVertexArray(0);
             self.gl.BindTexture(gl::TEXTURE_2D, 0);
         }
@@ -151,6 +262,12 @@ impl<'a> Drop for Vao<'a> {
             if self.vbo > 0 {
                 self.gl.DeleteBuffers(1, &self.vbo as _);
             }
+            if self.ebo > 0 {
+                self.gl.DeleteBuffers(1, &self.ebo as _);
+            }
+            if self.instance_vbo > 0 {
+                self.gl.DeleteBuffers(1, &self.instance_vbo as _);
+            }
             if self.vao > 0 {
                 self.gl.DeleteVertexArrays(1, &self.vao as _);
             }
@@ -1,12 +1,16 @@
 //! 頂点の情報を動的に追加・削除するためのバッファ
 
 use std::mem;
+use std::ops::Range;
 
 use super::vao_config::VaoConfig;
 use super::Vao;
 
 use crate::gl;
-use crate::gl::{types::GLfloat, Gl};
+use crate::gl::{
+    types::{GLenum, GLfloat},
+    Gl,
+};
 
 /// 1つの頂点は`VERTEX_SIZE`個のf32から成る。
 ///
@@ -16,9 +20,14 @@ use crate::gl::{types::GLfloat, Gl};
 pub const VERTEX_SIZE: usize = 8;
 
 /// 頂点の情報を動的に追加・削除するためのバッファ
+///
+/// `new_indexed`で作ったバッファは頂点のインデックスも持ち、`Vao`は`glDrawElements`で描く。
+/// `VaoBuilder3DGeometry`で追加する面は、インデックスを使って頂点を共有する。
 pub struct VaoBuffer {
     buffer: Vec<f32>,
     vertex_num: i32,
+    /// 頂点のインデックス。インデックスを使わないバッファでは`None`
+    indices: Option<Vec<u32>>,
 }
 
 impl VaoBuffer {
@@ -27,6 +36,15 @@ impl VaoBuffer {
         Self {
             buffer: Vec::<f32>::new(),
             vertex_num: 0,
+            indices: None,
+        }
+    }
+
+    /// 頂点のインデックスを持つ、空の`VaoBuffer`を作る
+    pub fn new_indexed() -> Self {
+        Self {
+            indices: Some(Vec::new()),
+            ..Self::new()
         }
     }
 
@@ -37,28 +55,98 @@ impl VaoBuffer {
         Self {
             buffer: Vec::<f32>::with_capacity(num_vertex_to_reserve * VERTEX_SIZE),
             vertex_num: 0,
+            indices: None,
         }
     }
 
+    /// 頂点のインデックスを持つかどうか
+    pub fn is_indexed(&self) -> bool {
+        self.indices.is_some()
+    }
+
     /// 頂点群を追加する
     ///
     /// * `v` - 頂点の情報がフラットに繰り返される`Vec`。したがって`v.len()`は`VERTEX_SIZE`の倍数になる。
     /// ※頂点情報の仕様については`VERTEX_SIZE`を参照
+    ///
+    /// インデックスを持つバッファでは、追加した頂点を順に並べたインデックスも追加する。
     pub fn append(&mut self, v: &mut Vec<f32>) {
         debug_assert_eq!(v.len() % VERTEX_SIZE, 0);
-        self.vertex_num += (v.len() / VERTEX_SIZE) as i32;
+        let first = self.vertex_num as u32;
+        let num = (v.len() / VERTEX_SIZE) as u32;
+        if let Some(ref mut indices) = self.indices {
+            indices.extend(first..first + num);
+        }
+        self.vertex_num += num as i32;
         self.buffer.append(v);
     }
 
+    /// 頂点群と、それらを結ぶインデックスを追加する
+    ///
+    /// `indices`は`v`の先頭の頂点を0とした番号。インデックスを持たないバッファでは、
+    /// `indices`の順に頂点を並べ直して追加する。
+    pub fn append_indexed(&mut self, v: &mut Vec<f32>, indices: &[u32]) {
+        debug_assert_eq!(v.len() % VERTEX_SIZE, 0);
+        debug_assert!(indices.iter().all(|&i| (i as usize) < v.len() / VERTEX_SIZE));
+        match self.indices {
+            Some(ref mut buffer_indices) => {
+                let first = self.vertex_num as u32;
+                buffer_indices.extend(indices.iter().map(|i| first + i));
+                self.vertex_num += (v.len() / VERTEX_SIZE) as i32;
+                self.buffer.append(v);
+            }
+            None => {
+                let mut flat: Vec<f32> = indices
+                    .iter()
+                    .flat_map(|&i| {
+                        let begin = i as usize * VERTEX_SIZE;
+                        v[begin..begin + VERTEX_SIZE].iter().copied()
+                    })
+                    .collect();
+                self.append(&mut flat);
+            }
+        }
+    }
+
+    /// 先頭から`first_vertex`番目の頂点から、頂点群を書き換える
+    ///
+    /// 頂点の数は変わらないので、`v`は今ある頂点の範囲に収まらなければならない。
+    pub fn overwrite(&mut self, first_vertex: usize, v: &[f32]) {
+        debug_assert_eq!(v.len() % VERTEX_SIZE, 0);
+        let begin = first_vertex * VERTEX_SIZE;
+        self.buffer[begin..begin + v.len()].copy_from_slice(v);
+    }
+
+    /// 頂点の数
+    pub fn vertex_num(&self) -> usize {
+        self.vertex_num as usize
+    }
+
+    /// `vertices`番目の頂点の情報。頂点ごとに`VERTEX_SIZE`個のf32が並ぶ
+    pub fn vertices(&self, vertices: Range<usize>) -> &[f32] {
+        &self.buffer[vertices.start * VERTEX_SIZE..vertices.end * VERTEX_SIZE]
+    }
+
+    /// 頂点のインデックス。インデックスを持たないバッファでは`None`
+    pub fn indices(&self) -> Option<&[u32]> {
+        self.indices.as_deref()
+    }
+
     /// すべての頂点を削除する
     pub fn clear(&mut self) {
         self.buffer.clear();
         self.vertex_num = self.buffer.len() as i32;
+        if let Some(ref mut indices) = self.indices {
+            indices.clear();
+        }
     }
 
     /// バッファの余分な容量をできるだけ縮める
     pub fn shrink(&mut self) {
         self.buffer.shrink_to_fit();
+        if let Some(ref mut indices) = self.indices {
+            indices.shrink_to_fit();
+        }
     }
 
     /// バッファの容量を予め確保する
@@ -69,23 +157,101 @@ impl VaoBuffer {
     }
 
     /// 先頭の`num_vertex_to_preserve`個の頂点以外の頂点を削除する
+    ///
+    /// インデックスは、削除した頂点を初めて指すところから後ろを削除する。
     pub fn clear_preserving_first(&mut self, num_vertex_to_preserve: usize) {
         self.buffer.truncate(num_vertex_to_preserve * VERTEX_SIZE);
+        self.vertex_num = (self.buffer.len() / VERTEX_SIZE) as i32;
+        if let Some(ref mut indices) = self.indices {
+            let preserved = indices
+                .iter()
+                .position(|&i| i as usize >= num_vertex_to_preserve)
+                .unwrap_or(indices.len());
+            indices.truncate(preserved);
+        }
     }
 
     /// 現在のバッファの内容をもとに`Vao`を作る
     pub fn build<'a>(&self, gl: &Gl, config: &'a VaoConfig<'a>) -> Vao<'a> {
+        self.build_with_usage(gl, config, gl::STATIC_DRAW)
+    }
+
+    /// 後から`update_vao`で書き換える`Vao`を作る
+    pub fn build_dynamic<'a>(&self, gl: &Gl, config: &'a VaoConfig<'a>) -> Vao<'a> {
+        self.build_with_usage(gl, config, gl::DYNAMIC_DRAW)
+    }
+
+    /// このバッファから作った`vao`の、`vertices`番目の頂点を今のバッファの内容で書き換える
+    ///
+    /// `vao`を作ったときから頂点の数が変わっていてはならない。
+    pub fn update_vao(&self, vao: &Vao, vertices: Range<usize>) {
+        let data = self.vertices(vertices.clone());
+        vao.update(
+            (vertices.start * VERTEX_SIZE * mem::size_of::<GLfloat>()) as _,
+            (data.len() * mem::size_of::<GLfloat>()) as _,
+            data.as_ptr() as _,
+        );
+    }
+
+    fn build_with_usage<'a>(&self, gl: &Gl, config: &'a VaoConfig<'a>, usage: GLenum) -> Vao<'a> {
         Vao::new(
             gl.clone(),
             (self.buffer.len() * mem::size_of::<GLfloat>()) as _,
             self.buffer.as_ptr() as _,
-            gl::STATIC_DRAW,
+            usage,
             3usize,
             vec![gl::FLOAT, gl::FLOAT, gl::FLOAT],
             vec![3, 3, 2],
             ((3 + 3 + 2) * mem::size_of::<GLfloat>()) as _,
             self.vertex_num,
+            self.indices(),
             config,
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(value: f32) -> Vec<f32> {
+        vec![value; VERTEX_SIZE]
+    }
+
+    #[test]
+    fn overwrite_and_truncate() {
+        let mut buffer = VaoBuffer::new();
+        buffer.append(&mut [vertex(1.0), vertex(2.0), vertex(3.0)].concat());
+        buffer.overwrite(1, &vertex(5.0));
+        assert_eq!(buffer.vertex_num(), 3);
+        assert_eq!(buffer.vertices(1..3), &[vertex(5.0), vertex(3.0)].concat()[..]);
+
+        buffer.clear_preserving_first(1);
+        assert_eq!(buffer.vertex_num(), 1);
+        buffer.append(&mut vertex(4.0));
+        assert_eq!(buffer.vertices(0..2), &[vertex(1.0), vertex(4.0)].concat()[..]);
+    }
+
+    #[test]
+    fn indices_follow_vertices() {
+        let mut buffer = VaoBuffer::new_indexed();
+        buffer.append(&mut [vertex(1.0), vertex(2.0)].concat());
+        buffer.append_indexed(&mut [vertex(3.0), vertex(4.0)].concat(), &[1, 0, 1]);
+        assert_eq!(buffer.vertex_num(), 4);
+        assert_eq!(buffer.indices(), Some(&[0, 1, 3, 2, 3][..]));
+
+        buffer.clear_preserving_first(2);
+        assert_eq!(buffer.indices(), Some(&[0, 1][..]));
+        buffer.clear();
+        assert_eq!(buffer.indices(), Some(&[][..]));
+
+        // インデックスを持たないバッファには、頂点を並べ直して追加する
+        let mut flat = VaoBuffer::new();
+        flat.append_indexed(&mut [vertex(3.0), vertex(4.0)].concat(), &[1, 0, 1]);
+        assert_eq!(flat.indices(), None);
+        assert_eq!(
+            flat.vertices(0..3),
+            &[vertex(4.0), vertex(3.0), vertex(4.0)].concat()[..]
+        );
+    }
+}
@@ -6,6 +6,8 @@ use crate::types::*;
 use super::vao_buffer::VaoBuffer;
 
 /// `VaoBuffer`上に立方体などの立体を追加する
+///
+/// インデックスを持つ`VaoBuffer`には、頂点を共有してインデックスで結んだ立体を追加する。
 pub trait VaoBuilder3DGeometry<const W: u32, const H: u32, const ATLAS_W: u32, const ATLAS_H: u32> {
     /// 各辺が軸に並行な直方体を追加する
     ///
@@ -38,6 +40,9 @@ pub trait VaoBuilder3DGeometry<const W: u32, const H: u32, const ATLAS_W: u32, c
     /// 正八面体を追加する
     ///
     /// `r`は中心から頂点までの距離
+    ///
+    /// インデックスを持つバッファでは6つの頂点をすべての面で共有するので、法線は中心から頂点への向きになり、
+    /// 面の境目の陰影が滑らかになる。
     fn add_octahedron(&mut self, center: &Point3, r: f32, uv: &TextureUV<W, H, ATLAS_W, ATLAS_H>);
 }
 
@@ -119,16 +124,33 @@ impl<const W: u32, const H: u32, const ATLAS_W: u32, const ATLAS_H: u32>
             p1.x, p1.y, p1.z, normal.x, normal.y, normal.z, uv.begin_u, uv.end_v,/* UVはtodo */
             p2.x, p2.y, p2.z, normal.x, normal.y, normal.z, uv.begin_u, uv.begin_v,
             p3.x, p3.y, p3.z, normal.x, normal.y, normal.z, uv.end_u, uv.begin_v,
-
-            p1.x, p1.y, p1.z, normal.x, normal.y, normal.z, uv.begin_u, uv.end_v,
-            p3.x, p3.y, p3.z, normal.x, normal.y, normal.z, uv.end_u, uv.begin_v,
             p4.x, p4.y, p4.z, normal.x, normal.y, normal.z, uv.end_u, uv.end_v,
         ];
 
-        self.append(&mut v);
+        self.append_indexed(&mut v, &[0, 1, 2, 0, 2, 3]);
     }
 
     fn add_octahedron(&mut self, center: &Point3, r: f32, uv: &TextureUV<W, H, ATLAS_W, ATLAS_H>) {
+        if self.is_indexed() {
+            // +x, +y, +z, -x, -y, -zの頂点
+            #[rustfmt::skip]
+            let mut v: Vec<f32> = vec![
+                center.x+r, center.y  , center.z  ,  1.0,  0.0,  0.0, uv.begin_u, uv.begin_v,
+                center.x  , center.y+r, center.z  ,  0.0,  1.0,  0.0, uv.begin_u, uv.end_v,
+                center.x  , center.y  , center.z+r,  0.0,  0.0,  1.0, uv.end_u, uv.end_v,
+                center.x-r, center.y  , center.z  , -1.0,  0.0,  0.0, uv.begin_u, uv.begin_v,
+                center.x  , center.y-r, center.z  ,  0.0, -1.0,  0.0, uv.begin_u, uv.end_v,
+                center.x  , center.y  , center.z-r,  0.0,  0.0, -1.0, uv.end_u, uv.end_v,
+            ];
+            #[rustfmt::skip]
+            let indices = [
+                0, 1, 2,  0, 5, 1,  0, 2, 4,  0, 4, 5,
+                3, 2, 1,  3, 1, 5,  3, 4, 2,  3, 5, 4,
+            ];
+            self.append_indexed(&mut v, &indices);
+            return;
+        }
+
         #[rustfmt::skip]
         let mut v: Vec<f32> = vec![
             center.x+r, center.y  , center.z  ,  1.0,  1.0,  1.0, uv.begin_u, uv.begin_v,
@@ -180,3 +202,78 @@ pub struct CuboidTextures<'a, const W: u32, const H: u32, const ATLAS_W: u32, co
     pub west: &'a TextureUV<W, H, ATLAS_W, ATLAS_H>,
     pub east: &'a TextureUV<W, H, ATLAS_W, ATLAS_H>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::texture_atlas::TextureAtlasPos;
+    use crate::vao::vao_buffer::VERTEX_SIZE;
+
+    type UV = TextureUV<64, 64, 256, 256>;
+
+    /// 三角形ごとの頂点の情報
+    fn triangles(buffer: &VaoBuffer) -> Vec<Vec<f32>> {
+        let vertices = buffer.vertices(0..buffer.vertex_num());
+        let vertex = |i: usize| &vertices[i * VERTEX_SIZE..(i + 1) * VERTEX_SIZE];
+        let order: Vec<usize> = match buffer.indices() {
+            Some(indices) => indices.iter().map(|&i| i as usize).collect(),
+            None => (0..buffer.vertex_num()).collect(),
+        };
+        order
+            .chunks(3)
+            .map(|triangle| triangle.iter().flat_map(|&i| vertex(i).to_vec()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn indexed_cuboid_shares_face_vertices() {
+        let uv = UV::of_atlas(&TextureAtlasPos::new(1, 2));
+        let textures = CuboidTextures {
+            top: &uv,
+            bottom: &uv,
+            south: &uv,
+            north: &uv,
+            west: &uv,
+            east: &uv,
+        };
+        let (begin, end) = (Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 2.0, 3.0));
+        let mut flat = VaoBuffer::new();
+        flat.add_cuboid(&begin, &end, &textures);
+        let mut indexed = VaoBuffer::new_indexed();
+        indexed.add_cuboid(&begin, &end, &textures);
+
+        assert_eq!(flat.vertex_num(), 36);
+        assert_eq!(indexed.vertex_num(), 24);
+        assert_eq!(indexed.indices().map(<[u32]>::len), Some(36));
+        // 描く三角形は変わらない
+        assert_eq!(triangles(&indexed), triangles(&flat));
+    }
+
+    #[test]
+    fn indexed_octahedron_shares_all_vertices() {
+        let uv = UV::of_atlas(&TextureAtlasPos::new(0, 1));
+        let center = Point3::new(1.0, 2.0, 3.0);
+        let mut flat = VaoBuffer::new();
+        flat.add_octahedron(&center, 0.5, &uv);
+        let mut indexed = VaoBuffer::new_indexed();
+        indexed.add_octahedron(&center, 0.5, &uv);
+        assert_eq!(indexed.vertex_num(), 6);
+
+        // 法線以外は同じ三角形を同じ向きで描く
+        let without_normal = |triangles: Vec<Vec<f32>>| -> Vec<Vec<f32>> {
+            triangles
+                .into_iter()
+                .map(|triangle| {
+                    triangle
+                        .chunks(VERTEX_SIZE)
+                        .flat_map(|v| [&v[0..3], &v[6..8]].concat())
+                        .collect()
+                })
+                .collect()
+        };
+        assert_eq!(
+            without_normal(triangles(&indexed)),
+            without_normal(triangles(&flat))
+        );
+    }
+}
@@ -0,0 +1,295 @@
+#!/usr/bin/env python3
+"""GNU Unifontの.hexから、同梱する日本語のフォント(UnifontJP.ttf)を作る
+
+    python3 unifont_subset.py unifont-13.0.06.hex UnifontJP.ttf
+
+収録する文字は、ASCII、Latin-1、JIS X 0208の全部(かな、第1・第2水準の漢字、記号)と半角カナ。
+ドットをそのまま輪郭にするので、16pxの整数倍の大きさで描くといちばんきれいに見える。
+"""
+
+import struct
+import sys
+
+# 1ドットの大きさ(フォントの単位)。1文字の幅が16ドットでも座標の差が1バイトに収まる
+DOT = 8
+UNITS_PER_EM = 16 * DOT
+ASCENT = 14 * DOT
+DESCENT = -2 * DOT
+
+FAMILY = "Unifont JP"
+COPYRIGHT = (
+    "Copyright (C) 1998-2020 Roman Czyborra, Paul Hardy, Qianqian Fang, Andrew Miller, "
+    "Johnnie Weaver, David Corbett, Nils Moskopp, Rebecca Bettencourt, et al."
+)
+LICENSE = "This Font Software is licensed under the SIL Open Font License, Version 1.1."
+LICENSE_URL = "http://scripts.sil.org/OFL"
+
+
+def wanted_chars():
+    chars = set(range(0x20, 0x7F)) | set(range(0xA0, 0x100)) | set(range(0xFF61, 0xFFA0))
+    for first in range(0xA1, 0xFF):
+        for second in range(0xA1, 0xFF):
+            try:
+                chars.add(ord(bytes([first, second]).decode("euc_jp")))
+            except UnicodeDecodeError:
+                pass
+    return chars
+
+
+def read_hex(path, chars):
+    glyphs = {}
+    with open(path) as f:
+        for line in f:
+            code, bits = line.strip().split(":")
+            code = int(code, 16)
+            if code in chars:
+                glyphs[code] = bits
+    return glyphs
+
+
+def dots(bits):
+    """ドットの(列, 行)と、ドット単位の幅。行は上から数える"""
+    width = len(bits) * 4 // 16
+    row_digits = width // 4
+    filled = set()
+    for row in range(16):
+        value = int(bits[row * row_digits : (row + 1) * row_digits], 16)
+        for column in range(width):
+            if value & (1 << (width - 1 - column)):
+                filled.add((column, row))
+    return filled, width
+
+
+def contours(filled):
+    """ドットの集まりの輪郭。外側は時計回り、穴は反時計回り(y軸は上向き)"""
+    edges = {}
+    for column, row in filled:
+        x, y = column, 13 - row
+        square = [(x, y), (x, y + 1), (x + 1, y + 1), (x + 1, y)]
+        for i in range(4):
+            start, end = square[i], square[(i + 1) % 4]
+            if (end, start) in edges:
+                del edges[(end, start)]
+            else:
+                edges[(start, end)] = True
+    outgoing = {}
+    for start, end in edges:
+        outgoing.setdefault(start, []).append(end)
+    result = []
+    while outgoing:
+        first = min(outgoing)
+        points = [first]
+        previous, current = None, first
+        while True:
+            ends = outgoing[current]
+            if len(ends) == 1 or previous is None:
+                end = ends[0]
+            else:
+                # 斜めに接するドットは、右に曲がって別の輪郭に分ける
+                direction = (current[0] - previous[0], current[1] - previous[1])
+                right = (direction[1], -direction[0])
+                end = next(
+                    (e for e in ends if (e[0] - current[0], e[1] - current[1]) == right), ends[0]
+                )
+            ends.remove(end)
+            if not ends:
+                del outgoing[current]
+            previous, current = current, end
+            if current == first:
+                break
+            points.append(current)
+        result.append(simplify(points))
+    return result
+
+
+def simplify(points):
+    """一直線に並ぶ点を除く"""
+    kept = []
+    for i, point in enumerate(points):
+        before, after = points[i - 1], points[(i + 1) % len(points)]
+        if (point[0] - before[0]) * (after[1] - point[1]) != (point[1] - before[1]) * (after[0] - point[0]):
+            kept.append(point)
+    return kept
+
+
+def encode_glyph(outlines):
+    if not outlines:
+        return b"", (0, 0, 0, 0)
+    points = [(x * DOT, y * DOT) for outline in outlines for x, y in outline]
+    xs, ys = [p[0] for p in points], [p[1] for p in points]
+    bbox = (min(xs), min(ys), max(xs), max(ys))
+    data = struct.pack(">hhhhh", len(outlines), *bbox)
+    end = -1
+    for outline in outlines:
+        end += len(outline)
+        data += struct.pack(">H", end)
+    data += struct.pack(">H", 0)
+    flags, x_bytes, y_bytes = [], b"", b""
+    last = (0, 0)
+    for point in points:
+        flag = 0x01
+        dx, dy = point[0] - last[0], point[1] - last[1]
+        if dx == 0:
+            flag |= 0x10
+        else:
+            flag |= 0x02 | (0x10 if dx > 0 else 0)
+            x_bytes += bytes([abs(dx)])
+        if dy == 0:
+            flag |= 0x20
+        else:
+            flag |= 0x04 | (0x20 if dy > 0 else 0)
+            y_bytes += bytes([abs(dy)])
+        flags.append(flag)
+        last = point
+    flag_bytes = b""
+    i = 0
+    while i < len(flags):
+        repeat = 0
+        while i + repeat + 1 < len(flags) and flags[i + repeat + 1] == flags[i] and repeat < 255:
+            repeat += 1
+        if repeat:
+            flag_bytes += bytes([flags[i] | 0x08, repeat])
+        else:
+            flag_bytes += bytes([flags[i]])
+        i += repeat + 1
+    data += flag_bytes + x_bytes + y_bytes
+    if len(data) % 2:
+        data += b"\0"
+    return data, bbox
+
+
+def notdef():
+    """どの文字にもない文字に使う、中抜きの四角"""
+    outer = [(1, -1), (1, 13), (7, 13), (7, -1)]
+    inner = [(2, 0), (6, 0), (6, 12), (2, 12)]
+    return [outer, inner], 8
+
+
+def cmap_table(codes):
+    """format 4。文字は全部BMPにある"""
+    segments = []
+    for glyph_id, code in enumerate(codes, 1):
+        if segments and segments[-1][1] == code - 1 and segments[-1][2] + code - segments[-1][0] == glyph_id:
+            segments[-1][1] = code
+        else:
+            segments.append([code, code, glyph_id])
+    segments.append([0xFFFF, 0xFFFF, 1])
+    count = len(segments)
+    search_range = 2 * (1 << (count.bit_length() - 1))
+    entry_selector = count.bit_length() - 1
+    range_shift = 2 * count - search_range
+    ends = b"".join(struct.pack(">H", s[1]) for s in segments)
+    starts = b"".join(struct.pack(">H", s[0]) for s in segments)
+    deltas = b"".join(struct.pack(">H", (s[2] - s[0]) % 0x10000) for s in segments[:-1]) + struct.pack(">H", 1)
+    offsets = b"\0\0" * count
+    body = struct.pack(">HHHH", count * 2, search_range, entry_selector, range_shift)
+    body += ends + b"\0\0" + starts + deltas + offsets
+    subtable = struct.pack(">HHH", 4, 6 + len(body), 0) + body
+    return struct.pack(">HHHHI", 0, 1, 3, 1, 12) + subtable
+
+
+def name_table():
+    records = [
+        (0, COPYRIGHT),
+        (1, FAMILY),
+        (2, "Regular"),
+        (3, FAMILY + " Regular"),
+        (4, FAMILY),
+        (5, "Version 13.0.06"),
+        (6, FAMILY.replace(" ", "") + "-Regular"),
+        (13, LICENSE),
+        (14, LICENSE_URL),
+    ]
+    strings, entries = b"", b""
+    for name_id, text in records:
+        encoded = text.encode("utf-16-be")
+        entries += struct.pack(">HHHHHH", 3, 1, 0x409, name_id, len(encoded), len(strings))
+        strings += encoded
+    return struct.pack(">HHH", 0, len(records), 6 + 12 * len(records)) + entries + strings
+
+
+def checksum(data):
+    data += b"\0" * (-len(data) % 4)
+    return sum(struct.unpack(">%dI" % (len(data) // 4), data)) & 0xFFFFFFFF
+
+
+def build(glyphs):
+    codes = sorted(glyphs)
+    outlines = [notdef()]
+    for code in codes:
+        filled, width = dots(glyphs[code])
+        outlines.append((contours(filled), width))
+    glyf, loca, hmtx = b"", [], b""
+    bbox = [0, 0, 0, 0]
+    max_points = max_contours = 0
+    for shapes, width in outlines:
+        data, (x_min, y_min, x_max, y_max) = encode_glyph(shapes)
+        loca.append(len(glyf))
+        glyf += data
+        hmtx += struct.pack(">Hh", width * DOT, x_min)
+        if data:
+            bbox = [min(bbox[0], x_min), min(bbox[1], y_min), max(bbox[2], x_max), max(bbox[3], y_max)]
+        max_points = max(max_points, sum(len(s) for s in shapes))
+        max_contours = max(max_contours, len(shapes))
+    loca.append(len(glyf))
+    count = len(outlines)
+    advance_max = max(width for _, width in outlines) * DOT
+
+    tables = {
+        b"cmap": cmap_table(codes),
+        b"glyf": glyf,
+        b"head": struct.pack(
+            ">HHIIIHHQQhhhhHHhhh",
+            1, 0, 0x000D0006, 0, 0x5F0F3CF5, 0x000B, UNITS_PER_EM, 0, 0, *bbox, 0, 8, 2, 1, 0,
+        ),
+        b"hhea": struct.pack(
+            ">HHhhhHhhhhhhhhhhhH",
+            1, 0, ASCENT, DESCENT, 0, advance_max, 0, 0, advance_max, 1, 0, 0, 0, 0, 0, 0, 0, count,
+        ),
+        b"hmtx": hmtx,
+        b"loca": b"".join(struct.pack(">I", offset) for offset in loca),
+        b"maxp": struct.pack(">IHHHHHHHHHHHHHH", 0x00010000, count, max_points, max_contours,
+                             0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0),
+        b"name": name_table(),
+        b"OS/2": struct.pack(
+            ">HhHHHhhhhhhhhhhh10sIIII4sHHHhhhHH",
+            1, 8 * DOT, 400, 5, 0,
+            8 * DOT, 8 * DOT, 0, 4 * DOT, 8 * DOT, 8 * DOT, 0, 6 * DOT, DOT, 5 * DOT, 0,
+            b"\0" * 10, 0, 0, 0, 0, b"UFNT", 0x40,
+            min(codes), min(max(codes), 0xFFFF), ASCENT, DESCENT, 0, ASCENT, -DESCENT,
+        ) + struct.pack(">II", 1 | (1 << 17), 0),
+        b"post": struct.pack(">IihhI4I", 0x00030000, 0, -DOT, DOT, 0, 0, 0, 0, 0),
+    }
+
+    tags = sorted(tables)
+    entry_selector = len(tags).bit_length() - 1
+    search_range = (1 << entry_selector) * 16
+    header = struct.pack(">IHHHH", 0x00010000, len(tags), search_range, entry_selector,
+                         len(tags) * 16 - search_range)
+    offset = 12 + 16 * len(tags)
+    directory, body = b"", b""
+    head_offset = 0
+    for tag in tags:
+        data = tables[tag]
+        if tag == b"head":
+            head_offset = offset
+        directory += struct.pack(">4sIII", tag, checksum(data), offset, len(data))
+        padded = data + b"\0" * (-len(data) % 4)
+        body += padded
+        offset += len(padded)
+    font = bytearray(header + directory + body)
+    adjustment = (0xB1B0AFBA - checksum(bytes(font))) & 0xFFFFFFFF
+    font[head_offset + 8 : head_offset + 12] = struct.pack(">I", adjustment)
+    return bytes(font)
+
+
+def main():
+    source, output = sys.argv[1:3]
+    glyphs = read_hex(source, wanted_chars())
+    with open(output, "wb") as f:
+        f.write(build(glyphs))
+    print("%d glyphs" % (len(glyphs) + 1))
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,64 @@
+#version 330 core
+
+struct Material {
+    vec3 specular;
+    float shininess;
+};
+
+struct Light {
+    vec3 direction;
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+};
+
+in float Alpha;
+in vec3 FragPosition;
+in vec3 Normal;
+in vec2 TexCoords;
+
+uniform sampler2D uScreenTexture;
+uniform vec3 uViewPosition;
+uniform Material uMaterial;
+uniform Light uLight;
+/* アトラスの1つのテクスチャの大きさ(UV座標) */
+uniform float uTileWidth;
+uniform float uTileHeight;
+
+/* vao_builder.rsのTILE_STRIDEとTILE_OFFSET */
+const float TILE_STRIDE = 16.0;
+const float TILE_OFFSET = 4.0;
+
+/* TexCoordsの「アトラスの何番目のテクスチャか × TILE_STRIDE + TILE_OFFSET + 繰り返しの中の位置」から、アトラスのUV座標を求める */
+vec2 atlasCoords()
+{
+    vec2 tileSize = vec2(uTileWidth, uTileHeight);
+    vec2 tile = floor(TexCoords / TILE_STRIDE);
+    /* 隣のテクスチャの色が混ざらないよう、テクセルの半分だけ内側に収める */
+    vec2 halfTexel = 0.5 / (vec2(textureSize(uScreenTexture, 0)) * tileSize);
+    vec2 local = clamp(fract(TexCoords - tile * TILE_STRIDE - TILE_OFFSET), halfTexel, 1.0 - halfTexel);
+    return (tile + local) * tileSize;
+}
+
+void main()
+{
+    vec3 texRGB = texture(uScreenTexture, atlasCoords()).rgb;
+
+    /* ambient*/
+    vec3 ambient = uLight.ambient * texRGB;
+
+    /* diffuse */
+    float diffuseScala = dot(normalize(Normal), normalize(-uLight.direction));
+    vec3 diffuse = uLight.diffuse * max(diffuseScala, 0.0) * texRGB;
+
+    /* specular */
+    vec3 viewDirection = normalize(uViewPosition - FragPosition);
+    vec3 reflectDirection = reflect(normalize(uLight.direction), normalize(Normal));
+    float specularScala = dot(viewDirection, reflectDirection);
+    vec3 specular = uLight.specular * pow(max(specularScala, 0.0), uMaterial.shininess) * uMaterial.specular;
+
+    vec3 result = ambient + diffuse + specular;
+
+    // gl_FragColor = vec4(FragPosition, 1.0);
+    gl_FragColor = vec4(result, Alpha);
+}
@@ -0,0 +1,16 @@
+#version 330 core
+
+in float Alpha;
+in vec3 Color;
+in vec2 TexCoords;
+
+// 文字の濃さだけが赤のチャンネルに入っている
+uniform sampler2D uScreenTexture;
+
+out vec4 FragColor;
+
+void main()
+{
+    float coverage = texture(uScreenTexture, TexCoords).r;
+    FragColor = vec4(Color, coverage * Alpha);
+}
\ No newline at end of file
@@ -0,0 +1,21 @@
+#version 330 core
+
+layout (location = 0) in vec3 iPosition;
+layout (location = 1) in vec3 iColor;  // 法線の代わりに文字の色が入っている
+layout (location = 2) in vec2 iTexCoords;
+
+uniform float uWidth;
+uniform float uHeight;
+uniform float uAlpha;
+
+out float Alpha;
+out vec3 Color;
+out vec2 TexCoords;
+
+void main()
+{
+    Alpha = uAlpha;
+    Color = iColor;
+    TexCoords = iTexCoords;
+    gl_Position = vec4(iPosition.x * 2 / uWidth - 1.0, -iPosition.y * 2 / uHeight + 1.0, iPosition.z, 1.0);
+}
\ No newline at end of file
@@ -3,6 +3,10 @@
 layout (location = 0) in vec3 iPosition;
 layout (location = 1) in vec3 iNormal;
 layout (location = 2) in vec2 iTexCoords;
+/* インスタンスごとの位置、大きさ、テクスチャのずれ。インスタンスを使わないVaoでは(0, 0, 0), (1, 1, 1), (0, 0) */
+layout (location = 3) in vec3 iInstanceOffset;
+layout (location = 4) in vec3 iInstanceScale;
+layout (location = 5) in vec2 iInstanceTexOffset;
 
 uniform mat4 uModel;
 uniform mat4 uView;
@@ -17,8 +21,9 @@ out vec2 TexCoords;
 void main()
 {
     Alpha = uAlpha;
-    FragPosition = vec3(uModel * vec4(iPosition, 1.0));
-    Normal = mat3(transpose(inverse(uModel))) * iNormal;
-    TexCoords = iTexCoords;
+    vec3 position = iPosition * iInstanceScale + iInstanceOffset;
+    FragPosition = vec3(uModel * vec4(position, 1.0));
+    Normal = mat3(transpose(inverse(uModel))) * (iNormal / iInstanceScale);
+    TexCoords = iTexCoords + iInstanceTexOffset;
     gl_Position = uProjection * uView * vec4(FragPosition, 1.0);
 }
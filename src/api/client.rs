@@ -1,27 +1,62 @@
 use std::{
-    collections::VecDeque,
     error::Error,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use rust_socketio::{Payload, Socket, SocketBuilder};
 use uuid::Uuid;
 
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
 
 use crate::{
-    api::json::{
-        DirectionJson, GameFinishReasonJson, OnUpdateUserJson, RequestAfterGameJson, RoomStateEventJson,
-        RoomStateJson, SetupUidJson, SquareJson, UpdateFieldJson,
+    api::{
+        decode::{
+            decode_available_rooms, decode_or_protocol_error, decode_room_state, decode_update_field,
+            decode_update_user, DecodeError,
+        },
+        event_queue::SharedEventQueue,
+        json::{DirectionJson, JoinRoomJson, RequestAfterGameJson, SetupUidJson},
     },
     player::{Player, Tagger},
+    replay::{Recorder, SharedRecorder},
     types::*,
-    GameFinishReason, FIELD_SIZE,
+    GameFinishReason,
 };
 
+/// 再接続を試みる最大の回数
+const RECONNECT_MAX_ATTEMPTS: u32 = 6;
+/// 1回目の再接続を試みるまでの待ち時間。2回目以降は倍々に伸ばす
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+/// 再接続の待ち時間の上限
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(8);
+
 pub struct ApiClient {
     socket: Option<Socket>,
     url: String,
+    /// 再接続の際にハンドラを登録し直すために`connect`で受け取ったキューを覚えておく
+    queue: Option<SharedEventQueue>,
+    /// socket.ioの`close`イベントを受け取ったら立つフラグ。ソケットのスレッドから書き込まれる
+    disconnected: Arc<AtomicBool>,
+    /// 再接続後に`setup-uid`をやり直すためのユーザーID
+    uid: Option<Uuid>,
+    /// 再接続後に入り直すルーム。ルームに入っていなければ`None`
+    joined_room: Option<RoomChoice>,
+    /// 再接続を試みている間だけ`Some`
+    reconnection: Option<Reconnection>,
+    /// 受け取ったイベントの記録。ソケットのハンドラと共有し、記録を終えるときに取り出す
+    recorder: SharedRecorder,
+}
+
+/// 再接続の進み具合
+struct Reconnection {
+    /// 何回目の試行か(1始まり)
+    attempt: u32,
+    /// 次に接続を試みる時刻
+    next_try: Instant,
 }
 
 impl ApiClient {
@@ -29,144 +64,119 @@ impl ApiClient {
         ApiClient {
             socket: None,
             url: url.to_string(),
+            queue: None,
+            disconnected: Arc::new(AtomicBool::new(false)),
+            uid: None,
+            joined_room: None,
+            reconnection: None,
+            recorder: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// 受け取ったイベントを記録するようにする。それまでの記録があれば終える
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.finish_recording();
+        *self.recorder.lock().unwrap_or_log() = recorder;
+    }
+
+    /// 記録を取り出してファイルを閉じる
+    fn finish_recording(&mut self) {
+        let recorder = self.recorder.lock().unwrap_or_log().take();
+        if let Some(recorder) = recorder {
+            match recorder.finish() {
+                Ok(_) => info!("replay saved"),
+                Err(error) => warn!("failed to finish replay: {}", error),
+            }
         }
     }
 
     #[tracing::instrument(name = "connect API", skip_all, fields(url = %self.url))]
-    pub fn connect(
-        &mut self,
-        queue: &Arc<Mutex<VecDeque<ApiEvent>>>,
-    ) -> Result<(), rust_socketio::error::Error> {
+    pub fn connect(&mut self, queue: &SharedEventQueue) -> Result<(), rust_socketio::error::Error> {
+        if let Some(mut socket) = self.socket.take() {
+            warn!("already connected. disconnecting old socket");
+            socket.disconnect().ok();
+        }
+        self.queue = Some(Arc::clone(queue));
+        self.reconnection = None;
+        self.joined_room = None;
+
+        info!("connecting server");
+        self.socket = Some(self.build_socket(queue)?);
+        Ok(())
+    }
+
+    fn build_socket(&self, queue: &SharedEventQueue) -> Result<Socket, rust_socketio::error::Error> {
         let queue_update_user = Arc::clone(queue);
         let queue_room_state = Arc::clone(queue);
         let queue_update_field = Arc::clone(queue);
+        let queue_available_rooms = Arc::clone(queue);
+        let recorder_update_user = Arc::clone(&self.recorder);
+        let recorder_room_state = Arc::clone(&self.recorder);
+        let recorder_update_field = Arc::clone(&self.recorder);
+        let disconnected = Arc::clone(&self.disconnected);
+        self.disconnected.store(false, Ordering::SeqCst);
 
-        info!("connecting server");
-        self.socket = Some(
-            SocketBuilder::new(&self.url)
-                .on("error", |err, _| error!("socket.io {:#?}", err))
-                .on(event::UPDATE_USER, move |payload, _| {
-                    debug!("{} event", event::UPDATE_USER);
-                    let json: OnUpdateUserJson =
-                        serde_json::from_slice(&payload.to_utf8_bytes()).unwrap_or_log();
-                    let event = ApiEvent::UpdateUser {
-                        uid: json.uid,
-                        name: json.name,
-                    };
-                    queue_update_user.lock().unwrap_or_log().push_back(event);
-                })
-                .on(event::ROOM_STATE, move |payload, _socket| {
-                    debug!("{} event", event::ROOM_STATE);
-                    let json: RoomStateEventJson =
-                        serde_json::from_slice(&payload.to_utf8_bytes()).unwrap_or_log();
-                    let event = match json.state {
-                        RoomStateJson::Opening => ApiEvent::RoomStateOpening {
-                            room_id: json.roomId.unwrap_or_log(),
-                            room_name: json.roomname.unwrap_or_log(),
-                        },
-                        RoomStateJson::Fulfilled => ApiEvent::RoomStateFulfilled {
-                            room_id: json.roomId.unwrap_or_log(),
-                            room_name: json.roomname.unwrap_or_log(),
-                            should_start: json
-                                .assignedUsers
-                                .iter()
-                                .all(|user| user.requestingToStartGame),
-                        },
-                        RoomStateJson::Empty => ApiEvent::RoomStateEmpty {
-                            room_id: json.roomId.unwrap_or_log(),
-                            room_name: json.roomname.unwrap_or_log(),
-                        },
-                        RoomStateJson::notJoining => ApiEvent::RoomStateNotJoined,
-                    };
-                    queue_room_state.lock().unwrap_or_log().push_back(event);
-                })
-                .on(event::UPDATE_FIELD, move |payload, _| {
-                    debug!("{} event", event::UPDATE_FIELD);
-                    let json: UpdateFieldJson =
-                        serde_json::from_slice(&payload.to_utf8_bytes()).unwrap_or_log();
-                    match json.state {
-                        crate::api::json::GameStatusJson::BeforeStart
-                        | crate::api::json::GameStatusJson::PendingStart => return,
-                        crate::api::json::GameStatusJson::InGame
-                        | crate::api::json::GameStatusJson::Finish => {
-                            let mut players = Vec::new();
-                            for player in &json.player_list {
-                                let player = Player::new(
-                                    Point2i::new(
-                                        FIELD_SIZE as i32 - 1 - player.position.row,
-                                        player.position.column,
-                                    ),
-                                    player.uid,
-                                    player.name.clone(),
-                                );
-                                players.push(player);
-                            }
-
-                            let tagger = Tagger::new(Point2i::new(
-                                FIELD_SIZE as i32 - 1 - json.tagger.position.row,
-                                json.tagger.position.column,
-                            ));
-
-                            debug_assert_eq!(json.battle_field.length, FIELD_SIZE as i32);
-                            let field = make_height_matrix(json.battle_field.squares);
-
-                            let field_update_event = ApiEvent::UpdateField {
-                                players,
-                                tagger,
-                                field,
-                            };
-                            queue_update_field
-                                .lock()
-                                .unwrap_or_log()
-                                .push_back(field_update_event);
-
-                            if let crate::api::json::GameStatusJson::Finish = json.state {
-                                let winner_uid = json.winner.map(|winner| winner.uid);
-                                let game_finished_event = ApiEvent::GameFinished {
-                                    reason: match json.finish_reason {
-                                        Some(GameFinishReasonJson::Fall) => {
-                                            GameFinishReason::Fall { winner_uid }
-                                        }
-                                        Some(GameFinishReasonJson::Collision) => {
-                                            GameFinishReason::Collision { winner_uid }
-                                        }
-                                        Some(GameFinishReasonJson::Timeup) => GameFinishReason::Timeup,
-                                        None => {
-                                            tracing::warn!(
-                                                "stateがFinishなのにfinishReasonがありません"
-                                            );
-                                            GameFinishReason::Abnromal
-                                        }
-                                    },
-                                };
-                                queue_update_field
-                                    .lock()
-                                    .unwrap_or_log()
-                                    .push_back(game_finished_event);
-                            }
-                        }
-                        crate::api::json::GameStatusJson::AbnormalEnd => queue_update_field
-                            .lock()
-                            .unwrap_or_log()
-                            .push_back(ApiEvent::GameFinished {
-                                reason: GameFinishReason::Abnromal,
-                            }),
-                    };
-                })
-                .connect()?,
-        );
-        Ok(())
+        SocketBuilder::new(&self.url)
+            .on("error", |err, _| error!("socket.io {:#?}", err))
+            .on("close", move |_, _| {
+                warn!("socket.io connection closed");
+                disconnected.store(true, Ordering::SeqCst);
+            })
+            .on(event::UPDATE_USER, move |payload, _| {
+                debug!("{} event", event::UPDATE_USER);
+                let raw = payload.to_utf8_bytes();
+                record(&recorder_update_user, event::UPDATE_USER, &raw);
+                let events = decode_or_protocol_error(event::UPDATE_USER, &raw, decode_update_user);
+                queue_update_user.lock().unwrap_or_log().extend(events);
+            })
+            .on(event::ROOM_STATE, move |payload, _socket| {
+                debug!("{} event", event::ROOM_STATE);
+                let raw = payload.to_utf8_bytes();
+                record(&recorder_room_state, event::ROOM_STATE, &raw);
+                let events = decode_or_protocol_error(event::ROOM_STATE, &raw, decode_room_state);
+                queue_room_state.lock().unwrap_or_log().extend(events);
+            })
+            .on(event::UPDATE_FIELD, move |payload, _| {
+                debug!("{} event", event::UPDATE_FIELD);
+                let raw = payload.to_utf8_bytes();
+                record(&recorder_update_field, event::UPDATE_FIELD, &raw);
+                let events = decode_or_protocol_error(event::UPDATE_FIELD, &raw, decode_update_field);
+                queue_update_field.lock().unwrap_or_log().extend(events);
+            })
+            .on(event::FIND_AVAILABLE_ROOM, move |payload, _| {
+                debug!("{} event", event::FIND_AVAILABLE_ROOM);
+                let events = decode_or_protocol_error(
+                    event::FIND_AVAILABLE_ROOM,
+                    &payload.to_utf8_bytes(),
+                    decode_available_rooms,
+                );
+                queue_available_rooms.lock().unwrap_or_log().extend(events);
+            })
+            .connect()
     }
 
     fn get_socket(&mut self) -> Result<&mut Socket, &str> {
         self.socket.as_mut().ok_or("no socket")
     }
 
+    /// イベントを送信する。送信に失敗した場合は切断されたものとみなす
+    fn emit<D>(&mut self, event: &str, data: D) -> Result<(), Box<dyn Error>>
+    where
+        D: Into<Payload>,
+    {
+        let socket = self.get_socket()?;
+        if let Err(error) = socket.emit(event, data) {
+            self.disconnected.store(true, Ordering::SeqCst);
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn setup_uid(&mut self, uid: Uuid) -> Result<(), Box<dyn Error>> {
         info!("emitting");
-        let socket = self.get_socket()?;
-        socket.emit(
+        self.uid = Some(uid);
+        self.emit(
             event::SETUP_UID,
             serde_json::to_string(&SetupUidJson { user_id: uid })?,
         )?;
@@ -174,11 +184,34 @@ impl ApiClient {
         Ok(())
     }
 
+    /// 入れるルームの一覧を問い合わせる。結果は`ApiEvent::AvailableRooms`で届く
     #[tracing::instrument(skip(self))]
-    pub fn join_room(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn find_available_rooms(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("emitting");
+        self.emit(event::FIND_AVAILABLE_ROOM, serde_json::json!({}))?;
+        debug!("done");
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    pub fn join_room(&mut self, choice: &RoomChoice) -> Result<(), Box<dyn Error>> {
         info!("emitting");
-        let socket = self.get_socket()?;
-        socket.emit(event::JOIN_ROOM, serde_json::json!("{}"))?;
+        let json = match choice {
+            RoomChoice::Any => JoinRoomJson {
+                room_id: None,
+                room_name: None,
+            },
+            RoomChoice::Existing { room_id } => JoinRoomJson {
+                room_id: Some(room_id.clone()),
+                room_name: None,
+            },
+            RoomChoice::New { room_name } => JoinRoomJson {
+                room_id: None,
+                room_name: Some(room_name.clone()),
+            },
+        };
+        self.emit(event::JOIN_ROOM, serde_json::to_string(&json)?)?;
+        self.joined_room = Some(choice.clone());
         info!("done");
         Ok(())
     }
@@ -186,8 +219,7 @@ impl ApiClient {
     #[tracing::instrument(skip(self))]
     pub fn try_move(&mut self, direction: &DirectionJson) -> Result<(), Box<dyn Error>> {
         debug!("emitting");
-        let socket = self.get_socket()?;
-        socket.emit(event::TRY_MOVE, serde_json::to_string(&direction)?)?;
+        self.emit(event::TRY_MOVE, serde_json::to_string(&direction)?)?;
         debug!("done");
         Ok(())
     }
@@ -195,8 +227,7 @@ impl ApiClient {
     #[tracing::instrument(skip(self))]
     pub fn restart(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("emitting");
-        let socket = self.get_socket()?;
-        socket.emit(
+        self.emit(
             event::REQUEST_AFTER_GAME,
             serde_json::to_string(&RequestAfterGameJson::restart)?,
         )?;
@@ -204,14 +235,177 @@ impl ApiClient {
         Ok(())
     }
 
+    /// 結果画面からルームを出る。サーバーからは`ApiEvent::RoomStateNotJoined`が返ってくる
+    #[tracing::instrument(skip(self))]
+    pub fn leave(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("emitting");
+        self.emit(
+            event::REQUEST_AFTER_GAME,
+            serde_json::to_string(&RequestAfterGameJson::leave)?,
+        )?;
+        self.joined_room = None;
+        debug!("done");
+        Ok(())
+    }
+
+    /// 接続していて、再接続中でもなければ`true`
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some() && self.reconnection.is_none() && !self.disconnected.load(Ordering::SeqCst)
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("disconnecting");
+        // 自分から切断したときは再接続しない
+        self.reconnection = None;
+        self.queue = None;
+        // 記録も終える
+        self.finish_recording();
         let socket = self.get_socket()?;
         socket.disconnect()?;
+        self.socket = None;
         debug!("disconnected");
         Ok(())
     }
+
+    /// 接続が切れていないか確認し、切れていたらバックオフを挟みながら再接続を試みる
+    ///
+    /// 毎フレーム呼ぶ。再接続の状況は`ApiEvent::Reconnecting`、`ApiEvent::Reconnected`、
+    /// `ApiEvent::GaveUp`としてキューに積まれる。
+    pub fn poll_connection(&mut self) {
+        let queue = match self.queue {
+            Some(ref queue) => Arc::clone(queue),
+            None => return,
+        };
+        let now = Instant::now();
+
+        if self.reconnection.is_none() {
+            if self.socket.is_none() || !self.disconnected.load(Ordering::SeqCst) {
+                return;
+            }
+            warn!("connection lost. start reconnecting");
+            self.socket = None;
+            self.start_reconnect_attempt(1, now, &queue);
+            return;
+        }
+
+        let (attempt, next_try) = {
+            let reconnection = self.reconnection.as_ref().unwrap_or_log();
+            (reconnection.attempt, reconnection.next_try)
+        };
+        if now < next_try {
+            return;
+        }
+
+        info!("reconnecting attempt {}/{}", attempt, RECONNECT_MAX_ATTEMPTS);
+        match self.build_socket(&queue) {
+            Ok(socket) => {
+                self.socket = Some(socket);
+                self.reconnection = None;
+                if let Err(error) = self.resume_session() {
+                    warn!("failed to resume session: {}", error);
+                    // 接続できてもセッションを戻せなければ失敗した試行として数える
+                    if let Some(mut socket) = self.socket.take() {
+                        socket.disconnect().ok();
+                    }
+                    self.retry_or_give_up(attempt, now, &queue);
+                    return;
+                }
+                info!("reconnected");
+                let rejoined_room = self.joined_room.is_some();
+                queue
+                    .lock()
+                    .unwrap_or_log()
+                    .push(ApiEvent::Reconnected { rejoined_room });
+            }
+            Err(error) => {
+                warn!("reconnect failed: {}", error);
+                self.retry_or_give_up(attempt, now, &queue);
+            }
+        }
+    }
+
+    /// `attempt`回目の再接続に失敗したので、次の試行を予約する。最大回数に達していればあきらめる
+    fn retry_or_give_up(&mut self, attempt: u32, now: Instant, queue: &SharedEventQueue) {
+        if attempt >= RECONNECT_MAX_ATTEMPTS {
+            error!("gave up reconnecting");
+            self.reconnection = None;
+            self.joined_room = None;
+            queue.lock().unwrap_or_log().push(ApiEvent::GaveUp);
+        } else {
+            self.start_reconnect_attempt(attempt + 1, now, queue);
+        }
+    }
+
+    fn start_reconnect_attempt(&mut self, attempt: u32, now: Instant, queue: &SharedEventQueue) {
+        self.reconnection = Some(Reconnection {
+            attempt,
+            next_try: now + reconnect_delay(attempt),
+        });
+        queue.lock().unwrap_or_log().push(ApiEvent::Reconnecting {
+            attempt,
+            max_attempts: RECONNECT_MAX_ATTEMPTS,
+        });
+    }
+
+    /// 再接続した後、切断前と同じユーザーとしてルームに入り直す
+    fn resume_session(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(uid) = self.uid {
+            self.setup_uid(uid)?;
+        }
+        if let Some(choice) = self.joined_room.clone() {
+            self.join_room(&choice)?;
+        }
+        Ok(())
+    }
+}
+
+/// `join-room`でどのルームに入るか
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoomChoice {
+    /// サーバーに任せる
+    Any,
+    /// `find-available-room`で見つけたルームに入る
+    Existing { room_id: String },
+    /// この名前のルームに入る。なければ作る
+    New { room_name: String },
+}
+
+/// `find-available-room`で見つかったルーム
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomSummary {
+    pub room_id: String,
+    pub room_name: String,
+    pub member_count: usize,
+    pub max_member: usize,
+}
+
+impl RoomSummary {
+    pub fn is_full(&self) -> bool {
+        self.member_count >= self.max_member
+    }
+}
+
+impl Drop for ApiClient {
+    /// 切断せずにウィンドウを閉じたときも記録を書き切る
+    fn drop(&mut self) {
+        self.finish_recording();
+    }
+}
+
+/// 記録中なら受け取ったペイロードを記録する
+fn record(recorder: &SharedRecorder, event: &str, raw: &[u8]) {
+    if let Some(recorder) = recorder.lock().unwrap_or_log().as_mut() {
+        if let Err(error) = recorder.record(event, raw) {
+            warn!("failed to record {} event: {}", event, error);
+        }
+    }
+}
+
+/// `attempt`回目の再接続を試みるまでの待ち時間
+fn reconnect_delay(attempt: u32) -> Duration {
+    let factor = 1u32 << attempt.saturating_sub(1).min(16);
+    (RECONNECT_INITIAL_DELAY * factor).min(RECONNECT_MAX_DELAY)
 }
 
 #[derive(Debug)]
@@ -236,19 +430,46 @@ pub enum ApiEvent {
     },
     /// 入っていたルームが閉じられるなどして、ルームから追い出された状態
     RoomStateNotJoined,
+    /// `find-available-room`の結果
+    AvailableRooms {
+        rooms: Vec<RoomSummary>,
+    },
     UpdateField {
+        /// ゲーム開始からのtick数
+        tick_count: i32,
         players: Vec<Player>,
         tagger: Tagger,
-        field: na::SMatrix<i32, FIELD_SIZE, FIELD_SIZE>,
+        field: FieldMatrix,
     },
     GameFinished {
         reason: GameFinishReason,
     },
+    /// 接続が切れたので再接続を試みている
+    Reconnecting {
+        /// 何回目の試行か(1始まり)
+        attempt: u32,
+        max_attempts: u32,
+    },
+    /// 再接続して、`setup-uid`を送り直した
+    Reconnected {
+        /// 切断前にルームに入っていて、`join-room`も送り直したかどうか
+        rejoined_room: bool,
+    },
+    /// 再接続をあきらめた
+    GaveUp,
+    /// サーバーから受け取ったペイロードを解釈できなかった
+    ProtocolError {
+        /// イベント名
+        event: &'static str,
+        /// 受け取ったペイロードそのもの
+        raw: String,
+        error: DecodeError,
+    },
 }
 
 // https://github.com/kcs1959/BlockingIO-api/blob/main/src/routes/socketEvents.ts を写しただけ
 #[allow(dead_code)]
-mod event {
+pub mod event {
     pub const JOIN_ROOM: &str = "join-room";
     pub const ROOM_STATE: &str = "room-state";
     pub const FIND_AVAILABLE_ROOM: &str = "find-available-room";
@@ -259,14 +480,6 @@ mod event {
     pub const REQUEST_AFTER_GAME: &str = "request-after-game";
 }
 
-fn make_height_matrix(
-    squares: [[SquareJson; FIELD_SIZE]; FIELD_SIZE],
-) -> na::SMatrix<i32, FIELD_SIZE, FIELD_SIZE> {
-    na::SMatrix::<i32, FIELD_SIZE, FIELD_SIZE>::from_fn(|x, z| {
-        squares[FIELD_SIZE - 1 - x][z].height as i32
-    })
-}
-
 #[allow(dead_code)]
 #[cfg(debug_assertions)]
 fn print_payload(payload: &Payload) {
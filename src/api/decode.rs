@@ -0,0 +1,377 @@
+//! socket.ioで受け取ったペイロードを`ApiEvent`に変換する
+//!
+//! サーバーから想定外のペイロードが送られてきてもパニックせず、
+//! `ApiEvent::ProtocolError`としてゲーム側に通知する。
+
+use std::fmt;
+
+use crate::{
+    api::{
+        client::{event, ApiEvent, RoomSummary},
+        json::{
+            GameFinishReasonJson, GameStatusJson, OnUpdateUserJson, PlayerStatusJson,
+            RoomStateEventJson, RoomStateJson, SquareJson, UpdateFieldJson,
+        },
+    },
+    player::{Player, Tagger},
+    types::*,
+    GameFinishReason,
+};
+
+/// ペイロードのデコードに失敗した理由
+#[derive(Debug)]
+pub enum DecodeError {
+    /// JSONとして解釈できなかった、または型が合わなかった
+    Json(serde_json::Error),
+    /// 必須のフィールドが`null`だったか、存在しなかった
+    MissingField(&'static str),
+    /// `battleField.squares`が長方形に並んでいなかった
+    InvalidBattleField(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Json(error) => write!(f, "invalid json: {}", error),
+            DecodeError::MissingField(field) => write!(f, "missing field `{}`", field),
+            DecodeError::InvalidBattleField(reason) => write!(f, "invalid battleField: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(error: serde_json::Error) -> Self {
+        DecodeError::Json(error)
+    }
+}
+
+/// `decoder`でペイロードをデコードする。失敗した場合は`ApiEvent::ProtocolError`を1つだけ返す
+pub fn decode_or_protocol_error<F>(event: &'static str, raw: &[u8], decoder: F) -> Vec<ApiEvent>
+where
+    F: FnOnce(&[u8]) -> Result<Vec<ApiEvent>, DecodeError>,
+{
+    match decoder(raw) {
+        Ok(events) => events,
+        Err(error) => {
+            tracing::error!("failed to decode {} event: {}", event, error);
+            vec![ApiEvent::ProtocolError {
+                event,
+                raw: String::from_utf8_lossy(raw).into_owned(),
+                error,
+            }]
+        }
+    }
+}
+
+/// イベント名に対応する`decode_*`でデコードする。デコードするイベントでなければ`None`
+pub fn decode_event(event: &str, raw: &[u8]) -> Option<Vec<ApiEvent>> {
+    type Decoder = fn(&[u8]) -> Result<Vec<ApiEvent>, DecodeError>;
+    let (event, decoder): (&'static str, Decoder) = match event {
+        event::UPDATE_USER => (event::UPDATE_USER, decode_update_user),
+        event::ROOM_STATE => (event::ROOM_STATE, decode_room_state),
+        event::FIND_AVAILABLE_ROOM => (event::FIND_AVAILABLE_ROOM, decode_available_rooms),
+        event::UPDATE_FIELD => (event::UPDATE_FIELD, decode_update_field),
+        _ => return None,
+    };
+    Some(decode_or_protocol_error(event, raw, decoder))
+}
+
+/// `on-update-user`イベント
+pub fn decode_update_user(raw: &[u8]) -> Result<Vec<ApiEvent>, DecodeError> {
+    let json: OnUpdateUserJson = serde_json::from_slice(raw)?;
+    Ok(vec![ApiEvent::UpdateUser {
+        uid: json.uid,
+        name: json.name,
+    }])
+}
+
+/// `room-state`イベント
+pub fn decode_room_state(raw: &[u8]) -> Result<Vec<ApiEvent>, DecodeError> {
+    let json: RoomStateEventJson = serde_json::from_slice(raw)?;
+    let event = match json.state {
+        RoomStateJson::Opening => ApiEvent::RoomStateOpening {
+            room_id: json.roomId.ok_or(DecodeError::MissingField("roomId"))?,
+            room_name: json.roomname.ok_or(DecodeError::MissingField("roomname"))?,
+        },
+        RoomStateJson::Fulfilled => ApiEvent::RoomStateFulfilled {
+            room_id: json.roomId.ok_or(DecodeError::MissingField("roomId"))?,
+            room_name: json.roomname.ok_or(DecodeError::MissingField("roomname"))?,
+            should_start: json.assignedUsers.iter().all(|user| user.requestingToStartGame),
+        },
+        RoomStateJson::Empty => ApiEvent::RoomStateEmpty {
+            room_id: json.roomId.ok_or(DecodeError::MissingField("roomId"))?,
+            room_name: json.roomname.ok_or(DecodeError::MissingField("roomname"))?,
+        },
+        RoomStateJson::notJoining => ApiEvent::RoomStateNotJoined,
+    };
+    Ok(vec![event])
+}
+
+/// `find-available-room`イベント
+///
+/// ペイロードは`room-state`と同じ形のルームの配列。`notJoining`のものは飛ばす
+pub fn decode_available_rooms(raw: &[u8]) -> Result<Vec<ApiEvent>, DecodeError> {
+    let json: Vec<RoomStateEventJson> = serde_json::from_slice(raw)?;
+    let mut rooms = Vec::with_capacity(json.len());
+    for room in json {
+        if let RoomStateJson::notJoining = room.state {
+            continue;
+        }
+        rooms.push(RoomSummary {
+            room_id: room.roomId.ok_or(DecodeError::MissingField("roomId"))?,
+            room_name: room.roomname.ok_or(DecodeError::MissingField("roomname"))?,
+            member_count: room.assignedUsers.len(),
+            max_member: room.maxMember.ok_or(DecodeError::MissingField("maxMember"))? as usize,
+        });
+    }
+    Ok(vec![ApiEvent::AvailableRooms { rooms }])
+}
+
+/// `update-field`イベント
+///
+/// ゲーム開始前のものは空、ゲームが終了したときは`UpdateField`と`GameFinished`の2つを返す
+pub fn decode_update_field(raw: &[u8]) -> Result<Vec<ApiEvent>, DecodeError> {
+    let json: UpdateFieldJson = serde_json::from_slice(raw)?;
+    match json.state {
+        GameStatusJson::BeforeStart | GameStatusJson::PendingStart => Ok(Vec::new()),
+        GameStatusJson::InGame | GameStatusJson::Finish => {
+            let mut events = Vec::with_capacity(2);
+
+            if json.battle_field.length as usize != json.battle_field.squares.len() {
+                tracing::warn!("unexpected battleField.length: {}", json.battle_field.length);
+            }
+            let field = make_height_matrix(&json.battle_field.squares)?;
+            // 1行目が+x方向の端
+            let top = field.nrows() as i32 - 1;
+
+            let players = json
+                .player_list
+                .iter()
+                .map(|player| Player {
+                    point: player.point,
+                    alive: matches!(player.status, PlayerStatusJson::Alive),
+                    ..Player::new(
+                        Point2i::new(top - player.position.row, player.position.column),
+                        player.uid,
+                        player.name.clone(),
+                    )
+                })
+                .collect();
+
+            let tagger = Tagger::new(Point2i::new(
+                top - json.tagger.position.row,
+                json.tagger.position.column,
+            ));
+
+            events.push(ApiEvent::UpdateField {
+                tick_count: json.tick_count,
+                players,
+                tagger,
+                field,
+            });
+
+            if let GameStatusJson::Finish = json.state {
+                let winner_uid = json.winner.map(|winner| winner.uid);
+                events.push(ApiEvent::GameFinished {
+                    reason: match json.finish_reason {
+                        Some(GameFinishReasonJson::Fall) => GameFinishReason::Fall { winner_uid },
+                        Some(GameFinishReasonJson::Collision) => {
+                            GameFinishReason::Collision { winner_uid }
+                        }
+                        Some(GameFinishReasonJson::Timeup) => GameFinishReason::Timeup,
+                        None => {
+                            tracing::warn!("stateがFinishなのにfinishReasonがありません");
+                            GameFinishReason::Abnromal
+                        }
+                    },
+                });
+            }
+            Ok(events)
+        }
+        GameStatusJson::AbnormalEnd => Ok(vec![ApiEvent::GameFinished {
+            reason: GameFinishReason::Abnromal,
+        }]),
+    }
+}
+
+/// `squares`の行数と列数の`FieldMatrix`を作る。1行目が+x方向の端
+fn make_height_matrix(squares: &[Vec<SquareJson>]) -> Result<FieldMatrix, DecodeError> {
+    let rows = squares.len();
+    let columns = squares.first().map_or(0, Vec::len);
+    if columns == 0 {
+        return Err(DecodeError::InvalidBattleField("no squares"));
+    }
+    if squares.iter().any(|row| row.len() != columns) {
+        return Err(DecodeError::InvalidBattleField("rows have different lengths"));
+    }
+    Ok(FieldMatrix::from_fn(rows, columns, |x, z| {
+        squares[rows - 1 - x][z].height as i32
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FIELD_SIZE;
+
+    fn update_field_json(state: &str) -> serde_json::Value {
+        field_json(state, FIELD_SIZE, FIELD_SIZE)
+    }
+
+    fn field_json(state: &str, rows: usize, columns: usize) -> serde_json::Value {
+        let squares: Vec<Vec<serde_json::Value>> = (0..rows)
+            .map(|row| {
+                (0..columns)
+                    .map(|column| serde_json::json!({ "height": (row + column) % 3 }))
+                    .collect()
+            })
+            .collect();
+        serde_json::json!({
+            "winner": null,
+            "tickCount": 10,
+            "battleField": { "length": rows, "squares": squares },
+            "listOfPlayer": [{
+                "position": { "row": 0, "column": 3 },
+                "direction": "up",
+                "point": 3,
+                "uid": "7c9e6679-7425-40de-944b-e07fc1f90ae7",
+                "name": "名無しユーザー",
+                "status": "dead",
+            }],
+            "tagger": {
+                "position": { "row": rows - 1, "column": 0 },
+                "direction": "down",
+                "name": "tagger",
+            },
+            "state": state,
+            "finishReason": null,
+        })
+    }
+
+    #[test]
+    fn update_user() {
+        let raw = br#"{"name":"a","point":0,"uid":"7c9e6679-7425-40de-944b-e07fc1f90ae7","socketId":"s","requestingToStartGame":false}"#;
+        let events = decode_or_protocol_error("on-update-user", raw, decode_update_user);
+        assert!(matches!(events.as_slice(), [ApiEvent::UpdateUser { name, .. }] if name == "a"));
+    }
+
+    #[test]
+    fn update_user_missing_uid() {
+        let raw = br#"{"name":"a","point":0,"socketId":"s","requestingToStartGame":false}"#;
+        let events = decode_or_protocol_error("on-update-user", raw, decode_update_user);
+        match events.as_slice() {
+            [ApiEvent::ProtocolError { event, raw, error }] => {
+                assert_eq!(*event, "on-update-user");
+                assert!(raw.contains("socketId"));
+                assert!(matches!(error, DecodeError::Json(_)));
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn room_state_without_room_id() {
+        let raw = br#"{"assignedUsers":[],"roomname":"r","state":"Opening"}"#;
+        let events = decode_or_protocol_error("room-state", raw, decode_room_state);
+        assert!(matches!(
+            events.as_slice(),
+            [ApiEvent::ProtocolError {
+                error: DecodeError::MissingField("roomId"),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn room_state_not_joining() {
+        let raw = br#"{"assignedUsers":[],"state":"notJoining"}"#;
+        let events = decode_or_protocol_error("room-state", raw, decode_room_state);
+        assert!(matches!(events.as_slice(), [ApiEvent::RoomStateNotJoined]));
+    }
+
+    #[test]
+    fn available_rooms() {
+        let raw = br#"[
+            {"maxMember":2,"assignedUsers":[{"name":"a","point":0,"uid":"7c9e6679-7425-40de-944b-e07fc1f90ae7","socketId":"s","requestingToStartGame":true}],"roomId":"r1","roomname":"room","currentGame":null,"state":"Opening"},
+            {"maxMember":2,"assignedUsers":[],"roomId":"r2","roomname":"empty","currentGame":null,"state":"Empty"}
+        ]"#;
+        let events = decode_or_protocol_error("find-available-room", raw, decode_available_rooms);
+        match events.as_slice() {
+            [ApiEvent::AvailableRooms { rooms }] => {
+                assert_eq!(rooms.len(), 2);
+                assert_eq!(rooms[0].room_id, "r1");
+                assert_eq!(rooms[0].member_count, 1);
+                assert_eq!(rooms[0].max_member, 2);
+                assert_eq!(rooms[1].member_count, 0);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn update_field_in_game() {
+        let raw = update_field_json("InGame").to_string();
+        let events = decode_or_protocol_error("update-field", raw.as_bytes(), decode_update_field);
+        match events.as_slice() {
+            [ApiEvent::UpdateField {
+                tick_count,
+                players,
+                tagger,
+                field,
+            }] => {
+                assert_eq!(*tick_count, 10);
+                assert_eq!(players[0].pos, Point2i::new(FIELD_SIZE as i32 - 1, 3));
+                assert_eq!((players[0].point, players[0].alive), (3, false));
+                assert_eq!(tagger.pos, Point2i::new(0, 0));
+                assert_eq!(field[(FIELD_SIZE - 1, 2)], 2);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn update_field_before_start() {
+        let raw = update_field_json("BeforeStart").to_string();
+        let events = decode_or_protocol_error("update-field", raw.as_bytes(), decode_update_field);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn update_field_with_unknown_state() {
+        let raw = update_field_json("Paused").to_string();
+        let events = decode_or_protocol_error("update-field", raw.as_bytes(), decode_update_field);
+        assert!(matches!(events.as_slice(), [ApiEvent::ProtocolError { .. }]));
+    }
+
+    #[test]
+    fn update_field_with_rectangular_field() {
+        let raw = field_json("InGame", 20, 48).to_string();
+        let events = decode_or_protocol_error("update-field", raw.as_bytes(), decode_update_field);
+        match events.as_slice() {
+            [ApiEvent::UpdateField { players, field, .. }] => {
+                assert_eq!(field.shape(), (20, 48));
+                // 1行目が+x方向の端
+                assert_eq!(players[0].pos, Point2i::new(19, 3));
+                assert_eq!(field[(19, 47)], 47 % 3);
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn update_field_with_ragged_rows() {
+        let mut json = update_field_json("InGame");
+        json["battleField"]["squares"][3].as_array_mut().unwrap().pop();
+        let raw = json.to_string();
+        let events = decode_or_protocol_error("update-field", raw.as_bytes(), decode_update_field);
+        assert!(matches!(
+            events.as_slice(),
+            [ApiEvent::ProtocolError {
+                error: DecodeError::InvalidBattleField(_),
+                ..
+            }]
+        ));
+    }
+}
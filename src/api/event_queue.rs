@@ -0,0 +1,196 @@
+//! socket.ioのスレッドからメインループへ`ApiEvent`を受け渡すためのキュー
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::api::client::ApiEvent;
+
+/// スレッド間で共有される`EventQueue`
+pub type SharedEventQueue = Arc<Mutex<EventQueue>>;
+
+/// `ApiEvent`のキュー
+///
+/// 取り出すときに、すぐ後ろに新しい`UpdateField`が控えている`UpdateField`はまとめて捨てる。
+/// また、すでに取り出したものより古い`tickCount`の`UpdateField`も捨てる。
+pub struct EventQueue {
+    events: VecDeque<ApiEvent>,
+    /// 最後に取り出した`UpdateField`の`tickCount`。ゲームが変わるとリセットされる
+    last_tick: Option<i32>,
+    /// 古い、または順番が入れ替わって届いたために捨てた`UpdateField`の数
+    dropped_ticks: u64,
+    /// 後続の`UpdateField`に上書きされたために捨てた`UpdateField`の数
+    collapsed_ticks: u64,
+    /// これまでにキューに溜まった最大のイベント数
+    max_depth: usize,
+}
+
+impl EventQueue {
+    pub fn new() -> Self {
+        Self {
+            events: VecDeque::new(),
+            last_tick: None,
+            dropped_ticks: 0,
+            collapsed_ticks: 0,
+            max_depth: 0,
+        }
+    }
+
+    pub fn new_shared() -> SharedEventQueue {
+        Arc::new(Mutex::new(Self::new()))
+    }
+
+    pub fn push(&mut self, event: ApiEvent) {
+        self.events.push_back(event);
+        self.max_depth = self.max_depth.max(self.events.len());
+    }
+
+    pub fn extend<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = ApiEvent>,
+    {
+        for event in events {
+            self.push(event);
+        }
+    }
+
+    /// 溜まっているイベントをすべて、届いた順に取り出す
+    pub fn drain(&mut self) -> Vec<ApiEvent> {
+        let mut drained = Vec::with_capacity(self.events.len());
+        while let Some(event) = self.events.pop_front() {
+            match event {
+                ApiEvent::UpdateField { tick_count, .. } => {
+                    if matches!(self.last_tick, Some(last_tick) if tick_count <= last_tick) {
+                        self.dropped_ticks += 1;
+                        continue;
+                    }
+                    if let Some(ApiEvent::UpdateField {
+                        tick_count: next_tick,
+                        ..
+                    }) = self.events.front()
+                    {
+                        if *next_tick > tick_count {
+                            self.collapsed_ticks += 1;
+                            continue;
+                        }
+                    }
+                    self.last_tick = Some(tick_count);
+                    drained.push(event);
+                }
+                ApiEvent::GameFinished { .. } | ApiEvent::RoomStateNotJoined | ApiEvent::GaveUp => {
+                    // 次のゲームのtickCountはまた0から始まる
+                    self.last_tick = None;
+                    drained.push(event);
+                }
+                _ => drained.push(event),
+            }
+        }
+        drained
+    }
+
+    /// 古い`UpdateField`を捨てるための`tickCount`を忘れる。リプレイで過去に戻るときに使う
+    pub fn reset_ticks(&mut self) {
+        self.last_tick = None;
+    }
+
+    /// 現在キューに溜まっているイベントの数
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn dropped_ticks(&self) -> u64 {
+        self.dropped_ticks
+    }
+
+    pub fn collapsed_ticks(&self) -> u64 {
+        self.collapsed_ticks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::Tagger;
+    use crate::types::*;
+    use crate::{GameFinishReason, FIELD_SIZE};
+
+    fn update_field(tick_count: i32) -> ApiEvent {
+        ApiEvent::UpdateField {
+            tick_count,
+            players: Vec::new(),
+            tagger: Tagger::new(Point2i::new(0, 0)),
+            field: FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+        }
+    }
+
+    fn ticks(events: &[ApiEvent]) -> Vec<Option<i32>> {
+        events
+            .iter()
+            .map(|event| match event {
+                ApiEvent::UpdateField { tick_count, .. } => Some(*tick_count),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drain_all_events() {
+        let mut queue = EventQueue::new();
+        queue.push(ApiEvent::RoomStateNotJoined);
+        queue.push(update_field(1));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(ticks(&queue.drain()), vec![None, Some(1)]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn collapse_superseded_ticks() {
+        let mut queue = EventQueue::new();
+        queue.extend(vec![update_field(1), update_field(2), update_field(3)]);
+        assert_eq!(ticks(&queue.drain()), vec![Some(3)]);
+        assert_eq!(queue.collapsed_ticks(), 2);
+        assert_eq!(queue.dropped_ticks(), 0);
+        assert_eq!(queue.max_depth(), 3);
+    }
+
+    #[test]
+    fn drop_stale_ticks() {
+        let mut queue = EventQueue::new();
+        queue.push(update_field(5));
+        queue.drain();
+        queue.extend(vec![update_field(4), update_field(5), update_field(6)]);
+        assert_eq!(ticks(&queue.drain()), vec![Some(6)]);
+        assert_eq!(queue.dropped_ticks(), 2);
+    }
+
+    #[test]
+    fn accept_older_tick_after_reset() {
+        let mut queue = EventQueue::new();
+        queue.push(update_field(5));
+        queue.drain();
+        queue.reset_ticks();
+        queue.push(update_field(2));
+        assert_eq!(ticks(&queue.drain()), vec![Some(2)]);
+    }
+
+    #[test]
+    fn keep_last_tick_before_game_finished() {
+        let mut queue = EventQueue::new();
+        queue.extend(vec![
+            update_field(9),
+            update_field(10),
+            ApiEvent::GameFinished {
+                reason: GameFinishReason::Timeup,
+            },
+            update_field(0),
+        ]);
+        assert_eq!(ticks(&queue.drain()), vec![Some(10), None, Some(0)]);
+    }
+}
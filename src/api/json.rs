@@ -1,15 +1,13 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::FIELD_SIZE;
-
 #[derive(Deserialize)]
 pub struct PositionJson {
     pub row: i32,
     pub column: i32,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DirectionJson {
     #[serde(rename = "down")]
     Down,
@@ -53,8 +51,10 @@ pub struct SquareJson {
 
 #[derive(Deserialize)]
 pub struct BattleFieldJson {
+    /// `squares`の行数
     pub length: i32,
-    pub squares: [[SquareJson; FIELD_SIZE]; FIELD_SIZE],
+    /// 行ごとのマス。どの行も同じ数のマスを持つ
+    pub squares: Vec<Vec<SquareJson>>,
 }
 
 #[derive(Deserialize)]
@@ -124,6 +124,15 @@ pub struct RoomStateEventJson {
     pub state: RoomStateJson,
 }
 
+/// `join-room`のペイロード。どちらも`None`ならサーバーがルームを選ぶ
+#[derive(Serialize)]
+pub struct JoinRoomJson {
+    #[serde(rename = "roomId", skip_serializing_if = "Option::is_none")]
+    pub room_id: Option<String>,
+    #[serde(rename = "roomname", skip_serializing_if = "Option::is_none")]
+    pub room_name: Option<String>,
+}
+
 #[derive(Serialize)]
 pub struct SetupUidJson {
     pub user_id: Uuid,
@@ -141,7 +150,6 @@ pub struct OnUpdateUserJson {
 
 #[derive(Serialize)]
 #[allow(non_camel_case_types)]
-#[allow(dead_code)]
 pub enum RequestAfterGameJson {
     restart,
     leave,
@@ -1,2 +1,5 @@
 pub mod client;
+pub mod decode;
+pub mod event_queue;
 pub mod json;
+pub mod transport;
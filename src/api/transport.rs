@@ -0,0 +1,254 @@
+//! サーバーとのやり取りを抽象化する
+//!
+//! 本物のサーバーにはsocket.ioで接続する`ApiClient`を、サーバーなしで遊ぶときは
+//! `offline::OfflineGame`を、テストでは`ScriptedTransport`を使う。
+//! どの実装も、サーバーからのイベントは`connect`で渡された`EventQueue`に積む。
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::api::client::{ApiClient, ApiEvent, RoomChoice};
+use crate::api::event_queue::SharedEventQueue;
+use crate::api::json::DirectionJson;
+
+pub trait GameTransport {
+    /// 接続する。以降、サーバーからのイベントは`queue`に積まれる
+    fn connect(&mut self, queue: &SharedEventQueue) -> Result<(), Box<dyn Error>>;
+
+    fn setup_uid(&mut self, uid: Uuid) -> Result<(), Box<dyn Error>>;
+
+    /// 入れるルームの一覧を問い合わせる。結果は`ApiEvent::AvailableRooms`で届く
+    fn find_available_rooms(&mut self) -> Result<(), Box<dyn Error>>;
+
+    fn join_room(&mut self, choice: &RoomChoice) -> Result<(), Box<dyn Error>>;
+
+    fn try_move(&mut self, direction: &DirectionJson) -> Result<(), Box<dyn Error>>;
+
+    /// 結果画面から再戦を希望する
+    fn restart(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// 結果画面からルームを出る。`ApiEvent::RoomStateNotJoined`が返ってくる
+    fn leave(&mut self) -> Result<(), Box<dyn Error>>;
+
+    fn disconnect(&mut self) -> Result<(), Box<dyn Error>>;
+
+    fn is_connected(&self) -> bool;
+
+    /// 毎フレーム呼ぶ。`elapsed`は前のフレームからの経過時間
+    fn poll(&mut self, elapsed: Duration);
+}
+
+impl GameTransport for ApiClient {
+    fn connect(&mut self, queue: &SharedEventQueue) -> Result<(), Box<dyn Error>> {
+        ApiClient::connect(self, queue).map_err(Into::into)
+    }
+
+    fn setup_uid(&mut self, uid: Uuid) -> Result<(), Box<dyn Error>> {
+        ApiClient::setup_uid(self, uid)
+    }
+
+    fn find_available_rooms(&mut self) -> Result<(), Box<dyn Error>> {
+        ApiClient::find_available_rooms(self)
+    }
+
+    fn join_room(&mut self, choice: &RoomChoice) -> Result<(), Box<dyn Error>> {
+        ApiClient::join_room(self, choice)
+    }
+
+    fn try_move(&mut self, direction: &DirectionJson) -> Result<(), Box<dyn Error>> {
+        ApiClient::try_move(self, direction)
+    }
+
+    fn restart(&mut self) -> Result<(), Box<dyn Error>> {
+        ApiClient::restart(self)
+    }
+
+    fn leave(&mut self) -> Result<(), Box<dyn Error>> {
+        ApiClient::leave(self)
+    }
+
+    fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        ApiClient::disconnect(self)
+    }
+
+    fn is_connected(&self) -> bool {
+        ApiClient::is_connected(self)
+    }
+
+    fn poll(&mut self, _elapsed: Duration) {
+        self.poll_connection();
+    }
+}
+
+/// `GameTransport`に送られた要求
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    SetupUid(Uuid),
+    FindAvailableRooms,
+    JoinRoom(RoomChoice),
+    TryMove(DirectionJson),
+    Restart,
+    Leave,
+}
+
+/// 台本どおりに返事をする、メモリ上だけの`GameTransport`
+///
+/// `reply`で登録した要求が送られてくると、対応するイベントをキューに積む。
+/// 送られた要求は`requests`で確かめられる。
+#[derive(Default)]
+pub struct ScriptedTransport {
+    queue: Option<SharedEventQueue>,
+    /// まだ使われていない返事。同じ要求には登録した順に使う
+    replies: VecDeque<(Request, Vec<ApiEvent>)>,
+    requests: Vec<Request>,
+    /// 残りの`connect`を失敗させる回数
+    failing_connects: u32,
+}
+
+impl ScriptedTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `request`が送られてきたら`events`を返すようにする
+    pub fn reply(&mut self, request: Request, events: Vec<ApiEvent>) -> &mut Self {
+        self.replies.push_back((request, events));
+        self
+    }
+
+    /// 要求とは関係なく、サーバーからイベントが届いたことにする
+    pub fn push(&mut self, events: Vec<ApiEvent>) {
+        match self.queue {
+            Some(ref queue) => queue.lock().unwrap().extend(events),
+            None => panic!("not connected"),
+        }
+    }
+
+    /// 次の`count`回の`connect`を失敗させる
+    pub fn fail_connects(&mut self, count: u32) {
+        self.failing_connects = count;
+    }
+
+    /// これまでに送られた要求
+    pub fn requests(&self) -> &[Request] {
+        &self.requests
+    }
+
+    fn send(&mut self, request: Request) -> Result<(), Box<dyn Error>> {
+        let queue = self.queue.clone().ok_or("not connected")?;
+        if let Some(index) = self.replies.iter().position(|(r, _)| *r == request) {
+            let (_, events) = self.replies.remove(index).unwrap();
+            queue.lock().unwrap().extend(events);
+        }
+        self.requests.push(request);
+        Ok(())
+    }
+}
+
+impl GameTransport for ScriptedTransport {
+    fn connect(&mut self, queue: &SharedEventQueue) -> Result<(), Box<dyn Error>> {
+        if self.failing_connects > 0 {
+            self.failing_connects -= 1;
+            return Err("connection refused".into());
+        }
+        self.queue = Some(Arc::clone(queue));
+        Ok(())
+    }
+
+    fn setup_uid(&mut self, uid: Uuid) -> Result<(), Box<dyn Error>> {
+        self.send(Request::SetupUid(uid))
+    }
+
+    fn find_available_rooms(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send(Request::FindAvailableRooms)
+    }
+
+    fn join_room(&mut self, choice: &RoomChoice) -> Result<(), Box<dyn Error>> {
+        self.send(Request::JoinRoom(choice.clone()))
+    }
+
+    fn try_move(&mut self, direction: &DirectionJson) -> Result<(), Box<dyn Error>> {
+        self.send(Request::TryMove(*direction))
+    }
+
+    fn restart(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send(Request::Restart)
+    }
+
+    fn leave(&mut self) -> Result<(), Box<dyn Error>> {
+        self.send(Request::Leave)
+    }
+
+    fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.queue.take().ok_or("not connected")?;
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    fn poll(&mut self, _elapsed: Duration) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::event_queue::EventQueue;
+
+    #[test]
+    fn scripted_replies() {
+        let queue = EventQueue::new_shared();
+        let uid = Uuid::new_v4();
+        let mut transport = ScriptedTransport::new();
+        transport
+            .reply(
+                Request::SetupUid(uid),
+                vec![ApiEvent::UpdateUser {
+                    uid,
+                    name: "me".to_string(),
+                }],
+            )
+            .reply(Request::Leave, vec![ApiEvent::RoomStateNotJoined]);
+
+        assert!(transport.setup_uid(uid).is_err());
+        transport.connect(&queue).unwrap();
+        transport.setup_uid(uid).unwrap();
+        transport.try_move(&DirectionJson::Up).unwrap();
+        transport.leave().unwrap();
+        // 返事は1回しか使わない
+        transport.leave().unwrap();
+
+        let events = queue.lock().unwrap().drain();
+        assert!(matches!(
+            events.as_slice(),
+            [ApiEvent::UpdateUser { .. }, ApiEvent::RoomStateNotJoined]
+        ));
+        assert_eq!(
+            transport.requests(),
+            &[
+                Request::SetupUid(uid),
+                Request::TryMove(DirectionJson::Up),
+                Request::Leave,
+                Request::Leave,
+            ]
+        );
+    }
+
+    #[test]
+    fn failing_connect() {
+        let queue = EventQueue::new_shared();
+        let mut transport = ScriptedTransport::new();
+        transport.fail_connects(1);
+        assert!(transport.connect(&queue).is_err());
+        assert!(!transport.is_connected());
+        transport.connect(&queue).unwrap();
+        assert!(transport.is_connected());
+        transport.disconnect().unwrap();
+        assert!(!transport.is_connected());
+    }
+}
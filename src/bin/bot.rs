@@ -0,0 +1,195 @@
+//! ウィンドウを開かずにゲームをするボット
+//!
+//! ```text
+//! cargo run --bin bot -- [--url <url>] [--uuid <uuid>] [--games <n>] [--strategy <name>] [--seed <n>]
+//! ```
+
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use blocking_io_client::api::client::{ApiClient, ApiEvent, RoomChoice};
+use blocking_io_client::api::event_queue::EventQueue;
+use blocking_io_client::bot::{strategy, Strategy, Summary};
+use blocking_io_client::setting_storage::DEFAULT_SERVER;
+use blocking_io_client::types::ResultExt;
+use blocking_io_client::world::World;
+
+/// イベントを確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+
+struct Options {
+    url: String,
+    uuid: Uuid,
+    games: u32,
+    strategy: Box<dyn Strategy>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: bot [--url <url>] [--uuid <uuid>] [--games <n>] [--strategy <{}>] [--seed <n>]",
+        strategy::NAMES.join("|")
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut url = DEFAULT_SERVER.to_string();
+    let mut uuid = Uuid::new_v4();
+    let mut games = 1;
+    let mut strategy_name = "safe-path".to_string();
+    let mut seed = rand::random();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(),
+        };
+        match arg.as_str() {
+            "--url" => url = value,
+            "--uuid" => uuid = Uuid::parse_str(&value).unwrap_or_else(|_| usage()),
+            "--games" => games = value.parse().unwrap_or_else(|_| usage()),
+            "--strategy" => strategy_name = value,
+            "--seed" => seed = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+
+    Options {
+        url,
+        uuid,
+        games,
+        strategy: strategy::by_name(&strategy_name, seed).unwrap_or_else(|| usage()),
+    }
+}
+
+fn main() {
+    let level = if std::env::var("BLKIO_TRACE").unwrap_or_default() == "1" {
+        tracing::Level::TRACE
+    } else {
+        tracing::Level::INFO
+    };
+    tracing_subscriber::fmt().with_max_level(level).init();
+
+    let Options {
+        url,
+        uuid,
+        games,
+        mut strategy,
+    } = parse_args();
+    info!("bot {} ({}) connecting {}", uuid, strategy.name(), url);
+
+    let queue = EventQueue::new_shared();
+    let mut api = ApiClient::new(&url);
+    if let Err(error) = api.connect(&queue) {
+        error!("サーバーに接続できません: {}", error);
+        process::exit(1);
+    }
+    if let Err(error) = api.setup_uid(uuid) {
+        error!("uidを設定できません: {}", error);
+        process::exit(1);
+    }
+
+    let mut world = World::new();
+    let mut summary = Summary::default();
+    let mut playing = false;
+    // `join-room`を送ったかどうか。再接続したときは`ApiClient`が入り直すので、
+    // その後の`update-user`でもう一度送らないようにする
+    let mut joined = false;
+
+    'main: while summary.games < games {
+        api.poll_connection();
+
+        let events = queue.lock().unwrap_or_log().drain();
+        for event in events {
+            match event {
+                ApiEvent::UpdateUser { name, .. } => {
+                    info!("user name: {}", name);
+                    if !joined {
+                        if let Err(error) = api.join_room(&RoomChoice::Any) {
+                            error!("ルームに入れません: {}", error);
+                            break 'main;
+                        }
+                        joined = true;
+                    }
+                }
+                ApiEvent::RoomStateOpening { room_name, .. } => {
+                    info!("waiting in room {}", room_name);
+                }
+                ApiEvent::RoomStateFulfilled {
+                    room_name,
+                    should_start,
+                    ..
+                } => {
+                    if should_start && !playing {
+                        info!("game {} started in room {}", summary.games + 1, room_name);
+                        playing = true;
+                    }
+                }
+                ApiEvent::UpdateField {
+                    players,
+                    tagger,
+                    field,
+                    ..
+                } => {
+                    playing = true;
+                    world.update(field);
+                    world.set_players(players);
+                    world.set_tagger(tagger);
+                    if let Some(direction) = strategy.next_move(&world, uuid) {
+                        if let Err(error) = api.try_move(&direction) {
+                            warn!("failed move: {}", error);
+                        }
+                    }
+                }
+                ApiEvent::GameFinished { reason } => {
+                    playing = false;
+                    summary.record(&reason, uuid);
+                    info!("game {} finished: {:?}", summary.games, reason);
+                    if summary.games < games {
+                        if let Err(error) = api.restart() {
+                            error!("再戦を希望できません: {}", error);
+                            break 'main;
+                        }
+                    }
+                }
+                ApiEvent::RoomStateNotJoined => {
+                    warn!("ルームから追い出されました。入り直します");
+                    playing = false;
+                    if let Err(error) = api.join_room(&RoomChoice::Any) {
+                        error!("ルームに入れません: {}", error);
+                        break 'main;
+                    }
+                }
+                ApiEvent::Reconnecting {
+                    attempt,
+                    max_attempts,
+                } => warn!("reconnecting {}/{}", attempt, max_attempts),
+                ApiEvent::Reconnected { rejoined_room } => {
+                    info!("reconnected. rejoined_room: {}", rejoined_room)
+                }
+                ApiEvent::GaveUp => {
+                    error!("サーバーに再接続できませんでした");
+                    break 'main;
+                }
+                ApiEvent::ProtocolError { event, error, .. } => {
+                    warn!("{}イベントを解釈できません: {}", event, error);
+                }
+                ApiEvent::RoomStateEmpty { .. } | ApiEvent::AvailableRooms { .. } => {
+                    debug!("ignored event: {:?}", event);
+                }
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    if let Err(error) = api.disconnect() {
+        warn!("failed disconnect: {}", error);
+    }
+    println!("{} ({}): {}", uuid, strategy.name(), summary);
+}
@@ -0,0 +1,66 @@
+//! BlockingIO-apiの代役をするローカルサーバーを起動する
+//!
+//! ```text
+//! cargo run --bin stand-in-server -- [--port 3000] [--scenario timeup] [--members 2]
+//! ```
+
+use std::net::SocketAddr;
+use std::process;
+
+use blocking_io_client::stand_in_server::{Scenario, StandInConfig, StandInServer};
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: stand-in-server [--port <port>] [--scenario <{}>] [--members <n>]",
+        Scenario::NAMES.join("|")
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> StandInConfig {
+    let mut config = StandInConfig::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match args.next() {
+            Some(value) => value,
+            None => usage(),
+        };
+        match arg.as_str() {
+            "--port" => {
+                let port = value.parse().unwrap_or_else(|_| usage());
+                config.addr = SocketAddr::from(([127, 0, 0, 1], port));
+            }
+            "--scenario" => config.scenario = Scenario::by_name(&value).unwrap_or_else(|| usage()),
+            "--members" => match value.parse() {
+                Ok(members) if members > 0 => config.max_member = members,
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    config
+}
+
+fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .init();
+
+    let config = parse_args();
+    let scenario = config.scenario.name;
+    let max_member = config.max_member;
+    let server = StandInServer::start(config).unwrap_or_else(|error| {
+        eprintln!("failed to start: {}", error);
+        process::exit(1);
+    });
+
+    println!("stand-in server: {}", server.url());
+    println!("scenario: {}, members: {}", scenario, max_member);
+    println!(
+        "blocking-io-settings.toml の server を \"{}\" にするとこのサーバーに接続します",
+        server.url()
+    );
+    loop {
+        std::thread::park();
+    }
+}
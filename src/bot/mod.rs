@@ -0,0 +1,114 @@
+//! ウィンドウを開かずにゲームをするボット
+//!
+//! `ApiClient`から受け取った`update-field`を`World`に反映し、`Strategy`に次の一手を決めさせる。
+//! サーバーの負荷試験や、練習相手として使う。実行ファイルは`src/bin/bot.rs`。
+
+pub mod strategy;
+
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::api::json::DirectionJson;
+use crate::types::*;
+use crate::world::in_field;
+use crate::GameFinishReason;
+
+pub use strategy::Strategy;
+
+/// この段差より大きい段差を移動すると落下する(とボットは考える)
+pub const MAX_SAFE_STEP: i32 = 1;
+
+pub const DIRECTIONS: [DirectionJson; 4] = [
+    DirectionJson::Up,
+    DirectionJson::Down,
+    DirectionJson::Left,
+    DirectionJson::Right,
+];
+
+pub fn height(field: &FieldMatrix, pos: &Point2i) -> i32 {
+    field[(pos.x as usize, pos.y as usize)]
+}
+
+/// `from`から`to`へ落ちずに動けるかどうか
+pub fn is_safe_step(field: &FieldMatrix, from: &Point2i, to: &Point2i) -> bool {
+    in_field(field, to) && (height(field, to) - height(field, from)).abs() <= MAX_SAFE_STEP
+}
+
+pub fn manhattan_distance(a: &Point2i, b: &Point2i) -> i32 {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+
+/// 対戦成績
+#[derive(Default, Debug, PartialEq)]
+pub struct Summary {
+    pub games: u32,
+    pub wins: u32,
+    pub losses: u32,
+    /// 時間切れ
+    pub draws: u32,
+    /// 自分が落下した回数(`losses`に含まれる)
+    pub falls: u32,
+    /// 自分が鬼につかまった回数(`losses`に含まれる)
+    pub caught: u32,
+    pub abnormal: u32,
+}
+
+impl Summary {
+    pub fn record(&mut self, reason: &GameFinishReason, own_uid: Uuid) {
+        self.games += 1;
+        match *reason {
+            GameFinishReason::Fall { winner_uid } if winner_uid == Some(own_uid) => self.wins += 1,
+            GameFinishReason::Fall { .. } => {
+                self.losses += 1;
+                self.falls += 1;
+            }
+            GameFinishReason::Collision { winner_uid } if winner_uid == Some(own_uid) => self.wins += 1,
+            GameFinishReason::Collision { .. } => {
+                self.losses += 1;
+                self.caught += 1;
+            }
+            GameFinishReason::Timeup => self.draws += 1,
+            GameFinishReason::Abnromal => self.abnormal += 1,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "games: {}, win: {}, loss: {} (fall: {}, caught: {}), draw: {}, abnormal: {}",
+            self.games, self.wins, self.losses, self.falls, self.caught, self.draws, self.abnormal
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_results() {
+        let me = Uuid::new_v4();
+        let other = Some(Uuid::new_v4());
+        let mut summary = Summary::default();
+        summary.record(&GameFinishReason::Fall { winner_uid: Some(me) }, me);
+        summary.record(&GameFinishReason::Fall { winner_uid: other }, me);
+        summary.record(&GameFinishReason::Collision { winner_uid: None }, me);
+        summary.record(&GameFinishReason::Timeup, me);
+        summary.record(&GameFinishReason::Abnromal, me);
+        assert_eq!(
+            summary,
+            Summary {
+                games: 5,
+                wins: 1,
+                losses: 2,
+                draws: 1,
+                falls: 1,
+                caught: 1,
+                abnormal: 1,
+            }
+        );
+    }
+}
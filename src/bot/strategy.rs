@@ -0,0 +1,232 @@
+//! ボットの戦略
+
+use std::collections::VecDeque;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use uuid::Uuid;
+
+use super::{is_safe_step, manhattan_distance, DIRECTIONS};
+use crate::api::json::DirectionJson;
+use crate::player::Player;
+use crate::types::*;
+use crate::world::World;
+use crate::world::{in_field, moved};
+
+/// `update-field`を受け取るたびに次の一手を決める
+pub trait Strategy {
+    fn name(&self) -> &'static str;
+
+    /// 次に動く方向。動かない場合は`None`
+    fn next_move(&mut self, world: &World, own_uid: Uuid) -> Option<DirectionJson>;
+}
+
+pub const NAMES: [&str; 3] = ["random-walk", "flee", "safe-path"];
+
+/// `NAMES`にある名前から戦略を作る
+pub fn by_name(name: &str, seed: u64) -> Option<Box<dyn Strategy>> {
+    match name {
+        "random-walk" => Some(Box::new(RandomWalk::new(seed))),
+        "flee" => Some(Box::new(FleeTagger)),
+        "safe-path" => Some(Box::new(SafePath::new())),
+        _ => None,
+    }
+}
+
+fn find_own_player(world: &World, own_uid: Uuid) -> Option<&Player> {
+    world.players().iter().find(|player| player.uid == own_uid)
+}
+
+/// フィールドの中をでたらめに歩く
+pub struct RandomWalk {
+    rng: StdRng,
+}
+
+impl RandomWalk {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Strategy for RandomWalk {
+    fn name(&self) -> &'static str {
+        "random-walk"
+    }
+
+    fn next_move(&mut self, world: &World, own_uid: Uuid) -> Option<DirectionJson> {
+        let me = find_own_player(world, own_uid)?;
+        let candidates: Vec<DirectionJson> = DIRECTIONS
+            .iter()
+            .copied()
+            .filter(|&direction| in_field(world.field(), &moved(&me.pos, direction)))
+            .collect();
+        candidates.choose(&mut self.rng).copied()
+    }
+}
+
+/// 高さを気にせず、鬼から一番遠くなる方向に逃げる
+pub struct FleeTagger;
+
+impl Strategy for FleeTagger {
+    fn name(&self) -> &'static str {
+        "flee"
+    }
+
+    fn next_move(&mut self, world: &World, own_uid: Uuid) -> Option<DirectionJson> {
+        let me = find_own_player(world, own_uid)?;
+        let tagger = world.tagger()?;
+        DIRECTIONS
+            .iter()
+            .copied()
+            .filter(|&direction| in_field(world.field(), &moved(&me.pos, direction)))
+            .max_by_key(|&direction| manhattan_distance(&moved(&me.pos, direction), &tagger.pos))
+    }
+}
+
+/// 落ちない段差だけを通って行ける範囲で、鬼から遠く、逃げ道の多いマスを目指す
+pub struct SafePath {
+    /// 何手先まで探索するか
+    depth: u32,
+}
+
+impl SafePath {
+    pub fn new() -> Self {
+        Self { depth: 8 }
+    }
+
+    /// マスの評価値。鬼から遠いほど、落ちずに動ける隣のマスが多いほど高い
+    ///
+    /// 鬼がいなければ、フィールドの中で最も遠いものとする。
+    fn score(field: &FieldMatrix, pos: &Point2i, tagger: Option<&Point2i>) -> i32 {
+        let exits = DIRECTIONS
+            .iter()
+            .filter(|&&direction| is_safe_step(field, pos, &moved(pos, direction)))
+            .count() as i32;
+        let farthest = (field.nrows() + field.ncols()) as i32;
+        let distance = tagger.map_or(farthest, |tagger| manhattan_distance(pos, tagger));
+        distance * 4 + exits
+    }
+}
+
+impl Strategy for SafePath {
+    fn name(&self) -> &'static str {
+        "safe-path"
+    }
+
+    fn next_move(&mut self, world: &World, own_uid: Uuid) -> Option<DirectionJson> {
+        let me = find_own_player(world, own_uid)?;
+        let field = world.field();
+        let tagger = world.tagger().map(|tagger| tagger.pos);
+        let blocked = |pos: &Point2i| {
+            // 鬼の隣や、ほかのプレイヤーがいるマスには入らない
+            tagger.map_or(false, |tagger| manhattan_distance(pos, &tagger) <= 1)
+                || world
+                    .players()
+                    .iter()
+                    .any(|player| player.uid != own_uid && player.pos == *pos)
+        };
+
+        // 幅優先探索で、行けるマスとそこへの最初の一手を求める
+        let mut visited = na::DMatrix::from_element(field.nrows(), field.ncols(), false);
+        visited[(me.pos.x as usize, me.pos.y as usize)] = true;
+        let mut queue = VecDeque::new();
+        queue.push_back((me.pos, 0, None));
+        let mut best: Option<(i32, Option<DirectionJson>)> = None;
+
+        while let Some((pos, steps, first_move)) = queue.pop_front() {
+            let score = Self::score(field, &pos, tagger.as_ref());
+            if best.map_or(true, |(best_score, _)| score > best_score) {
+                best = Some((score, first_move));
+            }
+            if steps >= self.depth {
+                continue;
+            }
+            for &direction in DIRECTIONS.iter() {
+                let next = moved(&pos, direction);
+                if !is_safe_step(field, &pos, &next) || blocked(&next) {
+                    continue;
+                }
+                let (x, z) = (next.x as usize, next.y as usize);
+                if visited[(x, z)] {
+                    continue;
+                }
+                visited[(x, z)] = true;
+                queue.push_back((next, steps + 1, first_move.or(Some(direction))));
+            }
+        }
+
+        best.and_then(|(_, first_move)| first_move)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::Tagger;
+    use crate::FIELD_SIZE;
+
+    fn world_with(field: FieldMatrix, me: Point2i, tagger: Point2i, own_uid: Uuid) -> World {
+        let mut world = World::new();
+        world.update(field);
+        world.set_players(vec![Player::new(me, own_uid, "bot".to_string())]);
+        world.set_tagger(Tagger::new(tagger));
+        world
+    }
+
+    #[test]
+    fn random_walk_stays_in_field() {
+        let uid = Uuid::new_v4();
+        let world = world_with(
+            FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            Point2i::new(0, 0),
+            Point2i::new(20, 20),
+            uid,
+        );
+        let mut strategy = RandomWalk::new(0);
+        for _ in 0..20 {
+            let direction = strategy.next_move(&world, uid).unwrap();
+            assert!(matches!(direction, DirectionJson::Up | DirectionJson::Right));
+        }
+    }
+
+    #[test]
+    fn flee_moves_away_from_tagger() {
+        let uid = Uuid::new_v4();
+        let world = world_with(
+            FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            Point2i::new(10, 10),
+            Point2i::new(10, 12),
+            uid,
+        );
+        assert_eq!(FleeTagger.next_move(&world, uid), Some(DirectionJson::Left));
+    }
+
+    #[test]
+    fn safe_path_avoids_cliff() {
+        let uid = Uuid::new_v4();
+        // 左側は崖になっていて、鬼は右にいる
+        let field = FieldMatrix::from_fn(FIELD_SIZE, FIELD_SIZE, |_, z| if z < 10 { 5 } else { 0 });
+        let world = world_with(field, Point2i::new(10, 10), Point2i::new(10, 14), uid);
+
+        let mut strategy = SafePath::new();
+        let direction = strategy.next_move(&world, uid).unwrap();
+        assert_ne!(direction, DirectionJson::Left);
+
+        // 高さを見ない戦略は崖に飛び込む
+        assert_eq!(FleeTagger.next_move(&world, uid), Some(DirectionJson::Left));
+    }
+
+    #[test]
+    fn unknown_player_does_not_move() {
+        let world = world_with(
+            FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            Point2i::new(0, 0),
+            Point2i::new(5, 5),
+            Uuid::new_v4(),
+        );
+        assert_eq!(SafePath::new().next_move(&world, Uuid::new_v4()), None);
+    }
+}
@@ -2,6 +2,21 @@ use crate::types::*;
 use re::interpolation::{Time, TimeSpan};
 use re::Interpolation;
 
+/// 視点の、注視しているものの真上からの高さ。`scale`をかけた後の座標
+const CAM_HEIGHT: Vector3 = Vector3::new(0.0, 5.0, 0.0);
+
+/// 画面の上下方向の視野角
+const FOVY: f32 = std::f32::consts::PI / 4.0;
+
+/// 画面に映っている、ある高さの水平面の範囲。ワールド座標
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VisibleArea {
+    pub min_x: f32,
+    pub max_x: f32,
+    pub min_z: f32,
+    pub max_z: f32,
+}
+
 pub struct Camera {
     pos: Point3,
     interpolation_x: Interpolation<f32>,
@@ -53,7 +68,6 @@ impl Camera {
     }
 
     pub fn view_matrix(&self, scale: f32) -> Matrix4 {
-        const CAM_HEIGHT: Vector3 = Vector3::new(0.0, 5.0, 0.0);
         // カメラが見下ろす方向は-y方向
         const DOWN: Vector3 = Vector3::new(0.0, -1.0, 0.0);
         // 画面の上方向は+x方向
@@ -65,4 +79,93 @@ impl Camera {
             &X_POSITIVE,
         )
     }
+
+    pub fn projection_matrix(window_width: u32, window_height: u32) -> Matrix4 {
+        Matrix4::new_perspective(window_width as f32 / window_height as f32, FOVY, 0.1, 100.0)
+    }
+
+    /// 高さ`ground_y`の水平面のうち、画面に映っている範囲。画面の上が+x方向、右が+z方向
+    pub fn visible_area(
+        &self,
+        ground_y: f32,
+        scale: f32,
+        window_width: u32,
+        window_height: u32,
+    ) -> VisibleArea {
+        // 真下を見ているので、映る範囲は視点からの距離に比例する
+        let distance = (self.pos.y - ground_y) + CAM_HEIGHT.y / scale;
+        let half_x = distance * (FOVY / 2.0).tan();
+        let half_z = half_x * window_width as f32 / window_height as f32;
+        VisibleArea {
+            min_x: self.pos.x - half_x,
+            max_x: self.pos.x + half_x,
+            min_z: self.pos.z - half_z,
+            max_z: self.pos.z + half_z,
+        }
+    }
+
+    /// ワールド座標の`pos`が画面のどこに映るか。左上が原点のピクセル単位で、カメラの後ろなら`None`
+    pub fn world_to_screen(
+        &self,
+        pos: &Point3,
+        scale: f32,
+        window_width: u32,
+        window_height: u32,
+    ) -> Option<(f32, f32)> {
+        let clip = Self::projection_matrix(window_width, window_height)
+            * self.view_matrix(scale)
+            * (pos * scale).to_homogeneous();
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let x = (clip.x / clip.w + 1.0) / 2.0 * window_width as f32;
+        let y = (1.0 - clip.y / clip.w) / 2.0 * window_height as f32;
+        Some((x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_to_screen() {
+        let camera = Camera::new(Point3::new(16.0, 0.0, 16.0));
+        let center = camera
+            .world_to_screen(&Point3::new(16.0, -1.0, 16.0), 0.5, 800, 600)
+            .unwrap();
+        assert!((center.0 - 400.0).abs() < 0.01 && (center.1 - 300.0).abs() < 0.01);
+
+        // 画面の上は+x方向、右は+z方向
+        let up = camera
+            .world_to_screen(&Point3::new(17.0, -1.0, 16.0), 0.5, 800, 600)
+            .unwrap();
+        assert!(up.1 < center.1);
+        let right = camera
+            .world_to_screen(&Point3::new(16.0, -1.0, 17.0), 0.5, 800, 600)
+            .unwrap();
+        assert!(right.0 > center.0);
+
+        // カメラより上は映らない
+        assert!(camera
+            .world_to_screen(&Point3::new(16.0, 20.0, 16.0), 0.5, 800, 600)
+            .is_none());
+    }
+
+    #[test]
+    fn visible_area_matches_screen_corners() {
+        let camera = Camera::new(Point3::new(16.0, 3.0, 16.0));
+        let area = camera.visible_area(1.0, 0.5, 800, 600);
+        assert!(area.max_x - area.min_x < area.max_z - area.min_z);
+
+        // 左上の角は+x方向と-z方向の端
+        let top_left = camera
+            .world_to_screen(&Point3::new(area.max_x, 1.0, area.min_z), 0.5, 800, 600)
+            .unwrap();
+        assert!(top_left.0.abs() < 0.01 && top_left.1.abs() < 0.01);
+        let bottom_right = camera
+            .world_to_screen(&Point3::new(area.min_x, 1.0, area.max_z), 0.5, 800, 600)
+            .unwrap();
+        assert!((bottom_right.0 - 800.0).abs() < 0.01 && (bottom_right.1 - 600.0).abs() < 0.01);
+    }
 }
@@ -0,0 +1,1083 @@
+//! 画面の状態遷移
+//!
+//! `main.rs`のループは、キー入力やサーバーからのイベントを`Input`にして`transition`に渡し、
+//! 返ってきた`Command`を実行する。状態遷移そのものはウィンドウにもサーバーにも依存しない。
+//!
+//! `doc/ClientState.dot`は`TRANSITIONS`から`to_dot`で生成する。
+
+use std::fmt::Write;
+
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::api::client::{ApiEvent, RoomChoice, RoomSummary};
+use crate::api::decode::DecodeError;
+use crate::player::{Player, Tagger};
+use crate::types::*;
+use crate::{GameFinishReason, FIELD_SIZE};
+
+/// ルームを出るときにサーバーの返事を待つフレーム数
+pub const LEAVE_TIMEOUT_FRAMES: u64 = 180;
+
+#[derive(PartialEq, Debug)]
+pub enum ClientState {
+    /// タイトル画面
+    TitleScreen,
+    /// on-update-userイベントを待っている状態
+    WaitingSettingUid,
+    /// 入るルームを選んでいる状態
+    BrowsingRooms,
+    /// ルームに入っていて、ゲーム開始を待っている状態
+    WaitingInRoom,
+    /// ゲーム中
+    Playing,
+    /// ゲームが終了して、結果を表示している状態
+    GameFinished { reason: GameFinishReason },
+    /// 結果画面からルームを出て、room-stateイベントを待っている状態
+    LeavingRoom {
+        /// ルームを出てから経過したフレーム数
+        waited_frames: u64,
+    },
+    /// 接続が切れて、再接続を待っている状態
+    Reconnecting,
+    /// サーバーから解釈できないイベントを受け取って、エラーを表示している状態
+    ProtocolError,
+    /// `--replay`で指定された記録を再生している状態
+    Replaying {
+        /// 再生中のゲームが終わっていれば、その結果
+        finished: Option<GameFinishReason>,
+    },
+    /// アプリケーションを終了すべき状態
+    Quit,
+}
+
+impl ClientState {
+    pub fn kind(&self) -> StateKind {
+        match self {
+            ClientState::TitleScreen => StateKind::TitleScreen,
+            ClientState::WaitingSettingUid => StateKind::WaitingSettingUid,
+            ClientState::BrowsingRooms => StateKind::BrowsingRooms,
+            ClientState::WaitingInRoom => StateKind::WaitingInRoom,
+            ClientState::Playing => StateKind::Playing,
+            ClientState::GameFinished { .. } => StateKind::GameFinished,
+            ClientState::LeavingRoom { .. } => StateKind::LeavingRoom,
+            ClientState::Reconnecting => StateKind::Reconnecting,
+            ClientState::ProtocolError => StateKind::ProtocolError,
+            ClientState::Replaying { .. } => StateKind::Replaying,
+            ClientState::Quit => StateKind::Quit,
+        }
+    }
+}
+
+/// `ClientState`の種類。状態遷移表で使う
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StateKind {
+    TitleScreen,
+    WaitingSettingUid,
+    BrowsingRooms,
+    WaitingInRoom,
+    Playing,
+    GameFinished,
+    LeavingRoom,
+    Reconnecting,
+    ProtocolError,
+    Replaying,
+    Quit,
+}
+
+impl StateKind {
+    pub const ALL: [StateKind; 11] = [
+        StateKind::TitleScreen,
+        StateKind::WaitingSettingUid,
+        StateKind::BrowsingRooms,
+        StateKind::WaitingInRoom,
+        StateKind::Playing,
+        StateKind::GameFinished,
+        StateKind::LeavingRoom,
+        StateKind::Reconnecting,
+        StateKind::ProtocolError,
+        StateKind::Replaying,
+        StateKind::Quit,
+    ];
+
+    /// 状態遷移図に書く名前
+    fn label(self) -> &'static str {
+        match self {
+            StateKind::TitleScreen => "TitleScreen",
+            StateKind::WaitingSettingUid => "WaitingSettingUid",
+            StateKind::BrowsingRooms => "BrowsingRooms",
+            StateKind::WaitingInRoom => "WaitingInRoom",
+            StateKind::Playing => "Playing",
+            StateKind::GameFinished => "GameFinished{reason}",
+            StateKind::LeavingRoom => "LeavingRoom{waited_frames}",
+            StateKind::Reconnecting => "Reconnecting",
+            StateKind::ProtocolError => "ProtocolError",
+            StateKind::Replaying => "Replaying{finished}",
+            StateKind::Quit => "Quit",
+        }
+    }
+
+    /// この種類の状態の例。`LeavingRoom`は次のフレームでタイムアウトする
+    fn sample(self) -> ClientState {
+        match self {
+            StateKind::TitleScreen => ClientState::TitleScreen,
+            StateKind::WaitingSettingUid => ClientState::WaitingSettingUid,
+            StateKind::BrowsingRooms => ClientState::BrowsingRooms,
+            StateKind::WaitingInRoom => ClientState::WaitingInRoom,
+            StateKind::Playing => ClientState::Playing,
+            StateKind::GameFinished => ClientState::GameFinished {
+                reason: GameFinishReason::Timeup,
+            },
+            StateKind::LeavingRoom => ClientState::LeavingRoom {
+                waited_frames: LEAVE_TIMEOUT_FRAMES,
+            },
+            StateKind::Reconnecting => ClientState::Reconnecting,
+            StateKind::ProtocolError => ClientState::ProtocolError,
+            StateKind::Replaying => ClientState::Replaying { finished: None },
+            StateKind::Quit => ClientState::Quit,
+        }
+    }
+}
+
+/// 状態遷移のきっかけ
+#[derive(Debug)]
+pub enum Input {
+    /// サーバーからのイベント
+    Event(ApiEvent),
+    /// スペースキー
+    Space,
+    /// Escキー
+    Escape,
+    /// タイトル画面でOキー
+    Offline,
+    /// ルームの一覧でEnterキー
+    SelectRoom(RoomChoice),
+    /// 毎フレーム1回
+    Frame,
+    /// 直前の遷移で返した`Command`の実行に失敗した
+    CommandFailed,
+    /// ウィンドウを閉じた
+    Close,
+}
+
+impl Input {
+    /// 状態遷移表と図に書く名前
+    pub fn label(&self) -> &'static str {
+        match self {
+            Input::Event(event) => match event {
+                ApiEvent::UpdateUser { .. } => "ApiEvent::UpdateUser",
+                ApiEvent::RoomStateOpening { .. } => "ApiEvent::RoomStateOpening",
+                ApiEvent::RoomStateFulfilled {
+                    should_start: true, ..
+                } => "ApiEvent::RoomStateFulfilled\\n{should_start=true}",
+                ApiEvent::RoomStateFulfilled { .. } => {
+                    "ApiEvent::RoomStateFulfilled\\n{should_start=false}"
+                }
+                ApiEvent::RoomStateEmpty { .. } => "ApiEvent::RoomStateEmpty",
+                ApiEvent::RoomStateNotJoined => "ApiEvent::\\nRoomStateNotJoined",
+                ApiEvent::AvailableRooms { .. } => "ApiEvent::AvailableRooms",
+                ApiEvent::UpdateField { .. } => "ApiEvent::UpdateField",
+                ApiEvent::GameFinished { .. } => "ApiEvent::GameFinished",
+                ApiEvent::Reconnecting { .. } => "ApiEvent::Reconnecting",
+                ApiEvent::Reconnected { rejoined_room: true } => {
+                    "ApiEvent::Reconnected\\n{rejoined_room=true}"
+                }
+                ApiEvent::Reconnected { .. } => "ApiEvent::Reconnected\\n{rejoined_room=false}",
+                ApiEvent::GaveUp => "ApiEvent::GaveUp",
+                ApiEvent::ProtocolError { .. } => "ApiEvent::ProtocolError",
+            },
+            Input::Space => "スペースキー",
+            Input::Escape => "Escキー",
+            Input::Offline => "Oキー",
+            Input::SelectRoom(_) => "Enterキー",
+            Input::Frame => "毎フレーム",
+            Input::CommandFailed => "コマンド失敗",
+            Input::Close => "ウィンドウを閉じる",
+        }
+    }
+}
+
+/// `transition`が`main.rs`に実行させること
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// 接続していなければサーバーに接続して、setup-uidする
+    Connect,
+    /// 1人用のゲームに切り替えて、ルームに入る
+    StartOffline,
+    FindAvailableRooms,
+    JoinRoom(RoomChoice),
+    Restart,
+    Leave,
+    Disconnect,
+    /// 自分のユーザーIDと名前を決める
+    SetUser {
+        uid: Uuid,
+        name: String,
+    },
+    /// `uid`が自分なら名前を変える
+    RenameUser {
+        uid: Uuid,
+        name: String,
+    },
+    /// ルームの一覧を更新する
+    SetRooms(Vec<RoomSummary>),
+    UpdateWorld {
+        /// ゲーム開始からのtick数
+        tick_count: i32,
+        players: Vec<Player>,
+        tagger: Tagger,
+        field: FieldMatrix,
+    },
+    ClearWorld,
+    StopReplay,
+}
+
+impl Command {
+    /// 状態遷移図に書く名前。画面の中だけで済むものは`None`
+    fn label(&self) -> Option<&'static str> {
+        match self {
+            Command::Connect => Some("connect & setup-uid"),
+            Command::StartOffline => Some("1人用のゲームを始める"),
+            Command::FindAvailableRooms => Some("find-available-room"),
+            Command::JoinRoom(_) => Some("join-room"),
+            Command::Restart => Some("request-after-game(restart)"),
+            Command::Leave => Some("request-after-game(leave)"),
+            Command::Disconnect => Some("disconnect"),
+            Command::SetUser { .. }
+            | Command::RenameUser { .. }
+            | Command::SetRooms(_)
+            | Command::UpdateWorld { .. }
+            | Command::ClearWorld
+            | Command::StopReplay => None,
+        }
+    }
+}
+
+/// `state`で`input`を受け取ったときの次の状態と、実行すべきコマンド
+pub fn transition(state: ClientState, input: Input) -> (ClientState, Vec<Command>) {
+    use ClientState::*;
+
+    match (state, input) {
+        (Quit, _) => (Quit, Vec::new()),
+        (_, Input::Close) => (Quit, vec![Command::Disconnect]),
+        (state, Input::Event(event)) => on_event(state, event),
+
+        (TitleScreen, Input::Space) => (WaitingSettingUid, vec![Command::Connect]),
+        (TitleScreen, Input::Offline) => (WaitingInRoom, vec![Command::StartOffline]),
+
+        (BrowsingRooms, Input::SelectRoom(choice)) => (WaitingInRoom, vec![Command::JoinRoom(choice)]),
+        (BrowsingRooms, Input::Escape) => (TitleScreen, vec![Command::Disconnect]),
+
+        (GameFinished { .. }, Input::Space) => {
+            (WaitingInRoom, vec![Command::ClearWorld, Command::Restart])
+        }
+        (GameFinished { .. }, Input::Escape) => (
+            LeavingRoom { waited_frames: 0 },
+            vec![Command::ClearWorld, Command::Leave],
+        ),
+
+        (LeavingRoom { waited_frames }, Input::Frame) => {
+            // サーバーから返事がなくてもタイトル画面に戻る
+            if waited_frames + 1 > LEAVE_TIMEOUT_FRAMES {
+                warn!("ルームから出た返事がありません。タイトル画面に戻ります。");
+                (TitleScreen, Vec::new())
+            } else {
+                (
+                    LeavingRoom {
+                        waited_frames: waited_frames + 1,
+                    },
+                    Vec::new(),
+                )
+            }
+        }
+
+        (ProtocolError, Input::Space) => (TitleScreen, vec![Command::Disconnect]),
+
+        (Replaying { .. }, Input::Escape) => {
+            (TitleScreen, vec![Command::StopReplay, Command::ClearWorld])
+        }
+
+        (WaitingSettingUid, Input::CommandFailed)
+        | (BrowsingRooms, Input::CommandFailed)
+        | (WaitingInRoom, Input::CommandFailed) => {
+            warn!("タイトル画面に戻ります。");
+            (TitleScreen, Vec::new())
+        }
+        (LeavingRoom { .. }, Input::CommandFailed) => {
+            warn!("ルームを出られませんでした。切断します。");
+            (TitleScreen, vec![Command::Disconnect])
+        }
+
+        (state, _) => (state, Vec::new()),
+    }
+}
+
+fn on_event(state: ClientState, event: ApiEvent) -> (ClientState, Vec<Command>) {
+    use ClientState::*;
+
+    match (state, event) {
+        // リプレイではルームの状態は関係ない
+        (
+            state @ Replaying { .. },
+            ApiEvent::RoomStateOpening { .. }
+            | ApiEvent::RoomStateFulfilled { .. }
+            | ApiEvent::RoomStateEmpty { .. }
+            | ApiEvent::RoomStateNotJoined,
+        ) => (state, Vec::new()),
+
+        // リプレイは記録した人の視点で再生する
+        (state @ Replaying { .. }, ApiEvent::UpdateUser { uid, name }) => {
+            (state, vec![Command::SetUser { uid, name }])
+        }
+        (WaitingSettingUid, ApiEvent::UpdateUser { uid, name }) => (
+            BrowsingRooms,
+            vec![Command::SetUser { uid, name }, Command::FindAvailableRooms],
+        ),
+        // 再接続後にsetup-uidをやり直したときにも送られてくる
+        (state, ApiEvent::UpdateUser { uid, name }) => (state, vec![Command::RenameUser { uid, name }]),
+
+        (state, ApiEvent::RoomStateOpening { room_id, room_name }) => {
+            info!("room opening id: {}, name: {}", room_id, room_name);
+            (state, Vec::new())
+        }
+
+        (
+            WaitingInRoom,
+            ApiEvent::RoomStateFulfilled {
+                room_id,
+                room_name,
+                should_start,
+            },
+        ) => {
+            info!("room fulfilled id: {}, name: {}", room_id, room_name);
+            if should_start {
+                info!("starting game");
+                (Playing, Vec::new())
+            } else {
+                info!("not starting game");
+                (WaitingInRoom, Vec::new())
+            }
+        }
+        // 相手が先に再戦を希望した場合
+        (state @ GameFinished { .. }, ApiEvent::RoomStateFulfilled { .. }) => (state, Vec::new()),
+        (
+            state,
+            ApiEvent::RoomStateFulfilled {
+                room_id, room_name, ..
+            },
+        ) => {
+            warn!(
+                "unexpected event ApiEvent::RoomStateFulfilled room_id: {}, room_name: {}. state: {:?}",
+                room_id, room_name, state
+            );
+            (state, Vec::new())
+        }
+
+        (state, ApiEvent::RoomStateEmpty { .. }) => {
+            warn!("room-stateイベントによるとルームはEmptyです");
+            (state, Vec::new())
+        }
+
+        (LeavingRoom { .. }, ApiEvent::RoomStateNotJoined) => {
+            info!("left room");
+            (TitleScreen, Vec::new())
+        }
+        // 退出の返事がタイムアウトの後に届いた場合
+        (state @ TitleScreen, ApiEvent::RoomStateNotJoined)
+        | (state @ BrowsingRooms, ApiEvent::RoomStateNotJoined) => {
+            debug!("ignored ApiEvent::RoomStateNotJoined. state: {:?}", state);
+            (state, Vec::new())
+        }
+        (Playing, ApiEvent::RoomStateNotJoined)
+        | (GameFinished { .. }, ApiEvent::RoomStateNotJoined)
+        | (WaitingInRoom, ApiEvent::RoomStateNotJoined) => (TitleScreen, Vec::new()),
+        (state, ApiEvent::RoomStateNotJoined) => {
+            warn!(
+                "unexpected event ApiEvent::RoomStateNotJoined. state: {:?}",
+                state
+            );
+            (TitleScreen, Vec::new())
+        }
+
+        (BrowsingRooms, ApiEvent::AvailableRooms { rooms }) => {
+            debug!("{} rooms available", rooms.len());
+            (BrowsingRooms, vec![Command::SetRooms(rooms)])
+        }
+        (state, ApiEvent::AvailableRooms { .. }) => {
+            debug!("ignored available rooms. state: {:?}", state);
+            (state, Vec::new())
+        }
+
+        (
+            state @ WaitingInRoom,
+            ApiEvent::UpdateField {
+                tick_count,
+                players,
+                tagger,
+                field,
+            },
+        )
+        | (
+            state @ Playing,
+            ApiEvent::UpdateField {
+                tick_count,
+                players,
+                tagger,
+                field,
+            },
+        ) => {
+            if state == WaitingInRoom {
+                // 再接続してルームに入り直したら、ゲームがすでに進んでいた場合
+                info!("resuming game");
+            }
+            (
+                Playing,
+                vec![Command::UpdateWorld {
+                    tick_count,
+                    players,
+                    tagger,
+                    field,
+                }],
+            )
+        }
+        // 次のゲームに進んだか、ゲーム中に巻き戻した
+        (
+            Replaying { .. },
+            ApiEvent::UpdateField {
+                tick_count,
+                players,
+                tagger,
+                field,
+            },
+        ) => (
+            Replaying { finished: None },
+            vec![Command::UpdateWorld {
+                tick_count,
+                players,
+                tagger,
+                field,
+            }],
+        ),
+        (state, ApiEvent::UpdateField { .. }) => {
+            warn!("unexpected event ApiEvent::UpdateField. state: {:?}", state);
+            (state, Vec::new())
+        }
+
+        (Playing, ApiEvent::GameFinished { reason }) => (GameFinished { reason }, Vec::new()),
+        (Replaying { .. }, ApiEvent::GameFinished { reason }) => (
+            Replaying {
+                finished: Some(reason),
+            },
+            Vec::new(),
+        ),
+        (state, ApiEvent::GameFinished { reason }) => {
+            warn!(
+                "unexpected event ApiEvent::GameFinished reason: {:?}. state: {:?}",
+                reason, state
+            );
+            (state, Vec::new())
+        }
+
+        (
+            state,
+            ApiEvent::Reconnecting {
+                attempt,
+                max_attempts,
+            },
+        ) => {
+            info!("reconnecting {}/{}. state: {:?}", attempt, max_attempts, state);
+            (Reconnecting, Vec::new())
+        }
+
+        (Reconnecting, ApiEvent::Reconnected { rejoined_room }) => {
+            info!("reconnected. rejoined_room: {}", rejoined_room);
+            if rejoined_room {
+                (WaitingInRoom, Vec::new())
+            } else {
+                (WaitingSettingUid, Vec::new())
+            }
+        }
+        (state, ApiEvent::Reconnected { .. }) => {
+            warn!("unexpected event ApiEvent::Reconnected. state: {:?}", state);
+            (state, Vec::new())
+        }
+
+        (state, ApiEvent::ProtocolError { event, raw, error }) => {
+            error!(
+                "サーバーから受け取った{}イベントを解釈できません: {}\n{}",
+                event, error, raw
+            );
+            // 待っているイベントが解釈できなかった場合は先に進めないのでエラー画面を出す。
+            // ゲーム中のupdate-fieldは次のtickで回復するので無視する
+            match state {
+                WaitingSettingUid | WaitingInRoom => (ProtocolError, Vec::new()),
+                state => (state, Vec::new()),
+            }
+        }
+
+        (_, ApiEvent::GaveUp) => {
+            error!("サーバーに再接続できませんでした。タイトル画面に戻ります。");
+            (TitleScreen, Vec::new())
+        }
+    }
+}
+
+/// 状態遷移表の1行
+pub struct Edge {
+    /// 遷移元。空なら`Quit`以外のすべての状態
+    pub from: &'static [StateKind],
+    /// `Input::label`
+    pub input: &'static str,
+    pub to: StateKind,
+    /// 遷移する条件など
+    pub note: &'static str,
+}
+
+const ANY: &[StateKind] = &[];
+
+/// 状態遷移表
+///
+/// ここにない組み合わせの入力では状態の種類は変わらない。`Quit`からはどこにも遷移しない。
+/// 表と`transition`が一致していることはテストで確かめる。
+pub const TRANSITIONS: &[Edge] = &[
+    Edge {
+        from: &[StateKind::TitleScreen],
+        input: "スペースキー",
+        to: StateKind::WaitingSettingUid,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::TitleScreen],
+        input: "Oキー",
+        to: StateKind::WaitingInRoom,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::WaitingSettingUid],
+        input: "ApiEvent::UpdateUser",
+        to: StateKind::BrowsingRooms,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::BrowsingRooms],
+        input: "ApiEvent::AvailableRooms",
+        to: StateKind::BrowsingRooms,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::BrowsingRooms],
+        input: "Enterキー",
+        to: StateKind::WaitingInRoom,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::BrowsingRooms],
+        input: "Escキー",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: &[
+            StateKind::WaitingSettingUid,
+            StateKind::BrowsingRooms,
+            StateKind::WaitingInRoom,
+        ],
+        input: "コマンド失敗",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::WaitingInRoom],
+        input: "ApiEvent::RoomStateFulfilled\\n{should_start=true}",
+        to: StateKind::Playing,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::WaitingInRoom],
+        input: "ApiEvent::UpdateField",
+        to: StateKind::Playing,
+        note: "再接続後のゲーム復帰",
+    },
+    Edge {
+        from: &[StateKind::Playing],
+        input: "ApiEvent::UpdateField",
+        to: StateKind::Playing,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::Playing],
+        input: "ApiEvent::GameFinished",
+        to: StateKind::GameFinished,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::GameFinished],
+        input: "スペースキー",
+        to: StateKind::WaitingInRoom,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::GameFinished],
+        input: "ApiEvent::RoomStateFulfilled\\n{should_start=true}",
+        to: StateKind::GameFinished,
+        note: "相手が先に再戦を希望",
+    },
+    Edge {
+        from: &[StateKind::GameFinished],
+        input: "Escキー",
+        to: StateKind::LeavingRoom,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::LeavingRoom],
+        input: "ApiEvent::\\nRoomStateNotJoined",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::LeavingRoom],
+        input: "毎フレーム",
+        to: StateKind::TitleScreen,
+        note: "タイムアウト",
+    },
+    Edge {
+        from: &[StateKind::LeavingRoom],
+        input: "コマンド失敗",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: &[
+            StateKind::WaitingSettingUid,
+            StateKind::WaitingInRoom,
+            StateKind::Playing,
+            StateKind::GameFinished,
+            StateKind::Reconnecting,
+            StateKind::ProtocolError,
+        ],
+        input: "ApiEvent::\\nRoomStateNotJoined",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::WaitingSettingUid, StateKind::WaitingInRoom],
+        input: "ApiEvent::ProtocolError",
+        to: StateKind::ProtocolError,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::ProtocolError],
+        input: "スペースキー",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: ANY,
+        input: "ApiEvent::Reconnecting",
+        to: StateKind::Reconnecting,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::Reconnecting],
+        input: "ApiEvent::Reconnected\\n{rejoined_room=true}",
+        to: StateKind::WaitingInRoom,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::Reconnecting],
+        input: "ApiEvent::Reconnected\\n{rejoined_room=false}",
+        to: StateKind::WaitingSettingUid,
+        note: "",
+    },
+    Edge {
+        from: ANY,
+        input: "ApiEvent::GaveUp",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::Replaying],
+        input: "ApiEvent::UpdateField",
+        to: StateKind::Replaying,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::Replaying],
+        input: "ApiEvent::GameFinished",
+        to: StateKind::Replaying,
+        note: "",
+    },
+    Edge {
+        from: &[StateKind::Replaying],
+        input: "Escキー",
+        to: StateKind::TitleScreen,
+        note: "",
+    },
+    Edge {
+        from: ANY,
+        input: "ウィンドウを閉じる",
+        to: StateKind::Quit,
+        note: "",
+    },
+];
+
+impl Edge {
+    /// `kind`からの遷移かどうか
+    pub fn is_from(&self, kind: StateKind) -> bool {
+        if self.from.is_empty() {
+            kind != StateKind::Quit
+        } else {
+            self.from.contains(&kind)
+        }
+    }
+}
+
+/// `Input`の種類ごとの例
+fn sample_inputs() -> Vec<Input> {
+    let uid = Uuid::nil();
+    let room_id = || "room".to_string();
+    vec![
+        Input::Event(ApiEvent::UpdateUser {
+            uid,
+            name: "user".to_string(),
+        }),
+        Input::Event(ApiEvent::RoomStateOpening {
+            room_id: room_id(),
+            room_name: room_id(),
+        }),
+        Input::Event(ApiEvent::RoomStateFulfilled {
+            room_id: room_id(),
+            room_name: room_id(),
+            should_start: true,
+        }),
+        Input::Event(ApiEvent::RoomStateFulfilled {
+            room_id: room_id(),
+            room_name: room_id(),
+            should_start: false,
+        }),
+        Input::Event(ApiEvent::RoomStateEmpty {
+            room_id: room_id(),
+            room_name: room_id(),
+        }),
+        Input::Event(ApiEvent::RoomStateNotJoined),
+        Input::Event(ApiEvent::AvailableRooms { rooms: Vec::new() }),
+        Input::Event(ApiEvent::UpdateField {
+            tick_count: 1,
+            players: vec![Player::new(Point2i::new(0, 0), uid, "user".to_string())],
+            tagger: Tagger::new(Point2i::new(1, 1)),
+            field: FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+        }),
+        Input::Event(ApiEvent::GameFinished {
+            reason: GameFinishReason::Timeup,
+        }),
+        Input::Event(ApiEvent::Reconnecting {
+            attempt: 1,
+            max_attempts: 6,
+        }),
+        Input::Event(ApiEvent::Reconnected { rejoined_room: true }),
+        Input::Event(ApiEvent::Reconnected { rejoined_room: false }),
+        Input::Event(ApiEvent::GaveUp),
+        Input::Event(ApiEvent::ProtocolError {
+            event: "update-field",
+            raw: "{}".to_string(),
+            error: DecodeError::MissingField("tagger"),
+        }),
+        Input::Space,
+        Input::Escape,
+        Input::Offline,
+        Input::SelectRoom(RoomChoice::Any),
+        Input::Frame,
+        Input::CommandFailed,
+        Input::Close,
+    ]
+}
+
+/// 状態遷移表をGraphvizのDOT形式にする
+pub fn to_dot() -> String {
+    let mut dot = String::new();
+    dot.push_str(concat!(
+        "// src/client_state.rsの状態遷移表から生成した。手で編集しないこと\n",
+        "digraph ClientState {\n",
+        "  graph [\n",
+        "    charset = \"UTF-8\"\n",
+        "  ];\n",
+        "  label = \"ClientState 遷移図\"\n",
+        "  labelloc = t\n",
+        "  labeljust = c\n",
+        "  rankdir = TB\n",
+        "  layout = dot\n",
+        "\n",
+        "  node [\n",
+        "    fontname = \"HackGen, Consolas, monospace\"\n",
+        "    shape = ellipse\n",
+        "    style = filled\n",
+        "    fillcolor = \"#cfecff\"\n",
+        "  ];\n",
+        "  edge [];\n",
+        "\n",
+    ));
+
+    for kind in StateKind::ALL.iter() {
+        let mut attributes = vec![format!("label = \"{}\"", kind.label())];
+        match kind {
+            StateKind::TitleScreen => {
+                attributes.push("fontcolor = white".to_string());
+                attributes.push("fillcolor = \"#17a6ff\"".to_string());
+            }
+            StateKind::Quit => {
+                attributes.push("shape = doublecircle".to_string());
+                attributes.push("fontcolor = white".to_string());
+                attributes.push("fillcolor = \"#17a6ff\"".to_string());
+            }
+            _ => {}
+        }
+        writeln!(dot, "  {:?} [ {} ];", kind, attributes.join(", ")).unwrap();
+    }
+    dot.push_str("  any [ label = \"*\" ];\n");
+
+    for edge in TRANSITIONS {
+        let mut label = edge.input.to_string();
+        if !edge.note.is_empty() {
+            write!(label, "\\n({})", edge.note).unwrap();
+        }
+        // コマンドは遷移元の例で実際に遷移させて調べる
+        let from = edge.from.first().copied().unwrap_or(StateKind::TitleScreen);
+        let input = sample_inputs()
+            .into_iter()
+            .find(|input| input.label() == edge.input);
+        if let Some(input) = input {
+            let (_, commands) = transition(from.sample(), input);
+            for command in commands.iter().filter_map(Command::label) {
+                write!(label, "\\n& {}", command).unwrap();
+            }
+        }
+
+        let from_names: Vec<String> = if edge.from.is_empty() {
+            vec!["any".to_string()]
+        } else {
+            edge.from.iter().map(|kind| format!("{:?}", kind)).collect()
+        };
+        for from_name in from_names {
+            writeln!(dot).unwrap();
+            writeln!(dot, "  {} -> {:?} [", from_name, edge.to).unwrap();
+            writeln!(dot, "    label = \"{}\"", label).unwrap();
+            writeln!(dot, "  ];").unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions_match_table() {
+        for kind in StateKind::ALL.iter().copied() {
+            for input in sample_inputs() {
+                let label = input.label();
+                let (next, _) = transition(kind.sample(), input);
+                let expected = TRANSITIONS
+                    .iter()
+                    .find(|edge| edge.is_from(kind) && edge.input == label)
+                    .map_or(kind, |edge| edge.to);
+                assert_eq!(next.kind(), expected, "{:?} --{}--> {:?}", kind, label, next);
+            }
+        }
+    }
+
+    #[test]
+    fn every_edge_is_reachable() {
+        let labels: Vec<&str> = sample_inputs().iter().map(Input::label).collect();
+        for edge in TRANSITIONS {
+            assert!(labels.contains(&edge.input), "unknown input {}", edge.input);
+            let duplicated = TRANSITIONS.iter().filter(|other| {
+                other.input == edge.input
+                    && StateKind::ALL
+                        .iter()
+                        .any(|&kind| edge.is_from(kind) && other.is_from(kind))
+            });
+            assert_eq!(duplicated.count(), 1, "duplicated edge {}", edge.input);
+        }
+    }
+
+    #[test]
+    fn unexpected_events_do_nothing() {
+        let unexpected = vec![
+            (
+                ClientState::TitleScreen,
+                Input::Event(ApiEvent::RoomStateFulfilled {
+                    room_id: "room".to_string(),
+                    room_name: "room".to_string(),
+                    should_start: true,
+                }),
+            ),
+            (
+                ClientState::TitleScreen,
+                Input::Event(ApiEvent::GameFinished {
+                    reason: GameFinishReason::Timeup,
+                }),
+            ),
+            (
+                ClientState::WaitingSettingUid,
+                Input::Event(ApiEvent::Reconnected { rejoined_room: true }),
+            ),
+            (
+                ClientState::GameFinished {
+                    reason: GameFinishReason::Abnromal,
+                },
+                Input::Event(ApiEvent::UpdateField {
+                    tick_count: 1,
+                    players: Vec::new(),
+                    tagger: Tagger::new(Point2i::new(0, 0)),
+                    field: FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+                }),
+            ),
+            (
+                ClientState::Playing,
+                Input::Event(ApiEvent::AvailableRooms { rooms: Vec::new() }),
+            ),
+            (
+                ClientState::Reconnecting,
+                Input::Event(ApiEvent::RoomStateEmpty {
+                    room_id: "room".to_string(),
+                    room_name: "room".to_string(),
+                }),
+            ),
+            (ClientState::Playing, Input::Escape),
+            (ClientState::WaitingInRoom, Input::Space),
+            (ClientState::Quit, Input::Event(ApiEvent::GaveUp)),
+        ];
+        for (state, input) in unexpected {
+            let expected = format!("{:?}", state);
+            let (next, commands) = transition(state, input);
+            assert_eq!(format!("{:?}", next), expected);
+            assert_eq!(commands, Vec::new());
+        }
+    }
+
+    #[test]
+    fn join_and_play() {
+        let uid = Uuid::new_v4();
+        let (state, commands) = transition(ClientState::TitleScreen, Input::Space);
+        assert_eq!(commands, vec![Command::Connect]);
+
+        let (state, commands) = transition(
+            state,
+            Input::Event(ApiEvent::UpdateUser {
+                uid,
+                name: "me".to_string(),
+            }),
+        );
+        assert_eq!(state, ClientState::BrowsingRooms);
+        assert_eq!(
+            commands,
+            vec![
+                Command::SetUser {
+                    uid,
+                    name: "me".to_string()
+                },
+                Command::FindAvailableRooms
+            ]
+        );
+
+        let choice = RoomChoice::New {
+            room_name: "room".to_string(),
+        };
+        let (state, commands) = transition(state, Input::SelectRoom(choice.clone()));
+        assert_eq!(state, ClientState::WaitingInRoom);
+        assert_eq!(commands, vec![Command::JoinRoom(choice)]);
+
+        let (state, commands) = transition(
+            state,
+            Input::Event(ApiEvent::UpdateField {
+                tick_count: 3,
+                players: Vec::new(),
+                tagger: Tagger::new(Point2i::new(2, 2)),
+                field: FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            }),
+        );
+        assert_eq!(state, ClientState::Playing);
+        assert!(matches!(
+            commands.as_slice(),
+            [Command::UpdateWorld { tagger, .. }] if tagger.pos == Point2i::new(2, 2)
+        ));
+    }
+
+    #[test]
+    fn leave_times_out() {
+        let mut state = ClientState::GameFinished {
+            reason: GameFinishReason::Timeup,
+        };
+        let (next, commands) = transition(state, Input::Escape);
+        assert_eq!(commands, vec![Command::ClearWorld, Command::Leave]);
+        state = next;
+        for _ in 0..LEAVE_TIMEOUT_FRAMES {
+            state = transition(state, Input::Frame).0;
+            assert_eq!(state.kind(), StateKind::LeavingRoom);
+        }
+        assert_eq!(transition(state, Input::Frame).0, ClientState::TitleScreen);
+    }
+
+    #[test]
+    fn failed_leave_disconnects() {
+        let (state, commands) = transition(
+            ClientState::LeavingRoom { waited_frames: 0 },
+            Input::CommandFailed,
+        );
+        assert_eq!(state, ClientState::TitleScreen);
+        assert_eq!(commands, vec![Command::Disconnect]);
+    }
+
+    #[test]
+    fn replay_keeps_result_until_next_game() {
+        let state = ClientState::Replaying { finished: None };
+        let (state, _) = transition(
+            state,
+            Input::Event(ApiEvent::GameFinished {
+                reason: GameFinishReason::Timeup,
+            }),
+        );
+        assert_eq!(
+            state,
+            ClientState::Replaying {
+                finished: Some(GameFinishReason::Timeup)
+            }
+        );
+        let (state, _) = transition(state, Input::Event(ApiEvent::RoomStateNotJoined));
+        assert_eq!(state.kind(), StateKind::Replaying);
+        let (state, commands) = transition(
+            state,
+            Input::Event(ApiEvent::UpdateField {
+                tick_count: 1,
+                players: Vec::new(),
+                tagger: Tagger::new(Point2i::new(0, 0)),
+                field: FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            }),
+        );
+        assert_eq!(state, ClientState::Replaying { finished: None });
+        assert_eq!(commands.len(), 1);
+    }
+
+    /// `BLKIO_UPDATE_DOC=1 cargo test`で`doc/ClientState.dot`を書き直す
+    #[test]
+    fn dot_is_up_to_date() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/doc/ClientState.dot");
+        let current = std::fs::read_to_string(path).unwrap();
+        let dot = to_dot();
+        if std::env::var("BLKIO_UPDATE_DOC").map_or(false, |value| value == "1") {
+            let dot = if current.contains("\r\n") {
+                dot.replace('\n', "\r\n")
+            } else {
+                dot
+            };
+            std::fs::write(path, dot).unwrap();
+            return;
+        }
+        assert!(
+            current.replace("\r\n", "\n") == dot,
+            "doc/ClientState.dotが古いです。BLKIO_UPDATE_DOC=1 cargo testで更新してください"
+        );
+    }
+}
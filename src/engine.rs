@@ -1,15 +1,21 @@
-use sdl2::video::{GLContext, Window};
-use sdl2::{EventPump, Sdl, TimerSubsystem, VideoSubsystem};
+use std::collections::HashMap;
+
+use sdl2::controller::GameController;
+use sdl2::video::{FullscreenType, GLContext, Window};
+use sdl2::{EventPump, GameControllerSubsystem, Sdl, TimerSubsystem, VideoSubsystem};
 
 use re::gl::Gl;
 use re::texture::image_manager::ImageManager;
 
-use crate::types::*;
+use blocking_io_client::types::*;
 
 pub struct Engine {
     _sdl: Sdl,
     _video_subsystem: VideoSubsystem,
     _timer_subsystem: TimerSubsystem,
+    game_controller_subsystem: GameControllerSubsystem,
+    /// つながっているゲームパッド。キーはinstance id
+    controllers: HashMap<u32, GameController>,
     window: Window,
     _gl_context: GLContext, /* GLContextを誰かが所有していないとOpenGLを使えない */
     gl: Gl,
@@ -28,6 +34,9 @@ impl Engine {
         info!("init SDL2 Video Subsystem");
         let timer_subsystem = sdl.timer().unwrap_or_log();
         info!("init SDL2 Timer Subsystem");
+        // つながっているゲームパッドは、起動直後にControllerDeviceAddedイベントで届く
+        let game_controller_subsystem = sdl.game_controller().unwrap_or_log();
+        info!("init SDL2 GameController Subsystem");
 
         {
             let gl_attr = video_subsystem.gl_attr();
@@ -59,6 +68,8 @@ impl Engine {
             _sdl: sdl,
             _video_subsystem: video_subsystem,
             _timer_subsystem: timer_subsystem,
+            game_controller_subsystem,
+            controllers: HashMap::new(),
             window,
             _gl_context,
             gl,
@@ -75,7 +86,32 @@ impl Engine {
         &self.gl
     }
 
-    pub fn event_pump(&self) -> &EventPump {
-        &self.event_pump
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let fullscreen_type = if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        if let Err(error) = self.window.set_fullscreen(fullscreen_type) {
+            tracing::warn!("全画面を切り替えられません: {}", error);
+        }
+    }
+
+    /// ゲームパッドがつながった。`device_index`はControllerDeviceAddedイベントの`which`
+    pub fn open_controller(&mut self, device_index: u32) {
+        match self.game_controller_subsystem.open(device_index) {
+            Ok(controller) => {
+                tracing::info!("controller connected: {}", controller.name());
+                self.controllers.insert(controller.instance_id(), controller);
+            }
+            Err(error) => tracing::warn!("ゲームパッドを開けません: {}", error),
+        }
+    }
+
+    /// ゲームパッドが外れた。`instance_id`はControllerDeviceRemovedイベントの`which`
+    pub fn close_controller(&mut self, instance_id: u32) {
+        if let Some(controller) = self.controllers.remove(&instance_id) {
+            tracing::info!("controller disconnected: {}", controller.name());
+        }
     }
 }
@@ -0,0 +1,153 @@
+//! 文字の描画に使うフォントを集める
+//!
+//! ラテン文字は同梱のDejaVu Sansで、日本語は同梱のUnifontのサブセットで描く。
+//! Unifontはドット文字なので、実行ファイルの隣の`fonts`フォルダーやOSに日本語のフォントがあれば、そちらを優先する。
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use re::text::FontSet;
+use tracing::{info, warn};
+
+use crate::types::*;
+
+/// 同梱の日本語のフォント。ASCII、Latin-1、JIS X 0208の文字を持つ
+///
+/// ライセンスは`rsc_included/fonts/LICENSE-Unifont.txt`。作り方は`rsc_included/fonts/unifont_subset.py`
+pub const BUNDLED_FONT: &[u8] = include_bytes!("../rsc_included/fonts/UnifontJP.ttf");
+
+/// 同梱のラテン文字のフォント。ライセンスは`rsc_included/fonts/LICENSE-DejaVu.txt`
+pub const LATIN_FONT: &[u8] = include_bytes!("../rsc_included/fonts/DejaVuSans.ttf");
+
+/// OSに入っている日本語のフォント。最初に見つかったものだけを使う
+const SYSTEM_FONTS: &[&str] = if cfg!(target_os = "windows") {
+    &[
+        "C:\\Windows\\Fonts\\meiryo.ttc",
+        "C:\\Windows\\Fonts\\YuGothM.ttc",
+        "C:\\Windows\\Fonts\\msgothic.ttc",
+    ]
+} else if cfg!(target_os = "macos") {
+    &[
+        "/System/Library/Fonts/ヒラギノ角ゴシック W4.ttc",
+        "/System/Library/Fonts/Hiragino Sans GB.ttc",
+    ]
+} else {
+    &[
+        "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+        "/usr/share/fonts/truetype/takao-gothic/TakaoPGothic.ttf",
+        "/usr/share/fonts/truetype/fonts-japanese-gothic.ttf",
+    ]
+};
+
+/// `fonts`フォルダーのフォント、OSの日本語のフォント、同梱のラテン文字のフォント、同梱の日本語のフォントの順に優先する
+pub fn load_fonts() -> FontSet {
+    let mut fonts = FontSet::new();
+    for path in user_fonts() {
+        add_file(&mut fonts, &path);
+    }
+    if let Some(path) = SYSTEM_FONTS.iter().map(Path::new).find(|path| path.is_file()) {
+        add_file(&mut fonts, path);
+    }
+    for font in [LATIN_FONT, BUNDLED_FONT] {
+        fonts
+            .add(font.to_vec(), 0)
+            .expect_or_log("同梱のフォントの読み込みに失敗");
+    }
+    fonts
+}
+
+/// 実行ファイルの隣の`fonts`フォルダーにあるフォント。名前の順
+fn user_fonts() -> Vec<PathBuf> {
+    let dir = match env::current_exe() {
+        Ok(exe) => exe.with_file_name("fonts"),
+        Err(_) => return Vec::new(),
+    };
+    let mut paths: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                ["ttf", "otf", "ttc"].contains(&extension.to_lowercase().as_str())
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+fn add_file(fonts: &mut FontSet, path: &Path) {
+    let result = fs::read(path)
+        .map_err(|error| error.to_string())
+        .and_then(|data| fonts.add(data, 0).map_err(|error| error.to_string()));
+    match result {
+        Ok(()) => info!("font: {}", path.display()),
+        Err(error) => warn!("フォントを読み込めません: {} {}", path.display(), error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use re::text::GlyphAtlas;
+
+    fn atlas() -> GlyphAtlas {
+        let mut fonts = FontSet::new();
+        fonts.add(BUNDLED_FONT.to_vec(), 0).unwrap();
+        GlyphAtlas::new(fonts, 32.0, 128, 128)
+    }
+
+    #[test]
+    fn bundled_font_glyphs() {
+        let mut atlas = atlas();
+        let a = atlas.glyph('A');
+        assert!(a.uv.is_some());
+        assert!(a.top < 0.0 && a.advance > 0.0);
+        assert_eq!(atlas.glyph('A'), a);
+        assert!(atlas.glyph(' ').uv.is_none());
+        assert_eq!(atlas.text_width("AA"), a.advance * 2.0);
+    }
+
+    #[test]
+    fn bundled_font_draws_japanese() {
+        let mut fonts = FontSet::new();
+        fonts.add(BUNDLED_FONT.to_vec(), 0).unwrap();
+        assert!("名無しユーザー、。↑↓".chars().all(|c| fonts.has_glyph(c)));
+        let mut atlas = GlyphAtlas::new(fonts, 32.0, 128, 128);
+        let name = atlas.glyph('名');
+        assert!(name.uv.is_some());
+        assert!(name.advance > atlas.glyph('A').advance);
+    }
+
+    #[test]
+    fn measure_lines() {
+        let mut atlas = atlas();
+        let (width, height) = atlas.measure("AA\nA");
+        assert_eq!(width, atlas.text_width("AA"));
+        assert_eq!(height, atlas.line_height() * 2.0);
+        assert_eq!(atlas.measure("").0, 0.0);
+    }
+
+    #[test]
+    fn full_atlas_repacks_at_next_frame() {
+        let mut atlas = atlas();
+        // 128x128には32pxの文字が全部は入らない。入らない文字はそのフレームでは描かない
+        let glyphs: Vec<_> = ('A'..='z').map(|c| (c, atlas.glyph(c))).collect();
+        let missing = glyphs.iter().find(|(_, glyph)| glyph.uv.is_none()).unwrap().0;
+        // フレームの途中では、すでに詰めた文字の位置は変わらない
+        for (c, glyph) in glyphs.iter().filter(|(_, glyph)| glyph.uv.is_some()) {
+            assert_eq!(atlas.glyph(*c), *glyph);
+        }
+
+        // 前のフレームで使った文字だけを詰め直す
+        atlas.begin_frame();
+        atlas.glyph('A');
+        atlas.glyph(missing);
+        atlas.begin_frame();
+        assert!(atlas.glyph('A').uv.is_some());
+        assert!(atlas.glyph(missing).uv.is_some());
+    }
+}
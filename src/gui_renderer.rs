@@ -1,85 +1,110 @@
 use re::gl::Gl;
 use re::gui::layout::{Origin, Position, Rect};
-use re::shader::UniformVariables;
+use re::shader::{Program, UniformVariables};
+use re::text::{FontSet, GlyphAtlas};
 use re::texture::dynamic_texture_atlas::DynamicTextureUV;
 use re::texture::image_manager::ImageLoadInfo;
-use re::vao::{VaoBuffer, VaoConfig};
+use re::vao::{VaoBuffer, VaoConfig, VaoConfigBuilder};
 
+use uuid::Uuid;
+
+use crate::api::json::DirectionJson;
+use crate::camera::VisibleArea;
+use crate::hud::{format_time, GameClock};
+use crate::input::Action;
+use crate::keybindings::KeyBindings;
+use crate::locale::Catalog;
+use crate::minimap::Minimap;
+use crate::player::{Player, Tagger};
+use crate::room_browser::RoomBrowser;
 use crate::types::*;
 
 const TEX_TITLE_BLOCKING_IO: Rect<i32, u32> = Rect::new_const(88, 0, 190, 38);
-const TEX_スペースキーでスタート: Rect<i32, u32> = Rect::new_const(0, 0, 88, 12);
-const TEX_勝ち: Rect<i32, u32> = Rect::new_const(0, 12, 64, 12);
-const TEX_負け: Rect<i32, u32> = Rect::new_const(0, 24, 64, 12);
-const TEX_待機中: Rect<i32, u32> = Rect::new_const(0, 36, 52, 12);
-const TEX_接続中: Rect<i32, u32> = Rect::new_const(0, 48, 36, 12);
-const TEX_引き分け: Rect<i32, u32> = Rect::new_const(0, 72, 64, 12);
-const TEX_相手が落下しました: Rect<i32, u32> = Rect::new_const(0, 84, 8 * 9, 12);
-const TEX_相手がつかまりました: Rect<i32, u32> = Rect::new_const(0, 96, 8 * 10, 12);
-const TEX_落下してしまった: Rect<i32, u32> = Rect::new_const(0, 108, 8 * 8, 12);
-const TEX_つかまってしまった: Rect<i32, u32> = Rect::new_const(0, 120, 8 * 9, 12);
-const TEX_異常終了: Rect<i32, u32> = Rect::new_const(0, 132, 8 * 4, 12);
+/// 真っ黒な部分。図形を描くのに使う
+const TEX_塗りつぶし: Rect<i32, u32> = Rect::new_const(114, 9, 2, 2);
+
+/// 文字をラスタライズする大きさ。これより大きく描くと少しぼやける
+const GLYPH_PX: f32 = 32.0;
+const GLYPH_ATLAS_SIZE: u32 = 1024;
+
+const TEXT_COLOR: Vector3 = Vector3::new(0.0, 0.0, 0.0);
+/// 画面の中央に出す文字の大きさ
+const MESSAGE_SIZE: f32 = 36.0;
+/// 画面の中央の文字の下に出す文字の大きさ
+const SUB_MESSAGE_SIZE: f32 = 24.0;
+
+/// 名札の文字の大きさ
+const NAMEPLATE_SIZE: f32 = 20.0;
+const NAMEPLATE_COLOR_OWN: Vector3 = Vector3::new(0.1, 0.3, 0.8);
+const NAMEPLATE_COLOR_OPPONENT: Vector3 = Vector3::new(0.8, 0.15, 0.1);
+
+/// HUDの文字の大きさと、画面の端からの距離
+const HUD_TEXT_SIZE: f32 = 20.0;
+const HUD_MARGIN: f32 = 16.0;
+/// 脱落したプレイヤーの色
+const HUD_COLOR_DEAD: Vector3 = Vector3::new(0.5, 0.5, 0.5);
+
+/// ミニマップの画面の端からの距離。1マスの大きさは`minimap::CELL_PX`
+const MINIMAP_MARGIN: i32 = 16;
+/// ミニマップを囲む枠の太さ
+const MINIMAP_BORDER: u32 = 2;
+
+/// ルーム選択画面の1行の高さ
+const ROOM_ROW_HEIGHT: u32 = 36;
+/// ルーム選択画面で、1人分を表す四角の大きさ
+const ROOM_PIP_SIZE: u32 = 16;
+/// ルーム選択画面の文字の大きさ
+const ROOM_TEXT_SIZE: f32 = 20.0;
+/// 新しいルームの名前を入力していないときの、新しいルームを作る行の文字の色
+const ROOM_PLACEHOLDER_COLOR: Vector3 = Vector3::new(0.5, 0.5, 0.5);
 
 pub struct GuiRenderer {
     buffer: VaoBuffer,
+    /// 文字は`buffer`とは別のテクスチャとシェーダーで、`buffer`の上に描く
+    text_buffer: VaoBuffer,
+    glyph_atlas: GlyphAtlas,
+    /// ミニマップは`buffer`の上に、ミニマップのテクスチャで描く
+    minimap_buffer: VaoBuffer,
+    minimap: Minimap,
+    catalog: Catalog,
     window_width: u32,
     window_height: u32,
     tex_title: DynamicTextureUV,
-    tex_スペースキーでスタート: DynamicTextureUV,
-    tex_勝ち: DynamicTextureUV,
-    tex_負け: DynamicTextureUV,
-    tex_待機中: DynamicTextureUV,
-    tex_接続中: DynamicTextureUV,
-    tex_引き分け: DynamicTextureUV,
-    tex_相手が落下しました: DynamicTextureUV,
-    tex_相手がつかまりました: DynamicTextureUV,
-    tex_落下してしまった: DynamicTextureUV,
-    tex_つかまってしまった: DynamicTextureUV,
-    tex_異常終了: DynamicTextureUV,
+    tex_塗りつぶし: DynamicTextureUV,
 }
 
 impl GuiRenderer {
-    pub fn new(window_width: u32, window_height: u32, gui_texture: &ImageLoadInfo) -> Self {
+    pub fn new(
+        window_width: u32,
+        window_height: u32,
+        gui_texture: &ImageLoadInfo,
+        fonts: FontSet,
+        catalog: Catalog,
+    ) -> Self {
         let tex_title =
             DynamicTextureUV::new(&TEX_TITLE_BLOCKING_IO, gui_texture.width, gui_texture.height);
-        let tex_スペースキーでスタート =
-            DynamicTextureUV::new(&TEX_スペースキーでスタート, gui_texture.width, gui_texture.height);
-        let tex_勝ち = DynamicTextureUV::new(&TEX_勝ち, gui_texture.width, gui_texture.height);
-        let tex_負け = DynamicTextureUV::new(&TEX_負け, gui_texture.width, gui_texture.height);
-        let tex_待機中 = DynamicTextureUV::new(&TEX_待機中, gui_texture.width, gui_texture.height);
-        let tex_接続中 = DynamicTextureUV::new(&TEX_接続中, gui_texture.width, gui_texture.height);
-        let tex_引き分け = DynamicTextureUV::new(&TEX_引き分け, gui_texture.width, gui_texture.height);
-        let tex_相手が落下しました =
-            DynamicTextureUV::new(&TEX_相手が落下しました, gui_texture.width, gui_texture.height);
-        let tex_相手がつかまりました =
-            DynamicTextureUV::new(&TEX_相手がつかまりました, gui_texture.width, gui_texture.height);
-        let tex_落下してしまった =
-            DynamicTextureUV::new(&TEX_落下してしまった, gui_texture.width, gui_texture.height);
-        let tex_つかまってしまった =
-            DynamicTextureUV::new(&TEX_つかまってしまった, gui_texture.width, gui_texture.height);
-        let tex_異常終了 = DynamicTextureUV::new(&TEX_異常終了, gui_texture.width, gui_texture.height);
+        let tex_塗りつぶし =
+            DynamicTextureUV::new(&TEX_塗りつぶし, gui_texture.width, gui_texture.height);
 
         Self {
             buffer: VaoBuffer::new(),
+            text_buffer: VaoBuffer::new(),
+            glyph_atlas: GlyphAtlas::new(fonts, GLYPH_PX, GLYPH_ATLAS_SIZE, GLYPH_ATLAS_SIZE),
+            minimap_buffer: VaoBuffer::new(),
+            minimap: Minimap::new(),
+            catalog,
             window_width,
             window_height,
             tex_title,
-            tex_スペースキーでスタート,
-            tex_勝ち,
-            tex_負け,
-            tex_待機中,
-            tex_接続中,
-            tex_引き分け,
-            tex_相手が落下しました,
-            tex_相手がつかまりました,
-            tex_落下してしまった,
-            tex_つかまってしまった,
-            tex_異常終了,
+            tex_塗りつぶし,
         }
     }
 
     pub fn clear(&mut self) {
         self.buffer.clear();
+        self.text_buffer.clear();
+        self.minimap_buffer.clear();
+        self.glyph_atlas.begin_frame();
     }
 
     pub fn change_window_size(&mut self, width: u32, height: u32) {
@@ -87,6 +112,10 @@ impl GuiRenderer {
         self.window_height = height;
     }
 
+    pub fn window_size(&self) -> (u32, u32) {
+        (self.window_width, self.window_height)
+    }
+
     pub fn draw_title(&mut self) {
         self.buffer.add_layout_rectangle(
             &self.tex_title,
@@ -101,159 +130,306 @@ impl GuiRenderer {
         );
     }
 
-    pub fn draw_スペースキーでスタート(&mut self) {
-        self.buffer.add_layout_rectangle(
-            &self.tex_スペースキーでスタート,
+    /// `key`の文字列を`Catalog`から引いて置く。`position_x`, `position_y`は画面の中の位置
+    fn draw_layout_message(
+        &mut self,
+        key: &str,
+        origin: &Origin,
+        position_x: &Position<i32>,
+        position_y: &Position<i32>,
+        size: f32,
+    ) {
+        self.text_buffer.add_layout_text(
+            &mut self.glyph_atlas,
+            self.catalog.get(key),
             self.window_width,
             self.window_height,
+            origin,
+            position_x,
+            position_y,
+            size,
+            &TEXT_COLOR,
+        );
+    }
+
+    /// 文字を置く。`position_x`, `position_y`は画面の中の位置
+    fn draw_layout_text(
+        &mut self,
+        text: &str,
+        origin: &Origin,
+        position_x: &Position<i32>,
+        position_y: &Position<i32>,
+        size: f32,
+    ) {
+        self.text_buffer.add_layout_text(
+            &mut self.glyph_atlas,
+            text,
+            self.window_width,
+            self.window_height,
+            origin,
+            position_x,
+            position_y,
+            size,
+            &TEXT_COLOR,
+        );
+    }
+
+    pub fn draw_press_space(&mut self) {
+        // ウィンドウの幅に合わせて大きさを変える
+        let size = self.window_width as f32 * 0.05;
+        self.draw_layout_message(
+            "title.press_space",
             &Origin::Center,
             &Position::Center(0),
             &Position::Negative((self.window_height as f32 * 0.2) as i32),
-            (self.window_width as f32 * 0.4) as u32,
-            (self.window_width as f32 * 0.4) as u32 / *TEX_スペースキーでスタート.width()
-                * *TEX_スペースキーでスタート.height(),
+            size,
         );
     }
 
-    fn draw_text(
-        buffer: &mut VaoBuffer,
-        tex: &DynamicTextureUV,
-        tex_rect: &Rect<i32, u32>,
-        window_width: u32,
-        window_height: u32,
-    ) {
-        buffer.add_layout_rectangle(
-            tex,
-            window_width,
-            window_height,
+    /// 画面の中央に大きく出す文字。`key`は`rsc_included/locales`の文字列のキー
+    pub fn draw_message(&mut self, key: &str) {
+        self.draw_layout_message(
+            key,
             &Origin::Center,
             &Position::Center(0),
             &Position::Center(0),
-            tex_rect.width() * 3,
-            tex_rect.height() * 3,
+            MESSAGE_SIZE,
         );
     }
 
-    fn draw_sub_text(
-        buffer: &mut VaoBuffer,
-        tex: &DynamicTextureUV,
-        tex_rect: &Rect<i32, u32>,
-        window_width: u32,
-        window_height: u32,
-    ) {
-        buffer.add_layout_rectangle(
-            tex,
-            window_width,
-            window_height,
+    /// `draw_message`の下に出す文字
+    pub fn draw_sub_message(&mut self, key: &str) {
+        self.draw_layout_message(
+            key,
             &Origin::Bottom,
             &Position::Center(0),
             &Position::Center(-30),
-            tex_rect.width() * 2,
-            tex_rect.height() * 2,
+            SUB_MESSAGE_SIZE,
         );
     }
 
-    pub fn draw_接続中(&mut self) {
-        Self::draw_text(
-            &mut self.buffer,
-            &self.tex_接続中,
-            &TEX_接続中,
-            self.window_width,
-            self.window_height,
-        );
+    fn fill(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        self.buffer
+            .add_rectangle(&self.tex_塗りつぶし, &Rect::new(x, y, width, height));
     }
 
-    pub fn draw_待機中(&mut self) {
-        Self::draw_text(
-            &mut self.buffer,
-            &self.tex_待機中,
-            &TEX_待機中,
-            self.window_width,
-            self.window_height,
-        );
-    }
+    /// ルーム選択画面
+    ///
+    /// 1行が1つのルームで、入っている人数を塗りつぶした四角、空きを小さい四角で表し、右にルームの名前を書く。
+    /// 最後の行は新しいルームを作る行で、「+」と入力中の名前を描く。名前が空なら行の説明を薄く描く。
+    /// 選択中の行には左に印をつけ、満員のルームには線を引く。一覧の下には操作の説明を、`bindings`のキーの名前で書く。
+    pub fn draw_room_browser(&mut self, browser: &RoomBrowser, bindings: &KeyBindings) {
+        let rows = browser.rooms().len() as u32 + 1;
+        let left = (self.window_width as f32 * 0.3) as i32;
+        let row_width = (self.window_width as f32 * 0.4) as u32;
+        let top = self.window_height as i32 / 2 - (rows * ROOM_ROW_HEIGHT) as i32 / 2;
+        let pip = ROOM_PIP_SIZE as i32;
 
-    pub fn draw_勝ち(&mut self) {
-        Self::draw_text(
-            &mut self.buffer,
-            &self.tex_勝ち,
-            &TEX_勝ち,
-            self.window_width,
-            self.window_height,
+        for row in 0..rows {
+            let y = top + (row * ROOM_ROW_HEIGHT) as i32;
+            let pip_y = y + (ROOM_ROW_HEIGHT as i32 - pip) / 2;
+
+            if row as usize == browser.selected() {
+                self.fill(left - pip * 2, pip_y, ROOM_PIP_SIZE, ROOM_PIP_SIZE);
+            }
+            // 行の区切り線
+            self.fill(left, y + ROOM_ROW_HEIGHT as i32 - 2, row_width, 2);
+
+            match browser.rooms().get(row as usize) {
+                Some(room) => {
+                    for slot in 0..room.max_member as i32 {
+                        let x = left + slot * pip * 3 / 2;
+                        if (slot as usize) < room.member_count {
+                            self.fill(x, pip_y, ROOM_PIP_SIZE, ROOM_PIP_SIZE);
+                        } else {
+                            self.fill(
+                                x + pip / 4,
+                                pip_y + pip / 4,
+                                ROOM_PIP_SIZE / 2,
+                                ROOM_PIP_SIZE / 2,
+                            );
+                        }
+                    }
+                    self.draw_layout_text(
+                        &room.room_name,
+                        &Origin::Left,
+                        &Position::Positive(left + room.max_member as i32 * pip * 3 / 2 + pip),
+                        &Position::Positive(pip_y + pip / 2),
+                        ROOM_TEXT_SIZE,
+                    );
+                    if room.is_full() {
+                        self.fill(left, pip_y + pip / 2 - 1, row_width, 2);
+                    }
+                }
+                None => {
+                    self.fill(left, pip_y + pip / 2 - 2, ROOM_PIP_SIZE, 4);
+                    self.fill(left + pip / 2 - 2, pip_y, 4, ROOM_PIP_SIZE);
+                    let (text, color) = if browser.new_room_name().is_empty() {
+                        (self.catalog.get("room.new"), &ROOM_PLACEHOLDER_COLOR)
+                    } else {
+                        (browser.new_room_name(), &TEXT_COLOR)
+                    };
+                    self.text_buffer.add_layout_text(
+                        &mut self.glyph_atlas,
+                        text,
+                        self.window_width,
+                        self.window_height,
+                        &Origin::Left,
+                        &Position::Positive(left + pip * 2),
+                        &Position::Positive(pip_y + pip / 2),
+                        ROOM_TEXT_SIZE,
+                        color,
+                    );
+                }
+            }
+        }
+
+        let hint = if browser.is_creating() {
+            self.catalog.get("room.new_hint").to_string()
+        } else {
+            self.catalog.format(
+                "room.hint",
+                &[
+                    &bindings.describe(Action::Move(DirectionJson::Up)),
+                    &bindings.describe(Action::Move(DirectionJson::Down)),
+                    &bindings.describe(Action::Confirm),
+                    &bindings.describe(Action::Back),
+                ],
+            )
+        };
+        self.draw_layout_text(
+            &hint,
+            &Origin::Top,
+            &Position::Center(0),
+            &Position::Positive(top + (rows * ROOM_ROW_HEIGHT) as i32 + pip),
+            ROOM_TEXT_SIZE,
         );
     }
 
-    pub fn draw_負け(&mut self) {
-        Self::draw_text(
-            &mut self.buffer,
-            &self.tex_負け,
-            &TEX_負け,
-            self.window_width,
-            self.window_height,
+    /// プレイヤーの名前を、`x`, `y`を下端の中央にして描く。自機の名前は色を変える
+    pub fn draw_nameplate(&mut self, name: &str, x: f32, y: f32, own: bool) {
+        let scale = NAMEPLATE_SIZE / self.glyph_atlas.px();
+        let width = self.glyph_atlas.text_width(name) * scale;
+        let height = self.glyph_atlas.line_height() * scale;
+        let color = if own {
+            NAMEPLATE_COLOR_OWN
+        } else {
+            NAMEPLATE_COLOR_OPPONENT
+        };
+        self.text_buffer.add_text(
+            &mut self.glyph_atlas,
+            name,
+            x - width / 2.0,
+            y - height,
+            NAMEPLATE_SIZE,
+            &color,
         );
     }
 
-    pub fn draw_異常終了(&mut self) {
-        Self::draw_text(
-            &mut self.buffer,
-            &self.tex_異常終了,
-            &TEX_異常終了,
-            self.window_width,
-            self.window_height,
-        );
+    /// ゲーム中に左上に出す情報
+    ///
+    /// 1行目に経過時間(分からなければtick数)と残り時間、その下に各プレイヤーの名前、点数、生死を書く。
+    /// 自機の行は名札と同じ色にする。
+    pub fn draw_hud(&mut self, clock: &GameClock, players: &[Player], own_uid: Option<Uuid>) {
+        let mut lines = Vec::new();
+
+        let mut time = match clock.elapsed() {
+            Some(elapsed) => self.catalog.format("hud.elapsed", &[&format_time(elapsed)]),
+            None => self.catalog.format("hud.tick", &[&clock.tick_count()]),
+        };
+        if let Some(remaining) = clock.remaining() {
+            time += "  ";
+            time += &self.catalog.format("hud.remaining", &[&format_time(remaining)]);
+        }
+        lines.push((time, TEXT_COLOR));
+
+        for player in players {
+            let own = Some(player.uid) == own_uid;
+            let mut line = self.catalog.format("hud.player", &[&player.name, &player.point]);
+            if own {
+                line = self.catalog.format("hud.you", &[&line]);
+            }
+            if !player.alive {
+                line = self.catalog.format("hud.dead", &[&line]);
+            }
+            let color = match (player.alive, own) {
+                (false, _) => HUD_COLOR_DEAD,
+                (true, true) => NAMEPLATE_COLOR_OWN,
+                (true, false) => NAMEPLATE_COLOR_OPPONENT,
+            };
+            lines.push((line, color));
+        }
+
+        let line_height = self.glyph_atlas.line_height() * HUD_TEXT_SIZE / self.glyph_atlas.px();
+        for (i, (line, color)) in lines.iter().enumerate() {
+            self.text_buffer.add_text(
+                &mut self.glyph_atlas,
+                line,
+                HUD_MARGIN,
+                HUD_MARGIN + i as f32 * line_height,
+                HUD_TEXT_SIZE,
+                color,
+            );
+        }
     }
 
-    pub fn draw_引き分け(&mut self) {
-        Self::draw_text(
-            &mut self.buffer,
-            &self.tex_引き分け,
-            &TEX_引き分け,
-            self.window_width,
-            self.window_height,
+    /// フィールド全体の地図を右上に描く
+    ///
+    /// * `viewport` - 画面に映っている範囲。地図に枠を描く
+    pub fn draw_minimap(
+        &mut self,
+        field: &FieldMatrix,
+        players: &[Player],
+        tagger: Option<&Tagger>,
+        own_uid: Option<Uuid>,
+        viewport: Option<&VisibleArea>,
+    ) {
+        self.minimap.update(field, players, tagger, own_uid, viewport);
+
+        // 1テクセルを1ピクセルで描かないと、1ピクセルの枠や印が行ごとに消えたり太ったりする
+        let (width, height) = (self.minimap.width() as u32, self.minimap.height() as u32);
+        let x = self.window_width as i32 - MINIMAP_MARGIN - width as i32;
+        let y = MINIMAP_MARGIN;
+        let border = MINIMAP_BORDER as i32;
+        self.fill(
+            x - border,
+            y - border,
+            width + MINIMAP_BORDER * 2,
+            height + MINIMAP_BORDER * 2,
         );
-    }
 
-    pub fn draw_相手が落下しました(&mut self) {
-        Self::draw_sub_text(
-            &mut self.buffer,
-            &self.tex_相手が落下しました,
-            &TEX_相手が落下しました,
-            self.window_width,
-            self.window_height,
-        )
+        let image = Rect::new(0, 0, width, height);
+        let texture = DynamicTextureUV::new(&image, width, height);
+        self.minimap_buffer
+            .add_rectangle(&texture, &Rect::new(x, y, width, height));
     }
 
-    pub fn draw_相手がつかまりました(&mut self) {
-        Self::draw_sub_text(
-            &mut self.buffer,
-            &self.tex_相手がつかまりました,
-            &TEX_相手がつかまりました,
-            self.window_width,
-            self.window_height,
-        )
-    }
+    /// リプレイの再生位置のバー。一時停止中は左に一時停止の印を描く
+    pub fn draw_replay_bar(&mut self, progress: f32, paused: bool) {
+        let left = (self.window_width as f32 * 0.1) as i32;
+        let width = (self.window_width as f32 * 0.8) as u32;
+        let y = self.window_height as i32 - 40;
 
-    pub fn draw_落下してしまった(&mut self) {
-        Self::draw_sub_text(
-            &mut self.buffer,
-            &self.tex_落下してしまった,
-            &TEX_落下してしまった,
-            self.window_width,
-            self.window_height,
-        )
-    }
+        self.fill(left, y, width, 2);
+        let played = (width as f32 * progress.max(0.0).min(1.0)) as u32;
+        self.fill(left, y - 3, played, 8);
+        self.fill(left + played as i32 - 2, y - 8, 4, 18);
 
-    pub fn draw_つかまってしまった(&mut self) {
-        Self::draw_sub_text(
-            &mut self.buffer,
-            &self.tex_つかまってしまった,
-            &TEX_つかまってしまった,
-            self.window_width,
-            self.window_height,
-        )
+        if paused {
+            self.fill(left - 30, y - 8, 6, 18);
+            self.fill(left - 20, y - 8, 6, 18);
+        }
     }
 
-    pub fn render(&self, gl: &Gl, vao_config: &VaoConfig) {
+    /// * `gui_program` - `vao_config`と同じ、`rsc_included/shader/gui.vert`などのシェーダー
+    /// * `text_program` - `rsc_included/shader/text.vert`などの、文字を描くシェーダー
+    pub fn render(
+        &mut self,
+        gl: &Gl,
+        vao_config: &VaoConfig,
+        gui_program: &Program,
+        text_program: &Program,
+    ) {
         let vao = self.buffer.build(gl, vao_config);
         let uniforms = {
             let mut uniforms = UniformVariables::new();
@@ -264,5 +440,53 @@ impl GuiRenderer {
             uniforms
         };
         vao.draw_triangles(&uniforms);
+
+        let minimap_config = VaoConfigBuilder::new(gui_program)
+            .texture(self.minimap.texture(gl))
+            .depth_test(false)
+            .build();
+        self.minimap_buffer
+            .build(gl, &minimap_config)
+            .draw_triangles(&uniforms);
+
+        let text_config = VaoConfigBuilder::new(text_program)
+            .texture(self.glyph_atlas.texture(gl))
+            .depth_test(false)
+            .build();
+        self.text_buffer.build(gl, &text_config).draw_triangles(&uniforms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::{BUNDLED_FONT, LATIN_FONT};
+
+    /// OSのフォントを使わず、同梱のフォントだけにする
+    fn bundled_fonts() -> FontSet {
+        let mut fonts = FontSet::new();
+        for font in [LATIN_FONT, BUNDLED_FONT] {
+            fonts.add(font.to_vec(), 0).unwrap();
+        }
+        fonts
+    }
+
+    #[test]
+    fn nameplate_draws_japanese_name() {
+        let name = "名無しユーザー";
+        let fonts = bundled_fonts();
+        let missing: String = name.chars().filter(|c| !fonts.has_glyph(*c)).collect();
+        assert!(missing.is_empty(), "{}", missing);
+
+        let gui_texture = ImageLoadInfo {
+            gl_id: 0,
+            id: "gui",
+            width: 256,
+            height: 256,
+        };
+        let mut renderer = GuiRenderer::new(800, 600, &gui_texture, fonts, Catalog::new("ja"));
+        renderer.draw_nameplate(name, 400.0, 300.0, true);
+        // 1文字が2つの三角形
+        assert_eq!(renderer.text_buffer.vertex_num(), name.chars().count() * 6);
     }
 }
@@ -0,0 +1,106 @@
+//! ゲーム中に画面の隅に出す時間
+//!
+//! サーバーはtick数しか送ってこないので、tickが続けて届いた間隔から1tickの長さを見積もって時間に直す。
+//! 1tickの長さと時間切れになるtick数が分かっている1人用のゲームでは、その値を使う。
+
+use std::time::{Duration, Instant};
+
+pub struct GameClock {
+    tick_count: i32,
+    last_tick_at: Option<Instant>,
+    tick_interval: Option<Duration>,
+    /// `tick_interval`が決まっていて、見積もらなくてよいかどうか
+    fixed_interval: bool,
+    /// 時間切れになるtick数。分からなければ`None`
+    time_limit_ticks: Option<i32>,
+}
+
+impl GameClock {
+    /// * `tick_interval` - 1tickの長さ。`None`ならtickの届く間隔から見積もる
+    /// * `time_limit_ticks` - 時間切れになるtick数。`None`なら残り時間を出さない
+    pub fn new(tick_interval: Option<Duration>, time_limit_ticks: Option<i32>) -> Self {
+        Self {
+            tick_count: 0,
+            last_tick_at: None,
+            tick_interval,
+            fixed_interval: tick_interval.is_some(),
+            time_limit_ticks,
+        }
+    }
+
+    /// 次のゲームのために、受け取ったtickを忘れる。見積もった1tickの長さは残す
+    pub fn reset(&mut self) {
+        self.tick_count = 0;
+        self.last_tick_at = None;
+    }
+
+    /// `at`に`tick_count`のtickが届いた
+    pub fn on_tick(&mut self, tick_count: i32, at: Instant) {
+        // 飛ばしたり巻き戻したりしたときの間隔は使わない
+        if let (false, Some(last_at), true) = (
+            self.fixed_interval,
+            self.last_tick_at,
+            tick_count == self.tick_count + 1,
+        ) {
+            let sample = at.saturating_duration_since(last_at);
+            self.tick_interval = Some(match self.tick_interval {
+                Some(interval) => (interval * 3 + sample) / 4,
+                None => sample,
+            });
+        }
+        self.tick_count = tick_count;
+        self.last_tick_at = Some(at);
+    }
+
+    pub fn tick_count(&self) -> i32 {
+        self.tick_count
+    }
+
+    /// ゲーム開始からの時間。1tickの長さがまだ分からなければ`None`
+    pub fn elapsed(&self) -> Option<Duration> {
+        Some(self.tick_interval? * self.tick_count.max(0) as u32)
+    }
+
+    /// 時間切れまでの時間
+    pub fn remaining(&self) -> Option<Duration> {
+        let ticks = (self.time_limit_ticks? - self.tick_count).max(0);
+        Some(self.tick_interval? * ticks as u32)
+    }
+}
+
+/// `1:05`のように分と秒で書く
+pub fn format_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_interval() {
+        let mut clock = GameClock::new(Some(Duration::from_millis(200)), Some(300));
+        let start = Instant::now();
+        clock.on_tick(50, start);
+        clock.on_tick(51, start + Duration::from_secs(5));
+        assert_eq!(clock.elapsed(), Some(Duration::from_millis(51 * 200)));
+        assert_eq!(clock.remaining(), Some(Duration::from_millis(249 * 200)));
+        assert_eq!(format_time(clock.remaining().unwrap()), "0:49");
+    }
+
+    #[test]
+    fn estimated_interval() {
+        let mut clock = GameClock::new(None, None);
+        let start = Instant::now();
+        clock.on_tick(1, start);
+        assert_eq!(clock.elapsed(), None);
+        clock.on_tick(2, start + Duration::from_millis(500));
+        assert_eq!(clock.elapsed(), Some(Duration::from_secs(1)));
+        // 飛んだtickの間隔は使わない
+        clock.on_tick(10, start + Duration::from_millis(600));
+        assert_eq!(clock.elapsed(), Some(Duration::from_secs(5)));
+        assert_eq!(clock.remaining(), None);
+        assert_eq!(format_time(Duration::from_secs(65)), "1:05");
+    }
+}
@@ -0,0 +1,390 @@
+//! キーボードやゲームパッドの入力を、ゲームの操作に変える
+//!
+//! どの入力機器も、押したボタンをまず`Action`に変えてから扱う。
+//!
+//! 移動はキーを押した瞬間に1回動き、押し続けると`RepeatConfig`の間隔で繰り返す。
+//! サーバーには1tickに1回しか移動を送らないので、その間に押されたキーは
+//! 少しだけためておき、次のtickで送る。
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::api::json::DirectionJson;
+
+/// ゲームの操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// 移動。ルーム選択画面では行の選択、リプレイでは再生速度の変更とコマ送り
+    Move(DirectionJson),
+    /// スペースキーで決める操作。ゲームの開始や再戦、ルームに入る、リプレイの一時停止
+    Confirm,
+    /// Escキーで戻る操作。ルームを出る
+    Back,
+    /// タイトル画面から1人用のゲームを始める
+    Offline,
+    ToggleFullscreen,
+    /// ゲーム中の画面の隅の地図を出したり消したりする
+    ToggleMinimap,
+    /// リプレイを10秒進める
+    SeekForward,
+    /// リプレイを10秒戻す
+    SeekBackward,
+    /// リプレイを最初に戻す
+    SeekStart,
+}
+
+impl Action {
+    pub const ALL: [Action; 12] = [
+        Action::Move(DirectionJson::Up),
+        Action::Move(DirectionJson::Down),
+        Action::Move(DirectionJson::Left),
+        Action::Move(DirectionJson::Right),
+        Action::Confirm,
+        Action::Back,
+        Action::Offline,
+        Action::ToggleFullscreen,
+        Action::ToggleMinimap,
+        Action::SeekForward,
+        Action::SeekBackward,
+        Action::SeekStart,
+    ];
+
+    /// 設定ファイルの`[keybindings]`に書く名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Move(DirectionJson::Up) => "move_up",
+            Action::Move(DirectionJson::Down) => "move_down",
+            Action::Move(DirectionJson::Left) => "move_left",
+            Action::Move(DirectionJson::Right) => "move_right",
+            Action::Confirm => "confirm",
+            Action::Back => "back",
+            Action::Offline => "offline",
+            Action::ToggleFullscreen => "toggle_fullscreen",
+            Action::ToggleMinimap => "toggle_minimap",
+            Action::SeekForward => "seek_forward",
+            Action::SeekBackward => "seek_backward",
+            Action::SeekStart => "seek_start",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActionEvent {
+    Pressed(Action),
+    Released(Action),
+}
+
+/// スティックをこれより倒すと押したことにする
+const STICK_PRESS_THRESHOLD: i16 = 16_000;
+/// 押した後は、これより戻すまで離したことにしない
+const STICK_RELEASE_THRESHOLD: i16 = 8_000;
+
+/// アナログスティックの傾きを、方向ボタンを押したり離したりしたことにする
+///
+/// 斜めに倒したときは、大きく倒している方の軸だけを使う。
+#[derive(Default)]
+pub struct StickInput {
+    x: i16,
+    y: i16,
+    pressed: Option<DirectionJson>,
+}
+
+impl StickInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 横軸が動いた。右が正
+    pub fn set_x(&mut self, value: i16) -> Vec<ActionEvent> {
+        self.x = value;
+        self.update()
+    }
+
+    /// 縦軸が動いた。SDLと同じく下が正
+    pub fn set_y(&mut self, value: i16) -> Vec<ActionEvent> {
+        self.y = value;
+        self.update()
+    }
+
+    fn update(&mut self) -> Vec<ActionEvent> {
+        let (x, y) = (i32::from(self.x), i32::from(self.y));
+        let threshold = i32::from(if self.pressed.is_some() {
+            STICK_RELEASE_THRESHOLD
+        } else {
+            STICK_PRESS_THRESHOLD
+        });
+        let direction = if x.abs().max(y.abs()) <= threshold {
+            None
+        } else if x.abs() > y.abs() {
+            Some(if x > 0 {
+                DirectionJson::Right
+            } else {
+                DirectionJson::Left
+            })
+        } else {
+            Some(if y > 0 {
+                DirectionJson::Down
+            } else {
+                DirectionJson::Up
+            })
+        };
+
+        if direction == self.pressed {
+            return Vec::new();
+        }
+        let mut events = Vec::new();
+        if let Some(released) = self.pressed {
+            events.push(ActionEvent::Released(Action::Move(released)));
+        }
+        if let Some(pressed) = direction {
+            events.push(ActionEvent::Pressed(Action::Move(pressed)));
+        }
+        self.pressed = direction;
+        events
+    }
+}
+
+/// キーを押し続けたときの繰り返しの設定
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RepeatConfig {
+    /// 押してから繰り返しが始まるまでの時間
+    pub initial_delay: Duration,
+    /// 繰り返しの間隔
+    pub repeat_interval: Duration,
+    /// tickを待っている間にためておく移動の数
+    pub buffer_size: usize,
+}
+
+impl Default for RepeatConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            repeat_interval: Duration::from_millis(150),
+            buffer_size: 2,
+        }
+    }
+}
+
+pub struct MoveInput {
+    config: RepeatConfig,
+    /// 押されている方向。後に押したものほど後ろにある
+    held: Vec<DirectionJson>,
+    /// 次に繰り返すまでの時間
+    until_repeat: Duration,
+    /// まだ送っていない移動
+    queue: VecDeque<DirectionJson>,
+    /// 最後のtickの後に移動を送ったかどうか
+    sent_since_tick: bool,
+}
+
+impl MoveInput {
+    pub fn new(config: RepeatConfig) -> Self {
+        Self {
+            config,
+            held: Vec::new(),
+            until_repeat: Duration::ZERO,
+            queue: VecDeque::new(),
+            sent_since_tick: false,
+        }
+    }
+
+    /// キーが押された。OSのキーリピートで届いた2回目以降は無視する
+    pub fn press(&mut self, direction: DirectionJson) {
+        if self.held.contains(&direction) {
+            return;
+        }
+        self.held.push(direction);
+        self.until_repeat = self.config.initial_delay;
+        self.enqueue(direction);
+    }
+
+    /// キーが離された。ためてある移動はそのまま送る
+    pub fn release(&mut self, direction: DirectionJson) {
+        let was_repeating = self.held.last() == Some(&direction);
+        self.held.retain(|&held| held != direction);
+        if was_repeating {
+            // 前から押されていたキーの繰り返しは、初めからやり直す
+            self.until_repeat = self.config.initial_delay;
+        }
+    }
+
+    /// 毎フレーム呼ぶ。`elapsed`は前のフレームからの経過時間
+    pub fn update(&mut self, elapsed: Duration) {
+        let direction = match self.held.last() {
+            Some(&direction) => direction,
+            None => return,
+        };
+        if elapsed < self.until_repeat {
+            self.until_repeat -= elapsed;
+            return;
+        }
+        // フレームの長さで繰り返しの間隔がずれないように、超えた分を次から引く
+        self.until_repeat = self
+            .config
+            .repeat_interval
+            .saturating_sub(elapsed - self.until_repeat);
+        // 押しっぱなしで移動がたまり、離した後も動き続けないようにする
+        if self.queue.is_empty() {
+            self.queue.push_back(direction);
+        }
+    }
+
+    /// サーバーからフィールドが届いた。次の移動を送れるようにする
+    pub fn on_tick(&mut self) {
+        self.sent_since_tick = false;
+    }
+
+    /// 今送るべき移動。前のtickからすでに送っていれば`None`
+    pub fn take_move(&mut self) -> Option<DirectionJson> {
+        if self.sent_since_tick {
+            return None;
+        }
+        let direction = self.queue.pop_front()?;
+        self.sent_since_tick = true;
+        Some(direction)
+    }
+
+    /// ゲームが終わったときなどに、押されているキーとためてある移動を忘れる
+    pub fn clear(&mut self) {
+        self.held.clear();
+        self.queue.clear();
+        self.sent_since_tick = false;
+    }
+
+    /// いっぱいなら一番古い移動を捨てる
+    fn enqueue(&mut self, direction: DirectionJson) {
+        if self.config.buffer_size == 0 {
+            return;
+        }
+        while self.queue.len() >= self.config.buffer_size {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(direction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    fn input() -> MoveInput {
+        MoveInput::new(RepeatConfig {
+            initial_delay: Duration::from_millis(100),
+            repeat_interval: Duration::from_millis(50),
+            buffer_size: 2,
+        })
+    }
+
+    /// `frames`フレーム進めて、その間に送った移動を返す。tickは毎フレーム届くことにする
+    fn run(input: &mut MoveInput, frames: u32) -> Vec<DirectionJson> {
+        let mut moves = Vec::new();
+        for _ in 0..frames {
+            input.update(FRAME);
+            moves.extend(input.take_move());
+            input.on_tick();
+        }
+        moves
+    }
+
+    #[test]
+    fn tap_moves_once() {
+        let mut input = input();
+        input.press(DirectionJson::Up);
+        // OSのキーリピート
+        input.press(DirectionJson::Up);
+        input.press(DirectionJson::Up);
+        assert_eq!(run(&mut input, 3), vec![DirectionJson::Up]);
+        input.release(DirectionJson::Up);
+        assert_eq!(run(&mut input, 30), vec![]);
+    }
+
+    #[test]
+    fn hold_repeats_after_delay() {
+        let mut input = input();
+        input.press(DirectionJson::Right);
+        // 押した瞬間に1回、100msで1回、その後は50msごと
+        assert_eq!(run(&mut input, 6).len(), 1);
+        assert_eq!(run(&mut input, 1).len(), 1);
+        assert_eq!(run(&mut input, 6).len(), 2);
+        input.release(DirectionJson::Right);
+        assert_eq!(run(&mut input, 30), vec![]);
+    }
+
+    #[test]
+    fn one_move_per_tick() {
+        let mut input = input();
+        input.press(DirectionJson::Up);
+        input.press(DirectionJson::Left);
+        assert_eq!(input.take_move(), Some(DirectionJson::Up));
+        assert_eq!(input.take_move(), None);
+        input.release(DirectionJson::Up);
+        input.release(DirectionJson::Left);
+
+        // tickが届くまで、押されたキーはためておく
+        input.on_tick();
+        assert_eq!(input.take_move(), Some(DirectionJson::Left));
+        assert_eq!(input.take_move(), None);
+        input.on_tick();
+        assert_eq!(input.take_move(), None);
+    }
+
+    #[test]
+    fn full_buffer_drops_oldest() {
+        let mut input = input();
+        input.press(DirectionJson::Up);
+        input.press(DirectionJson::Down);
+        input.press(DirectionJson::Left);
+        assert_eq!(input.take_move(), Some(DirectionJson::Down));
+        input.on_tick();
+        assert_eq!(input.take_move(), Some(DirectionJson::Left));
+    }
+
+    #[test]
+    fn stick_with_hysteresis() {
+        use ActionEvent::*;
+
+        let mut stick = StickInput::new();
+        assert_eq!(stick.set_x(10_000), vec![]);
+        assert_eq!(
+            stick.set_x(20_000),
+            vec![Pressed(Action::Move(DirectionJson::Right))]
+        );
+        // 少し戻しただけでは離したことにしない
+        assert_eq!(stick.set_x(12_000), vec![]);
+        // 縦に大きく倒すと向きが変わる
+        assert_eq!(
+            stick.set_y(-30_000),
+            vec![
+                Released(Action::Move(DirectionJson::Right)),
+                Pressed(Action::Move(DirectionJson::Up)),
+            ]
+        );
+        stick.set_x(0);
+        assert_eq!(
+            stick.set_y(-5_000),
+            vec![Released(Action::Move(DirectionJson::Up))]
+        );
+    }
+
+    #[test]
+    fn latest_held_key_repeats() {
+        let mut input = input();
+        input.press(DirectionJson::Up);
+        run(&mut input, 3);
+        input.press(DirectionJson::Left);
+        let moves = run(&mut input, 20);
+        assert!(moves.iter().all(|&direction| direction == DirectionJson::Left));
+
+        // 後から押したキーを離すと、前のキーの繰り返しに戻る
+        input.release(DirectionJson::Left);
+        let moves = run(&mut input, 20);
+        assert!(!moves.is_empty());
+        assert!(moves.iter().all(|&direction| direction == DirectionJson::Up));
+    }
+}
@@ -0,0 +1,226 @@
+//! キーボードのどのキーにどの操作を割り当てるか
+//!
+//! 設定ファイルの`[keybindings]`に、操作の名前(`Action::name`)ごとにキーの名前の配列を書く。
+//! キーの名前はSDLのスキャンコード名(`"W"`, `"Up"`, `"Space"`など)で、大文字と小文字は区別しない。
+//!
+//! ```toml
+//! [keybindings]
+//! move_up = ["W", "Up"]
+//! confirm = ["Space", "Return"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::api::json::DirectionJson;
+use crate::input::Action;
+
+/// 設定ファイルに書いてある形
+pub type KeyBindingTable = BTreeMap<String, Vec<String>>;
+
+/// 設定ファイルに書いていない操作に割り当てるキー
+pub fn default_keys(action: Action) -> &'static [&'static str] {
+    match action {
+        Action::Move(DirectionJson::Up) => &["W", "Up"],
+        Action::Move(DirectionJson::Down) => &["S", "Down"],
+        Action::Move(DirectionJson::Left) => &["A", "Left"],
+        Action::Move(DirectionJson::Right) => &["D", "Right"],
+        Action::Confirm => &["Space", "Return"],
+        Action::Back => &["Escape"],
+        Action::Offline => &["O"],
+        Action::ToggleFullscreen => &["F11"],
+        Action::ToggleMinimap => &["M"],
+        Action::SeekForward => &["PageUp"],
+        Action::SeekBackward => &["PageDown"],
+        Action::SeekStart => &["Home"],
+    }
+}
+
+/// `table`に書いていない`action`に割り当てるキー
+///
+/// `default_keys`のうち、`table`で他の操作に割り当てられているキーは除く。
+pub fn unbound_default_keys(action: Action, table: &KeyBindingTable) -> Vec<String> {
+    default_keys(action)
+        .iter()
+        .filter(|key| {
+            let bound = table
+                .values()
+                .flatten()
+                .any(|bound| bound.eq_ignore_ascii_case(key));
+            if bound {
+                tracing::warn!(
+                    "default key `{}` for `{}` is already bound to another action",
+                    key,
+                    action.name()
+                );
+            }
+            !bound
+        })
+        .map(|key| key.to_string())
+        .collect()
+}
+
+/// 1つのキーが2つの操作に割り当てられている
+#[derive(Debug, PartialEq)]
+pub struct KeyBindingConflict {
+    pub key: String,
+    pub actions: (Action, Action),
+}
+
+impl fmt::Display for KeyBindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key `{}` is bound to both `{}` and `{}`",
+            self.key,
+            self.actions.0.name(),
+            self.actions.1.name()
+        )
+    }
+}
+
+impl std::error::Error for KeyBindingConflict {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    /// `Action::ALL`の順
+    bindings: Vec<(Action, Vec<String>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::from_table(&KeyBindingTable::new())
+    }
+}
+
+/// 設定ファイルと同じ形で書き出す
+impl Serialize for KeyBindings {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_table().serialize(serializer)
+    }
+}
+
+impl KeyBindings {
+    /// `table`に書いていない操作には`unbound_default_keys`を割り当てる
+    pub fn from_table(table: &KeyBindingTable) -> Self {
+        for name in table.keys() {
+            if Action::from_name(name).is_none() {
+                tracing::warn!("unknown action in keybindings: {}", name);
+            }
+        }
+        let bindings = Action::ALL
+            .iter()
+            .map(|&action| {
+                let keys = match table.get(action.name()) {
+                    Some(keys) => keys.clone(),
+                    None => unbound_default_keys(action, table),
+                };
+                (action, keys)
+            })
+            .collect();
+        Self { bindings }
+    }
+
+    pub fn to_table(&self) -> KeyBindingTable {
+        self.bindings
+            .iter()
+            .map(|(action, keys)| (action.name().to_string(), keys.clone()))
+            .collect()
+    }
+
+    pub fn keys(&self, action: Action) -> &[String] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map_or(&[], |(_, keys)| keys.as_slice())
+    }
+
+    /// 画面に出す、`action`のキーの名前。複数なら`/`でつなぐ
+    pub fn describe(&self, action: Action) -> String {
+        self.keys(action).join("/")
+    }
+
+    /// キーと操作の組をすべて返す
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Action)> {
+        self.bindings
+            .iter()
+            .flat_map(|(action, keys)| keys.iter().map(move |key| (key.as_str(), *action)))
+    }
+
+    /// 同じキーが2つの操作に割り当てられていないか調べる
+    pub fn validate(&self) -> Result<(), KeyBindingConflict> {
+        let mut seen: Vec<(String, Action)> = Vec::new();
+        for (key, action) in self.iter() {
+            let key = key.to_lowercase();
+            match seen.iter().find(|(seen_key, _)| *seen_key == key) {
+                Some(&(_, other)) if other != action => {
+                    return Err(KeyBindingConflict {
+                        key,
+                        actions: (other, action),
+                    });
+                }
+                Some(_) => {}
+                None => seen.push((key, action)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, &[&str])]) -> KeyBindingTable {
+        entries
+            .iter()
+            .map(|(action, keys)| {
+                (
+                    action.to_string(),
+                    keys.iter().map(|key| key.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn missing_actions_use_defaults() {
+        let bindings = KeyBindings::from_table(&table(&[("confirm", &["Return"])]));
+        assert_eq!(bindings.keys(Action::Confirm), ["Return"]);
+        assert_eq!(bindings.keys(Action::Back), ["Escape"]);
+        assert_eq!(bindings.keys(Action::SeekStart), ["Home"]);
+        assert_eq!(bindings.to_table().len(), Action::ALL.len());
+        assert!(KeyBindings::default().validate().is_ok());
+
+        // 初期設定のキーが他の操作に使われていたら、そのキーは割り当てない
+        let bindings = KeyBindings::from_table(&table(&[("move_left", &["o"])]));
+        assert!(bindings.keys(Action::Offline).is_empty());
+        assert!(bindings.validate().is_ok());
+    }
+
+    #[test]
+    fn describe_keys() {
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.describe(Action::Confirm), "Space/Return");
+        let bindings = KeyBindings::from_table(&table(&[("back", &[])]));
+        assert_eq!(bindings.describe(Action::Back), "");
+    }
+
+    #[test]
+    fn conflicting_keys() {
+        let bindings = KeyBindings::from_table(&table(&[("move_up", &["W"]), ("offline", &["w"])]));
+        assert_eq!(
+            bindings.validate(),
+            Err(KeyBindingConflict {
+                key: "w".to_string(),
+                actions: (Action::Move(DirectionJson::Up), Action::Offline),
+            })
+        );
+
+        // 同じ操作に同じキーを2回書くのはかまわない
+        let bindings = KeyBindings::from_table(&table(&[("move_up", &["W", "w"])]));
+        assert!(bindings.validate().is_ok());
+    }
+}
@@ -0,0 +1,44 @@
+//! Blocking.ioのクライアントのうち、ウィンドウ(SDL2)に依存しない部分
+//!
+//! ゲーム本体(`main.rs`)のほか、`src/bin`以下のツールからも使う
+
+pub mod api;
+pub mod bot;
+pub mod camera;
+pub mod client_state;
+pub mod fonts;
+pub mod gui_renderer;
+pub mod hud;
+pub mod input;
+pub mod keybindings;
+pub mod locale;
+pub mod minimap;
+pub mod offline;
+pub mod player;
+pub mod replay;
+pub mod room_browser;
+pub mod setting_storage;
+pub mod socketio_encoding;
+pub mod stand_in_server;
+pub mod tracing_ex;
+pub mod types;
+pub mod world;
+
+use uuid::Uuid;
+
+// 64x64ピクセルのテクスチャが4x4個並んでいる
+pub const TEX_W: u32 = 64;
+pub const TEX_H: u32 = 64;
+pub const TEX_ATLAS_W: u32 = TEX_W * 4;
+pub const TEX_ATLAS_H: u32 = TEX_H * 4;
+
+/// サーバーからフィールドが届く前と、オフラインの対戦のフィールドの1辺のマス数
+pub const FIELD_SIZE: usize = 32;
+
+#[derive(PartialEq, Debug)]
+pub enum GameFinishReason {
+    Fall { winner_uid: Option<Uuid> },
+    Collision { winner_uid: Option<Uuid> },
+    Timeup,
+    Abnromal,
+}
@@ -0,0 +1,164 @@
+//! 画面に出す文字列を、言語ごとに切り替える
+//!
+//! 文字列は言語ごとに`rsc_included/locales/<言語>.toml`に書く。
+//! テーブルの名前とキーを`.`でつないだもの(`result.win`など)で引く。
+//! 選んだ言語にない文字列は日本語で出す。
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use tracing::warn;
+
+/// 設定ファイルに`language`がないときや、知らない言語のときに使う言語
+pub const DEFAULT_LANGUAGE: &str = "ja";
+
+/// 同梱している言語と、そのファイルの中身
+const LOCALES: &[(&str, &str)] = &[
+    ("ja", include_str!("../rsc_included/locales/ja.toml")),
+    ("en", include_str!("../rsc_included/locales/en.toml")),
+];
+
+pub struct Catalog {
+    language: &'static str,
+    messages: HashMap<String, String>,
+    /// `DEFAULT_LANGUAGE`の文字列
+    fallback: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn new(language: &str) -> Self {
+        let language = match LOCALES.iter().find(|(name, _)| *name == language) {
+            Some((name, _)) => *name,
+            None => {
+                warn!("unknown language: {}, use {}", language, DEFAULT_LANGUAGE);
+                DEFAULT_LANGUAGE
+            }
+        };
+        Self {
+            language,
+            messages: parse(language),
+            fallback: parse(DEFAULT_LANGUAGE),
+        }
+    }
+
+    /// 同梱している言語の名前
+    pub fn languages() -> impl Iterator<Item = &'static str> {
+        LOCALES.iter().map(|(name, _)| *name)
+    }
+
+    pub fn language(&self) -> &str {
+        self.language
+    }
+
+    /// `key`の文字列。どの言語にもなければ`key`をそのまま返す
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.messages
+            .get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, String::as_str)
+    }
+
+    /// `key`の文字列の`{}`を、前から順に`args`で置き換える
+    pub fn format(&self, key: &str, args: &[&dyn Display]) -> String {
+        let mut parts = self.get(key).split("{}");
+        let mut text = parts.next().unwrap_or("").to_string();
+        for (i, part) in parts.enumerate() {
+            if let Some(arg) = args.get(i) {
+                text += &arg.to_string();
+            }
+            text += part;
+        }
+        text
+    }
+}
+
+fn parse(language: &str) -> HashMap<String, String> {
+    let text = LOCALES
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map_or("", |(_, text)| *text);
+    let mut messages = HashMap::new();
+    match toml::from_str::<toml::Value>(text) {
+        Ok(value) => flatten("", &value, &mut messages),
+        Err(error) => warn!("failed to parse locale {}: {}", language, error),
+    }
+    messages
+}
+
+/// 入れ子のテーブルを、キーを`.`でつないだ1つの表にする
+fn flatten(prefix: &str, value: &toml::Value, messages: &mut HashMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, messages);
+            }
+        }
+        toml::Value::String(text) => {
+            messages.insert(prefix.to_string(), text.clone());
+        }
+        _ => warn!("locale value is not a string: {}", prefix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::BUNDLED_FONT;
+    use crate::types::*;
+    use re::text::FontSet;
+
+    #[test]
+    fn every_language_has_every_message() {
+        let fallback = parse(DEFAULT_LANGUAGE);
+        assert!(fallback.contains_key("result.win"));
+        for language in Catalog::languages() {
+            let messages = parse(language);
+            let mut missing: Vec<_> = fallback
+                .keys()
+                .filter(|key| !messages.contains_key(*key))
+                .collect();
+            missing.sort();
+            assert!(missing.is_empty(), "{} lacks {:?}", language, missing);
+        }
+    }
+
+    #[test]
+    fn falls_back_to_japanese() {
+        let catalog = Catalog::new("en");
+        assert_eq!(catalog.get("result.win"), "You win");
+
+        let catalog = Catalog::new("xx");
+        assert_eq!(catalog.language(), "ja");
+        assert_eq!(catalog.get("result.win"), "勝ち");
+        assert_eq!(catalog.get("no.such.key"), "no.such.key");
+    }
+
+    #[test]
+    fn bundled_font_draws_every_message() {
+        let mut fonts = FontSet::new();
+        fonts.add(BUNDLED_FONT.to_vec(), 0).unwrap();
+        for language in Catalog::languages() {
+            let missing: String = parse(language)
+                .values()
+                .flat_map(|text| text.chars())
+                .filter(|c| !c.is_whitespace() && !fonts.has_glyph(*c))
+                .collect();
+            assert!(missing.is_empty(), "{} lacks {}", language, missing);
+        }
+    }
+
+    #[test]
+    fn format_arguments() {
+        let catalog = Catalog::new("ja");
+        assert_eq!(
+            catalog.format("hud.player", &[&"名無しユーザー", &3]),
+            "名無しユーザー  3点"
+        );
+        assert_eq!(catalog.format("hud.elapsed", &[]), "経過 ");
+    }
+}
@@ -1,13 +1,16 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
 use std::ffi::CString;
 use std::fs::File;
-use std::sync::Arc;
+use std::path::PathBuf;
 use std::sync::Mutex;
+use std::time::Instant;
 
-use sdl2::keyboard::KeyboardState;
-use sdl2::keyboard::Scancode;
+use sdl2::controller::{Axis, Button};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Scancode};
 use uuid::Uuid;
 
 use re::gl;
@@ -18,39 +21,39 @@ use re::shader::UniformVariables;
 use re::vao::Vao;
 use re::vao::VaoConfigBuilder;
 
-mod api;
-mod camera;
 mod engine;
-mod gui_renderer;
-mod player;
-mod setting_storage;
-mod socketio_encoding;
-mod tracing_ex;
-mod types;
-mod world;
-
-use crate::api::client::ApiClient;
-use crate::api::client::ApiEvent;
-use crate::api::json::DirectionJson;
-use crate::camera::Camera;
-use crate::engine::Engine;
-use crate::gui_renderer::GuiRenderer;
-use crate::player::Player;
-use crate::setting_storage::Setting;
-use crate::tracing_ex::WarnIfError;
-use crate::types::*;
-use crate::world::World;
 
-// 64x64ピクセルのテクスチャが4x4個並んでいる
-pub const TEX_W: u32 = 64;
-pub const TEX_H: u32 = 64;
-pub const TEX_ATLAS_W: u32 = TEX_W * 4;
-pub const TEX_ATLAS_H: u32 = TEX_H * 4;
+use blocking_io_client::api::client::ApiClient;
+use blocking_io_client::api::client::ApiEvent;
+use blocking_io_client::api::client::RoomChoice;
+use blocking_io_client::api::event_queue::{EventQueue, SharedEventQueue};
+use blocking_io_client::api::json::DirectionJson;
+use blocking_io_client::api::transport::GameTransport;
+use blocking_io_client::camera::Camera;
+use blocking_io_client::client_state::{transition, ClientState, Command, Input};
+use blocking_io_client::fonts::load_fonts;
+use blocking_io_client::gui_renderer::GuiRenderer;
+use blocking_io_client::hud::GameClock;
+use blocking_io_client::input::{Action, ActionEvent, MoveInput, StickInput};
+use blocking_io_client::keybindings::KeyBindings;
+use blocking_io_client::locale::Catalog;
+use blocking_io_client::offline::{self, OfflineGame};
+use blocking_io_client::player::Player;
+use blocking_io_client::replay::{self, Recorder, Recording, ReplayPlayer};
+use blocking_io_client::room_browser::RoomBrowser;
+use blocking_io_client::setting_storage::Setting;
+use blocking_io_client::tracing_ex::WarnIfError;
+use blocking_io_client::types::*;
+use blocking_io_client::world::World;
+use blocking_io_client::{GameFinishReason, FIELD_SIZE, TEX_ATLAS_H, TEX_ATLAS_W, TEX_H, TEX_W};
+
+use crate::engine::Engine;
 
-const FIELD_SIZE: usize = 32;
+/// リプレイでPageUp/PageDownを押したときに動く時間(ミリ秒)
+const REPLAY_SEEK_MS: f64 = 10_000.0;
 
 fn main() {
-    use tracing::{info, warn};
+    use tracing::{debug, info};
 
     {
         let level = if std::env::var("BLKIO_TRACE").unwrap_or("".to_string()) == "1" {
@@ -72,7 +75,7 @@ fn main() {
         };
     }
 
-    let setting = Setting::load().expect_or_log("設定ファイルの読み込みに失敗");
+    let mut setting = Setting::load().expect_or_log("設定ファイルの読み込みに失敗");
 
     let socketio_thread = tokio::runtime::Runtime::new().unwrap_or_log();
 
@@ -97,6 +100,19 @@ fn main() {
     let shader = Program::from_shaders(gl.clone(), &[vert_shader, frag_shader]).unwrap_or_log();
     info!("world shader program");
 
+    let vert_shader = Shader::from_vert_code(
+        gl.clone(),
+        &CString::new(include_str!("../rsc_included/shader/world.vert")).unwrap_or_log(),
+    )
+    .unwrap_or_log();
+    let frag_shader = Shader::from_frag_code(
+        gl.clone(),
+        &CString::new(include_str!("../rsc_included/shader/field.frag")).unwrap_or_log(),
+    )
+    .unwrap_or_log();
+    let shader_field = Program::from_shaders(gl.clone(), &[vert_shader, frag_shader]).unwrap_or_log();
+    info!("field shader program");
+
     let vert_shader = Shader::from_vert_code(
         gl.clone(),
         &CString::new(include_str!("../rsc_included/shader/gui.vert")).unwrap_or_log(),
@@ -110,6 +126,19 @@ fn main() {
     let shader_gui = Program::from_shaders(gl.clone(), &[vert_shader, frag_shader]).unwrap_or_log();
     info!("gui shader program");
 
+    let vert_shader = Shader::from_vert_code(
+        gl.clone(),
+        &CString::new(include_str!("../rsc_included/shader/text.vert")).unwrap_or_log(),
+    )
+    .unwrap_or_log();
+    let frag_shader = Shader::from_frag_code(
+        gl.clone(),
+        &CString::new(include_str!("../rsc_included/shader/text.frag")).unwrap_or_log(),
+    )
+    .unwrap_or_log();
+    let shader_text = Program::from_shaders(gl.clone(), &[vert_shader, frag_shader]).unwrap_or_log();
+    info!("text shader program");
+
     let main_texture = engine
         .image_manager
         .load_from_memory(
@@ -128,7 +157,7 @@ fn main() {
         .unwrap_or_log();
     info!("load texture 2/2");
 
-    let mut world = World::<FIELD_SIZE, FIELD_SIZE>::new();
+    let mut world = World::new();
     info!("world");
 
     let vao_config = VaoConfigBuilder::new(&shader)
@@ -140,121 +169,244 @@ fn main() {
         .diffuse(Vector3::new(0.5, 0.5, 0.5))
         .specular(Vector3::new(0.2, 0.2, 0.2))
         .build();
-    let mut field_vao = world.render_field().build(&gl, &vao_config);
-    let mut player_vao = world.render_players().build(&gl, &vao_config);
+    // フィールドはまとめた面にテクスチャを繰り返して貼るので、専用のシェーダーで描く
+    let field_vao_config = VaoConfigBuilder::new(&shader_field)
+        .texture(&main_texture)
+        .material_specular(Vector3::new(0.2, 0.2, 0.2))
+        .material_shininess(0.1_f32)
+        .light_direction(Vector3::new(0.2, 1.0, 0.2))
+        .ambient(Vector3::new(0.3, 0.3, 0.3))
+        .diffuse(Vector3::new(0.5, 0.5, 0.5))
+        .specular(Vector3::new(0.2, 0.2, 0.2))
+        .build();
+    // フィールドは変わった区画だけを書き換え、大きさが変わったときだけ作り直す
+    let mut field_vao = world.render_field().build_dynamic(&gl, &field_vao_config);
+    // 端の床のブロック、プレイヤーと鬼は1つの形を、インスタンスごとの位置に描く
+    let mut block_vao = world.block_mesh().build(&gl, &vao_config);
+    block_vao.set_instances(world.field_blocks());
+    let mut player_vao = world.player_mesh().build(&gl, &vao_config);
+    player_vao.set_instances(world.render_players(0));
     info!("world VAO buffers");
 
     let gui_vao_config = VaoConfigBuilder::new(&shader_gui).texture(&gui_texture).build();
     let (width, height) = engine.window().drawable_size();
-    let mut gui_renderer = GuiRenderer::new(width, height, &gui_texture);
+    let fonts = load_fonts();
+    let catalog = Catalog::new(&setting.language);
+    let mut gui_renderer = GuiRenderer::new(width, height, &gui_texture, fonts, catalog);
     info!("GUI Renderer");
 
-    let mut own_player_pos: Point3 = Point3::new(16.0, 0.0, 16.0);
+    let own_player_pos: Point3 = world.center();
     let mut camera = Camera::new(own_player_pos);
 
-    let mut user_id = setting.uuid;
-    let mut user_name: String = "".to_string();
-
     let mut client_state = ClientState::TitleScreen;
+    let mut session = Session {
+        // タイトル画面でスペースキーを押すとサーバーに、Oキーを押すと1人用のゲームにつなぎ替える
+        transport: Box::new(ApiClient::new(&setting.server)),
+        events: EventQueue::new_shared(),
+        world,
+        room_browser: RoomBrowser::new(),
+        replay_player: None,
+        user_id: setting.uuid,
+        user_name: "".to_string(),
+        own_player_pos,
+        move_input: MoveInput::new(setting.move_repeat()),
+        clock: GameClock::new(None, None),
+    };
 
-    let mut api = ApiClient::new(&setting.server);
-    let unhandled_events = Arc::new(Mutex::new(VecDeque::new()));
+    // `--replay <path>`が指定されたら、接続せずに記録を再生する
+    if let Some(path) = replay_path_from_args() {
+        match Recording::load(&path) {
+            Ok(recording) => {
+                info!("replaying {}", path.display());
+                session.replay_player = Some(ReplayPlayer::new(recording));
+                client_state = ClientState::Replaying { finished: None };
+            }
+            Err(error) => tracing::error!("リプレイを読み込めません: {} {}", path.display(), error),
+        }
+    }
 
     // ゲーム開始から現在までのフレーム数。約60フレームで1秒
     let mut frames: u64 = 0;
+    let mut last_frame_at = Instant::now();
+
+    let key_actions = key_actions(&setting.keybindings);
+    // ゲームパッドごとの左スティック。キーはinstance id
+    let mut sticks: HashMap<u32, StickInput> = HashMap::new();
 
     'main: loop {
         frames += 1;
+        let frame_time = last_frame_at.elapsed();
+        last_frame_at = Instant::now();
+
+        let mut inputs = Vec::new();
 
         // OSのイベントを処理
-        for event in engine.event_pump.poll_iter() {
-            use sdl2::event::Event;
+        let sdl_events: Vec<Event> = engine.event_pump.poll_iter().collect();
+        let mut action_events = Vec::new();
+        for event in sdl_events {
             match event {
-                Event::Quit { .. } => client_state = ClientState::Quit,
+                Event::Quit { .. } => inputs.push(Input::Close),
+                // どの画面でも切り替えられるように、ほかのキーより先に見る
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat: false,
+                    ..
+                } if key_actions.get(&scancode) == Some(&Action::ToggleFullscreen) => {
+                    setting.fullscreen = !setting.fullscreen;
+                    engine.set_fullscreen(setting.fullscreen);
+                }
+                // ルームの名前の入力中は文字として扱う
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat: false,
+                    ..
+                } if key_actions.get(&scancode) == Some(&Action::ToggleMinimap)
+                    && client_state != ClientState::BrowsingRooms =>
+                {
+                    setting.minimap = !setting.minimap;
+                }
+                Event::TextInput { ref text, .. } if client_state == ClientState::BrowsingRooms => {
+                    session.room_browser.input_text(text);
+                }
+                // 新しいルームの名前の入力中は、BackspaceとEnterは入力欄のキーとして扱う
+                Event::KeyDown {
+                    scancode: Some(scancode @ Scancode::Backspace),
+                    ..
+                }
+                | Event::KeyDown {
+                    scancode: Some(scancode @ Scancode::Return),
+                    ..
+                }
+                | Event::KeyDown {
+                    scancode: Some(scancode @ Scancode::KpEnter),
+                    ..
+                } if client_state == ClientState::BrowsingRooms
+                    && session.room_browser.is_creating() =>
+                {
+                    if scancode == Scancode::Backspace {
+                        session.room_browser.backspace();
+                    } else {
+                        handle_action(
+                            ActionEvent::Pressed(Action::Confirm),
+                            &client_state,
+                            &mut session,
+                            &mut inputs,
+                        );
+                    }
+                }
+                // 行の選択やリプレイの操作は、押し続けたときのキーリピートでも繰り返す
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    keycode,
+                    ..
+                } if client_state == ClientState::BrowsingRooms
+                    || matches!(client_state, ClientState::Replaying { .. }) =>
+                {
+                    // 名前の入力中は、文字を入力するキーを操作として扱わない
+                    let typing = client_state == ClientState::BrowsingRooms
+                        && session.room_browser.is_creating()
+                        && matches!(keycode, Some(keycode) if types_text(keycode));
+                    if let Some(&action) = key_actions.get(&scancode).filter(|_| !typing) {
+                        handle_action(
+                            ActionEvent::Pressed(action),
+                            &client_state,
+                            &mut session,
+                            &mut inputs,
+                        );
+                    }
+                }
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat: false,
+                    ..
+                } => action_events.extend(key_actions.get(&scancode).copied().map(ActionEvent::Pressed)),
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    action_events.extend(key_actions.get(&scancode).copied().map(ActionEvent::Released))
+                }
+
+                Event::ControllerDeviceAdded { which, .. } => engine.open_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    engine.close_controller(which);
+                    sticks.remove(&which);
+                }
+                Event::ControllerButtonDown { button, .. } => {
+                    action_events.extend(button_action(button).map(ActionEvent::Pressed))
+                }
+                Event::ControllerButtonUp { button, .. } => {
+                    action_events.extend(button_action(button).map(ActionEvent::Released))
+                }
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => {
+                    let stick = sticks.entry(which).or_insert_with(StickInput::new);
+                    match axis {
+                        Axis::LeftX => action_events.extend(stick.set_x(value)),
+                        Axis::LeftY => action_events.extend(stick.set_y(value)),
+                        _ => {}
+                    }
+                }
                 _ => {}
             }
         }
+        for event in action_events {
+            handle_action(event, &client_state, &mut session, &mut inputs);
+        }
+
+        // リプレイを進める
+        if let Some(ref mut player) = session.replay_player {
+            push_replay_events(&session.events, player.update(frame_time), false);
+        }
+
+        // 接続が切れていたら再接続する。1人用のゲームはここで進む
+        session.transport.poll(frame_time);
 
         // Socket.ioのイベントを処理
         socketio_thread.block_on(async {
-            let mut lock = unhandled_events.lock().unwrap_or_log();
-            if let Some(event) = lock.pop_front() {
-                match event {
-                    ApiEvent::UpdateUser { uid, name } => {
-                        if client_state == ClientState::WaitingSettingUid {
-                            user_id = uid;
-                            user_name = name;
-                            client_state = ClientState::JoiningRoom;
-                        } else {
-                            warn!(
-                                "unexpected event ApiEvent::UpdateUser uid:{}. state: {:?}",
-                                uid, client_state
-                            );
-                        }
-                    }
-                    ApiEvent::RoomStateOpening { room_id, room_name } => {
-                        info!("room opening id: {}, name: {}", room_id, room_name);
-                    }
-                    ApiEvent::RoomStateFulfilled { room_id, room_name, should_start } => {
-                        if client_state == ClientState::WaitingInRoom {
-                            info!("room fulfilled id: {}, name: {}", room_id, room_name);
-                            if should_start {
-                                info!("starting game");
-                                client_state = ClientState::Playing;
-                            } else {
-                                info!("not starting game");
-                            }
-                        } else if let ClientState::GameFinished{..} = client_state {
-                            // 相手が先に再戦を希望した場合
-                            // 何もしない
-                        }
-                        else {
-                            warn!(
-                                "unexpected event ApiEvent::RoomStateFulfilled room_id: {}, room_name: {}. state: {:?}",
-                                 room_id, room_name ,
-                                client_state
-                            );
-                        }
-                    }
-                    ApiEvent::RoomStateEmpty { .. } => {
-                        warn!("room-stateイベントによるとルームはEmptyです");
-                    }
-                    ApiEvent::RoomStateNotJoined => {
-                        if !(matches!(client_state, ClientState::Playing | ClientState::GameFinished{..} | ClientState::WaitingInRoom)) {
-                            warn!(
-                                "unexpected event ApiEvent::RoomStateNotJoined. state: {:?}",
-                                client_state
-                            );
-                        }
-                        client_state = ClientState::TitleScreen;
-                    }
-                    ApiEvent::UpdateField { players, tagger, field } => {
-                        if client_state == ClientState::Playing {
-                            if let Some(own_player) = find_own_player(&players, user_id) {
-                                own_player_pos = world.player_world_pos(own_player);
-                            } else {
-                                // TODO: 自機がいないときのカメラの場所
-                            }
-                            world.update(field);
-                            world.set_players(players);
-                            world.set_tagger(tagger);
-                        } else {
-                            warn!(
-                                "unexpected event ApiEvent::UpdateField. state: {:?}",
-                                client_state
-                            );
-                        }
-                    }
-                    ApiEvent::GameFinished { reason } => {
-                        if client_state == ClientState::Playing {
-                            client_state = ClientState::GameFinished { reason };
-                        }else {
-                            warn!("unexpected event ApiEvent::GameFinished reason: {:?}. state: {:?}", reason, client_state);
+            let events = session.events.lock().unwrap_or_log().drain();
+            inputs.extend(events.into_iter().map(Input::Event));
+        });
+        inputs.push(Input::Frame);
+        client_state = session.update_state(client_state, inputs, &setting);
+
+        // 移動は状態を変えないので、そのまま送る
+        if client_state == ClientState::Playing {
+            session.move_input.update(frame_time);
+            if let Some(direction) = session.move_input.take_move() {
+                match session.transport.try_move(&direction) {
+                    Ok(_) if setting.predict_movement => {
+                        // サーバーの返事を待たずに自機を動かす
+                        if let Some(pos) = session.world.predict_move(session.user_id, direction) {
+                            session.own_player_pos = pos;
                         }
                     }
+                    Ok(_) => {}
+                    Err(error) => tracing::warn!("failed move: {:?}", error),
                 }
             }
-        });
+        }
+
+        // 3秒ごとにルームの一覧を更新
+        if client_state == ClientState::BrowsingRooms && frames % 180 == 0 {
+            session
+                .transport
+                .find_available_rooms()
+                .warn_if_error("failed find available rooms");
+        }
+
+        // 10秒ごとにキューの状況を出力
+        if frames % 600 == 0 {
+            let queue = session.events.lock().unwrap_or_log();
+            debug!(
+                "event queue depth: {} (max {}), dropped ticks: {}, collapsed ticks: {}",
+                queue.len(),
+                queue.max_depth(),
+                queue.dropped_ticks(),
+                queue.collapsed_ticks()
+            );
+        }
 
         // 画面をクリア
         let (width, height) = engine.window().drawable_size();
@@ -265,153 +417,161 @@ fn main() {
             gl.Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
         }
 
+        let world = &mut session.world;
         match client_state {
             ClientState::TitleScreen => {
                 gui_renderer.clear();
                 gui_renderer.change_window_size(width, height);
                 gui_renderer.draw_title();
-                gui_renderer.draw_スペースキーでスタート();
-                gui_renderer.render(&gl, &gui_vao_config);
-
-                let key_state = KeyboardState::new(&engine.event_pump);
-                if key_state.is_scancode_pressed(Scancode::Space) {
-                    client_state = ClientState::SettingConnection
-                }
+                gui_renderer.draw_press_space();
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
             }
 
-            ClientState::SettingConnection => {
-                // サーバーに接続
-                let result = api.connect(&unhandled_events);
-                if let Err(error) = result {
-                    tracing::error!("サーバーに接続できません: {}", error);
-                    client_state = ClientState::TitleScreen;
-                    continue;
-                }
-                let result = api.setup_uid(setting.uuid);
-                if let Err(error) = result {
-                    tracing::error!("uidを設定できません: {}", error);
-                    client_state = ClientState::TitleScreen;
-                    continue;
-                }
-                client_state = ClientState::WaitingSettingUid;
+            ClientState::WaitingSettingUid | ClientState::LeavingRoom { .. } => {
+                gui_renderer.clear();
+                gui_renderer.change_window_size(width, height);
+                gui_renderer.draw_message("status.connecting");
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
             }
 
-            ClientState::WaitingSettingUid => {
+            ClientState::BrowsingRooms => {
                 gui_renderer.clear();
                 gui_renderer.change_window_size(width, height);
-                gui_renderer.draw_接続中();
-                gui_renderer.render(&gl, &gui_vao_config);
+                gui_renderer.draw_room_browser(&session.room_browser, &setting.keybindings);
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
             }
 
-            ClientState::JoiningRoom => {
-                match api.join_room() {
-                    Ok(_) => {
-                        client_state = ClientState::WaitingInRoom;
-                    }
-                    Err(_) => {
-                        warn!("ルームに入れませんでした。タイトル画面に戻ります。");
-                        client_state = ClientState::TitleScreen;
-                    }
-                };
+            ClientState::WaitingInRoom => {
+                gui_renderer.clear();
+                gui_renderer.change_window_size(width, height);
+                gui_renderer.draw_message("status.waiting");
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
             }
 
-            ClientState::WaitingInRoom => {
+            ClientState::Reconnecting => {
                 gui_renderer.clear();
                 gui_renderer.change_window_size(width, height);
-                gui_renderer.draw_待機中();
-                gui_renderer.render(&gl, &gui_vao_config);
+                gui_renderer.draw_message("status.connecting");
+                draw_nameplates(&mut gui_renderer, world, &camera, 0.2, frames, session.user_id);
+                render_field_and_player_vao(
+                    &field_vao,
+                    &block_vao,
+                    &player_vao,
+                    &camera,
+                    0.2,
+                    width,
+                    height,
+                );
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
             }
 
             ClientState::Playing => {
-                // 入力
-                let key_state = KeyboardState::new(&engine.event_pump());
-                if key_state.is_scancode_pressed(Scancode::W)
-                    || key_state.is_scancode_pressed(Scancode::Up)
-                {
-                    api.try_move(&DirectionJson::Up).warn_if_error("failed move");
+                // カメラ移動
+                if world.players_updated() {
+                    camera.shade_to_new_position(session.own_player_pos, frames, 12);
                 }
-                if key_state.is_scancode_pressed(Scancode::S)
-                    || key_state.is_scancode_pressed(Scancode::Down)
-                {
-                    api.try_move(&DirectionJson::Down).warn_if_error("failed move");
+                camera.update_position(frames);
+
+                if world.field_updated() && !world.update_field_vao(&field_vao) {
+                    field_vao = world.render_field().build_dynamic(&gl, &field_vao_config);
+                    block_vao.set_instances(world.field_blocks());
                 }
-                if key_state.is_scancode_pressed(Scancode::D)
-                    || key_state.is_scancode_pressed(Scancode::Right)
-                {
-                    api.try_move(&DirectionJson::Right).warn_if_error("failed move");
+                if world.players_updated() || world.players_moving(frames) {
+                    player_vao.set_instances(world.render_players(frames));
                 }
-                if key_state.is_scancode_pressed(Scancode::A)
-                    || key_state.is_scancode_pressed(Scancode::Left)
-                {
-                    api.try_move(&DirectionJson::Left).warn_if_error("failed move");
+                gui_renderer.clear();
+                gui_renderer.change_window_size(width, height);
+                draw_nameplates(&mut gui_renderer, world, &camera, 0.5, frames, session.user_id);
+                draw_hud(&mut gui_renderer, world, &session.clock, session.user_id);
+                if setting.minimap {
+                    draw_minimap(&mut gui_renderer, world, &camera, 0.5, session.user_id);
                 }
 
-                // カメラ移動
+                render_field_and_player_vao(
+                    &field_vao,
+                    &block_vao,
+                    &player_vao,
+                    &camera,
+                    0.5,
+                    width,
+                    height,
+                );
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
+            }
+
+            ClientState::GameFinished { ref reason } => {
+                gui_renderer.clear();
+                gui_renderer.change_window_size(width, height);
+
+                draw_game_result(&mut gui_renderer, reason, session.user_id);
+                gui_renderer.draw_press_space();
+                draw_nameplates(&mut gui_renderer, world, &camera, 0.2, frames, session.user_id);
+                draw_hud(&mut gui_renderer, world, &session.clock, session.user_id);
+                if setting.minimap {
+                    draw_minimap(&mut gui_renderer, world, &camera, 0.2, session.user_id);
+                }
+
+                render_field_and_player_vao(
+                    &field_vao,
+                    &block_vao,
+                    &player_vao,
+                    &camera,
+                    0.2,
+                    width,
+                    height,
+                );
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
+            }
+
+            ClientState::Replaying { ref finished } => {
                 if world.players_updated() {
-                    camera.shade_to_new_position(own_player_pos, frames, 12);
+                    camera.shade_to_new_position(session.own_player_pos, frames, 12);
                 }
                 camera.update_position(frames);
 
-                if world.field_updated() {
-                    field_vao = world.render_field().build(&gl, &vao_config);
+                if world.field_updated() && !world.update_field_vao(&field_vao) {
+                    field_vao = world.render_field().build_dynamic(&gl, &field_vao_config);
+                    block_vao.set_instances(world.field_blocks());
                 }
-                if world.players_updated() {
-                    player_vao = world.render_players().build(&gl, &vao_config);
+                if world.players_updated() || world.players_moving(frames) {
+                    player_vao.set_instances(world.render_players(frames));
                 }
-                render_field_and_player_vao(&field_vao, &player_vao, &camera, 0.5, width, height);
-            }
 
-            ClientState::GameFinished { ref reason } => {
                 gui_renderer.clear();
                 gui_renderer.change_window_size(width, height);
-
-                match *reason {
-                    GameFinishReason::Fall {
-                        winner_uid: Some(winner_uid),
-                    } => {
-                        if winner_uid == user_id {
-                            gui_renderer.draw_相手が落下しました();
-                            gui_renderer.draw_勝ち();
-                        } else {
-                            gui_renderer.draw_落下してしまった();
-                            gui_renderer.draw_負け();
-                        }
-                    }
-                    GameFinishReason::Collision {
-                        winner_uid: Some(winner_uid),
-                    } => {
-                        if winner_uid == user_id {
-                            gui_renderer.draw_相手がつかまりました();
-                            gui_renderer.draw_勝ち();
-                        } else {
-                            gui_renderer.draw_つかまってしまった();
-                            gui_renderer.draw_負け();
-                        }
-                    }
-                    GameFinishReason::Timeup => {
-                        gui_renderer.draw_引き分け();
-                    }
-                    _ => {
-                        gui_renderer.draw_異常終了();
-                    }
-                };
-                gui_renderer.draw_スペースキーでスタート();
-
-                render_field_and_player_vao(&field_vao, &player_vao, &camera, 0.2, width, height);
-                gui_renderer.render(&gl, &gui_vao_config);
-
-                let key_state = KeyboardState::new(&engine.event_pump);
-                if key_state.is_scancode_pressed(Scancode::Space) {
-                    world.update(FieldMatrix::zeros());
-                    world.set_players(Vec::new());
-                    world.set_no_tagger();
-                    api.restart().unwrap_or_log();
-                    client_state = ClientState::WaitingInRoom;
+                if let Some(ref reason) = *finished {
+                    draw_game_result(&mut gui_renderer, reason, session.user_id);
                 }
+                if let Some(ref player) = session.replay_player {
+                    gui_renderer.draw_replay_bar(player.progress(), player.is_paused());
+                }
+                draw_nameplates(&mut gui_renderer, world, &camera, 0.5, frames, session.user_id);
+                draw_hud(&mut gui_renderer, world, &session.clock, session.user_id);
+                if setting.minimap {
+                    draw_minimap(&mut gui_renderer, world, &camera, 0.5, session.user_id);
+                }
+
+                render_field_and_player_vao(
+                    &field_vao,
+                    &block_vao,
+                    &player_vao,
+                    &camera,
+                    0.5,
+                    width,
+                    height,
+                );
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
+            }
+
+            ClientState::ProtocolError => {
+                gui_renderer.clear();
+                gui_renderer.change_window_size(width, height);
+                gui_renderer.draw_message("result.abnormal");
+                gui_renderer.draw_press_space();
+                gui_renderer.render(&gl, &gui_vao_config, &shader_gui, &shader_text);
             }
 
             ClientState::Quit => {
-                api.disconnect().warn_if_error("failed disconnect");
                 setting.save().expect_or_log("設定ファイルの保存に失敗");
                 break 'main;
             }
@@ -422,14 +582,352 @@ fn main() {
     }
 }
 
+/// 状態遷移の`Command`で書き換えるもの
+struct Session {
+    transport: Box<dyn GameTransport>,
+    /// サーバーやリプレイから届いて、まだ処理していないイベント
+    events: SharedEventQueue,
+    world: World,
+    room_browser: RoomBrowser,
+    replay_player: Option<ReplayPlayer>,
+    user_id: Uuid,
+    user_name: String,
+    own_player_pos: Point3,
+    move_input: MoveInput,
+    clock: GameClock,
+}
+
+impl Session {
+    /// `inputs`を順に状態遷移に渡して、返ってきた`Command`を実行する
+    ///
+    /// 実行に失敗したら、残りの`Command`は捨てて`Input::CommandFailed`を渡す
+    fn update_state(
+        &mut self,
+        mut state: ClientState,
+        inputs: Vec<Input>,
+        setting: &Setting,
+    ) -> ClientState {
+        let mut inputs: VecDeque<Input> = inputs.into();
+        while let Some(input) = inputs.pop_front() {
+            let (next, commands) = transition(state, input);
+            state = next;
+            for command in commands {
+                if let Err(error) = self.execute(command, setting) {
+                    tracing::warn!("コマンドを実行できませんでした。 {}", error);
+                    inputs.push_front(Input::CommandFailed);
+                    break;
+                }
+            }
+        }
+        state
+    }
+
+    fn execute(&mut self, command: Command, setting: &Setting) -> Result<(), Box<dyn Error>> {
+        use tracing::{debug, info, warn};
+
+        match command {
+            Command::Connect => {
+                // ルームを出てタイトル画面に戻ってきた場合は、接続し直さない
+                if !self.transport.is_connected() {
+                    let mut api = ApiClient::new(&setting.server);
+                    if setting.record_replay {
+                        match replay::default_dir().and_then(|dir| Recorder::create(&dir)) {
+                            Ok((recorder, path)) => {
+                                info!("recording replay to {}", path.display());
+                                api.set_recorder(Some(recorder));
+                            }
+                            Err(error) => warn!("リプレイを記録できません: {}", error),
+                        }
+                    }
+                    self.transport = Box::new(api);
+                    // サーバーの1tickの長さと制限時間は分からないので、経過時間だけを見積もる
+                    self.clock = GameClock::new(None, None);
+                    self.transport.connect(&self.events)?;
+                }
+                self.transport.setup_uid(setting.uuid)?;
+            }
+            Command::StartOffline => {
+                info!("starting offline game");
+                if self.transport.is_connected() {
+                    self.transport.disconnect().warn_if_error("failed disconnect");
+                }
+                // 1人用のゲームはルームを選ばずに始める
+                self.transport = Box::new(OfflineGame::new(rand::random()));
+                self.clock =
+                    GameClock::new(Some(offline::TICK_INTERVAL), Some(offline::TIME_LIMIT_TICKS));
+                self.user_id = setting.uuid;
+                self.transport.connect(&self.events)?;
+                self.transport.setup_uid(setting.uuid)?;
+                self.transport.join_room(&RoomChoice::Any)?;
+            }
+            Command::FindAvailableRooms => self.transport.find_available_rooms()?,
+            Command::JoinRoom(choice) => self.transport.join_room(&choice)?,
+            Command::Restart => self.transport.restart()?,
+            Command::Leave => self.transport.leave()?,
+            Command::Disconnect => {
+                // 切断できなくても、もう使わないので先に進む
+                self.transport.disconnect().warn_if_error("failed disconnect");
+            }
+            Command::SetUser { uid, name } => {
+                self.user_id = uid;
+                self.user_name = name;
+            }
+            Command::RenameUser { uid, name } => {
+                if uid == self.user_id {
+                    // 再接続後にsetup-uidをやり直したときにも送られてくる
+                    debug!("user updated uid:{} name:{}", uid, name);
+                    self.user_name = name;
+                } else {
+                    warn!("unexpected event ApiEvent::UpdateUser uid:{}", uid);
+                }
+            }
+            Command::SetRooms(rooms) => {
+                debug!("{} rooms available", rooms.len());
+                self.room_browser.set_rooms(rooms);
+            }
+            Command::UpdateWorld {
+                tick_count,
+                players,
+                tagger,
+                field,
+            } => {
+                self.clock.on_tick(tick_count, Instant::now());
+                self.world.update(field);
+                self.world.set_players(players);
+                self.world.set_tagger(tagger);
+                if let Some(own_player) = find_own_player(self.world.players(), self.user_id) {
+                    self.own_player_pos = self.world.player_world_pos(own_player);
+                } else {
+                    // 自機がいなければ、フィールドの真ん中を映す
+                    self.own_player_pos = self.world.center();
+                }
+                self.move_input.on_tick();
+            }
+            Command::ClearWorld => {
+                self.world.update(FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE));
+                self.world.set_players(Vec::new());
+                self.world.set_no_tagger();
+                self.move_input.clear();
+                self.clock.reset();
+            }
+            Command::StopReplay => self.replay_player = None,
+        }
+        Ok(())
+    }
+}
+
+/// 設定ファイルのキー割り当てを、スキャンコードから操作を引く表にする
+fn key_actions(bindings: &KeyBindings) -> HashMap<Scancode, Action> {
+    let mut actions = HashMap::new();
+    for (name, action) in bindings.iter() {
+        match Scancode::from_name(name) {
+            Some(scancode) => {
+                actions.insert(scancode, action);
+            }
+            None => tracing::warn!("キー割り当てのキー名がわかりません: {}", name),
+        }
+    }
+    actions
+}
+
+/// 押すと文字が入力されるキーかどうか。SDLでは、文字のキーのキーコードはその文字のコードになる
+fn types_text(keycode: Keycode) -> bool {
+    (0x20..0x7f).contains(&(keycode as i32))
+}
+
+fn button_action(button: Button) -> Option<Action> {
+    match button {
+        Button::DPadUp => Some(Action::Move(DirectionJson::Up)),
+        Button::DPadDown => Some(Action::Move(DirectionJson::Down)),
+        Button::DPadRight => Some(Action::Move(DirectionJson::Right)),
+        Button::DPadLeft => Some(Action::Move(DirectionJson::Left)),
+        Button::A | Button::Start => Some(Action::Confirm),
+        Button::B => Some(Action::Back),
+        _ => None,
+    }
+}
+
+/// キーボードやゲームパッドの操作を、状態遷移の入力か移動にする
+fn handle_action(
+    event: ActionEvent,
+    client_state: &ClientState,
+    session: &mut Session,
+    inputs: &mut Vec<Input>,
+) {
+    match event {
+        ActionEvent::Pressed(action) if matches!(*client_state, ClientState::Replaying { .. }) => {
+            match session.replay_player {
+                Some(ref mut player) => control_replay(action, player, &session.events, inputs),
+                None if action == Action::Back => inputs.push(Input::Escape),
+                None => {}
+            }
+        }
+        ActionEvent::Pressed(Action::Move(direction)) => match *client_state {
+            ClientState::Playing => session.move_input.press(direction),
+            ClientState::BrowsingRooms => match direction {
+                DirectionJson::Up => session.room_browser.select_previous(),
+                DirectionJson::Down => session.room_browser.select_next(),
+                _ => {}
+            },
+            _ => {}
+        },
+        ActionEvent::Released(Action::Move(direction)) => session.move_input.release(direction),
+        ActionEvent::Pressed(Action::Confirm) => {
+            if *client_state == ClientState::BrowsingRooms {
+                match session.room_browser.choice() {
+                    Some(choice) => inputs.push(Input::SelectRoom(choice)),
+                    None => tracing::info!("room is full"),
+                }
+            } else {
+                inputs.push(Input::Space);
+            }
+        }
+        ActionEvent::Pressed(Action::Back) => inputs.push(Input::Escape),
+        ActionEvent::Pressed(Action::Offline) => inputs.push(Input::Offline),
+        // 全画面と地図の切り替えはキーのイベントを受け取ったところで済ませている
+        ActionEvent::Pressed(Action::ToggleFullscreen)
+        | ActionEvent::Pressed(Action::ToggleMinimap)
+        | ActionEvent::Pressed(Action::SeekForward)
+        | ActionEvent::Pressed(Action::SeekBackward)
+        | ActionEvent::Pressed(Action::SeekStart)
+        | ActionEvent::Released(_) => {}
+    }
+}
+
+/// リプレイの画面の操作
+fn control_replay(
+    action: Action,
+    player: &mut ReplayPlayer,
+    events: &SharedEventQueue,
+    inputs: &mut Vec<Input>,
+) {
+    match action {
+        Action::Confirm => player.toggle_pause(),
+        Action::Move(DirectionJson::Up) => player.faster(),
+        Action::Move(DirectionJson::Down) => player.slower(),
+        Action::Move(DirectionJson::Right) => push_replay_events(events, player.step_forward(), false),
+        Action::Move(DirectionJson::Left) => push_replay_events(events, player.step_backward(), true),
+        Action::SeekForward => {
+            let position = player.position_ms() + REPLAY_SEEK_MS;
+            push_replay_events(events, player.seek(position), true)
+        }
+        Action::SeekBackward => {
+            let position = player.position_ms() - REPLAY_SEEK_MS;
+            push_replay_events(events, player.seek(position), true)
+        }
+        Action::SeekStart => push_replay_events(events, player.seek(0.0), true),
+        Action::Back => inputs.push(Input::Escape),
+        Action::Offline | Action::ToggleFullscreen | Action::ToggleMinimap => {}
+    }
+}
+
 /// 自機を見つける
 /// `players`の要素数は2程度
-fn find_own_player(players: &Vec<Player>, uid: Uuid) -> Option<&Player> {
+fn find_own_player(players: &[Player], uid: Uuid) -> Option<&Player> {
     players.iter().find(|player| player.uid == uid)
 }
 
+/// プレイヤーの名札を、八面体の少し上に描く。画面の上は+x方向
+fn draw_nameplates(
+    gui_renderer: &mut GuiRenderer,
+    world: &World,
+    camera: &Camera,
+    scale: f32,
+    frames: u64,
+    user_id: Uuid,
+) {
+    let (width, height) = gui_renderer.window_size();
+    let own_uid = find_own_player(world.players(), user_id).map(|player| player.uid);
+    for (player, pos) in world.player_positions(frames) {
+        let anchor = pos + Vector3::new(0.6, 0.0, 0.0);
+        if let Some((x, y)) = camera.world_to_screen(&anchor, scale, width, height) {
+            gui_renderer.draw_nameplate(&player.name, x, y, Some(player.uid) == own_uid);
+        }
+    }
+}
+
+/// 時間と各プレイヤーの点数を左上に描く
+fn draw_hud(gui_renderer: &mut GuiRenderer, world: &World, clock: &GameClock, user_id: Uuid) {
+    let own_uid = find_own_player(world.players(), user_id).map(|player| player.uid);
+    gui_renderer.draw_hud(clock, world.players(), own_uid);
+}
+
+/// フィールド全体の地図を描く。画面に映っている範囲は、自機の足元の高さで測る
+fn draw_minimap(
+    gui_renderer: &mut GuiRenderer,
+    world: &World,
+    camera: &Camera,
+    scale: f32,
+    user_id: Uuid,
+) {
+    let (width, height) = gui_renderer.window_size();
+    // カメラは八面体の中心を見ているので、足元はその1.5下
+    let viewport = camera.visible_area(camera.pos().y - 1.5, scale, width, height);
+    let own_uid = find_own_player(world.players(), user_id).map(|player| player.uid);
+    gui_renderer.draw_minimap(
+        world.field(),
+        world.players(),
+        world.tagger(),
+        own_uid,
+        Some(&viewport),
+    );
+}
+
+/// 結果画面の文字を描く
+fn draw_game_result(gui_renderer: &mut GuiRenderer, reason: &GameFinishReason, user_id: Uuid) {
+    // (結果, 理由)。文字列のキーは`rsc_included/locales`を参照
+    let (result, reason) = match *reason {
+        // 1人用のゲームでは勝者がいない
+        GameFinishReason::Fall { winner_uid } => {
+            if winner_uid == Some(user_id) {
+                ("result.win", Some("reason.opponent_fell"))
+            } else {
+                ("result.lose", Some("reason.fell"))
+            }
+        }
+        GameFinishReason::Collision { winner_uid } => {
+            if winner_uid == Some(user_id) {
+                ("result.win", Some("reason.opponent_caught"))
+            } else {
+                ("result.lose", Some("reason.caught"))
+            }
+        }
+        GameFinishReason::Timeup => ("result.draw", Some("reason.timeup")),
+        GameFinishReason::Abnromal => ("result.abnormal", None),
+    };
+    if let Some(reason) = reason {
+        gui_renderer.draw_sub_message(reason);
+    }
+    gui_renderer.draw_message(result);
+}
+
+/// リプレイのイベントを通常のイベントと同じキューに積む
+///
+/// 巻き戻したときは古いtickのイベントが捨てられないように、キューのtickを忘れさせる
+fn push_replay_events(queue: &SharedEventQueue, events: Vec<ApiEvent>, rewound: bool) {
+    let mut queue = queue.lock().unwrap_or_log();
+    if rewound {
+        queue.reset_ticks();
+    }
+    for event in events {
+        queue.push(event);
+    }
+}
+
+/// コマンドライン引数の`--replay <path>`
+fn replay_path_from_args() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--replay" {
+            return args.next().map(PathBuf::from);
+        }
+    }
+    None
+}
+
 fn render_field_and_player_vao(
     field_vao: &Vao,
+    block_vao: &Vao,
     player_vao: &Vao,
     camera: &Camera,
     scale: f32,
@@ -438,12 +936,7 @@ fn render_field_and_player_vao(
 ) {
     let model_matrix: Matrix4 = Matrix4::identity().scale(scale);
     let view_matrix: Matrix4 = camera.view_matrix(scale);
-    let projection_matrix: Matrix4 = Matrix4::new_perspective(
-        window_width as f32 / window_height as f32,
-        std::f32::consts::PI / 4.0f32,
-        0.1,
-        100.0,
-    );
+    let projection_matrix: Matrix4 = Camera::projection_matrix(window_width, window_height);
     let uniforms = {
         let mut uniforms = UniformVariables::new();
         use c_str_macro::c_str;
@@ -455,37 +948,12 @@ fn render_field_and_player_vao(
             c_str!("uViewPosition"),
             TripleFloat(camera.pos().x, camera.pos().y, camera.pos().z),
         );
+        uniforms.add(c_str!("uTileWidth"), Float(TEX_W as f32 / TEX_ATLAS_W as f32));
+        uniforms.add(c_str!("uTileHeight"), Float(TEX_H as f32 / TEX_ATLAS_H as f32));
         uniforms
     };
 
     field_vao.draw_triangles(&uniforms);
+    block_vao.draw_triangles(&uniforms);
     player_vao.draw_triangles(&uniforms);
 }
-
-#[derive(PartialEq, Debug)]
-enum ClientState {
-    /// タイトル画面
-    TitleScreen,
-    /// サーバーに接続してsetup-uidすべき状態
-    SettingConnection,
-    /// on-update-userイベントを待っている状態
-    WaitingSettingUid,
-    /// join-roomすべき状態
-    JoiningRoom,
-    /// ルームに入っていて、ゲーム開始を待っている状態
-    WaitingInRoom,
-    /// ゲーム中
-    Playing,
-    /// ゲームが終了して、結果を表示している状態
-    GameFinished { reason: GameFinishReason },
-    /// アプリケーションを終了すべき状態
-    Quit,
-}
-
-#[derive(PartialEq, Debug)]
-pub enum GameFinishReason {
-    Fall { winner_uid: Option<Uuid> },
-    Collision { winner_uid: Option<Uuid> },
-    Timeup,
-    Abnromal,
-}
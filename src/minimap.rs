@@ -0,0 +1,297 @@
+//! 画面の隅に出す、フィールド全体を真上から見た小さな地図
+//!
+//! フィールドの1マスを`CELL_PX`四方のピクセルにした画像をCPUで描き、テクスチャにして2DのGUIとして貼る。
+//! 向きは3Dの画面と同じで、上が+x方向、右が+z方向。画像の大きさはフィールドの大きさに合わせる。
+
+use std::os::raw::c_void;
+
+use re::gl;
+use re::gl::Gl;
+use re::texture::image_manager::ImageLoadInfo;
+use uuid::Uuid;
+
+use crate::camera::VisibleArea;
+use crate::player::{Player, Tagger};
+use crate::types::*;
+
+/// 1マスの大きさ(ピクセル)
+pub const CELL_PX: usize = 6;
+
+/// いちばん低いマスと高いマスの色。その間は高さに合わせて混ぜる
+const COLOR_LOW: [u8; 3] = [70, 80, 100];
+const COLOR_HIGH: [u8; 3] = [225, 230, 240];
+/// 名札と同じ色
+const COLOR_OWN: [u8; 3] = [25, 75, 205];
+const COLOR_OPPONENT: [u8; 3] = [205, 40, 25];
+const COLOR_DEAD: [u8; 3] = [128, 128, 128];
+const COLOR_TAGGER: [u8; 3] = [240, 150, 0];
+/// 画面に映っている範囲の枠
+const COLOR_VIEWPORT: [u8; 3] = [0, 0, 0];
+
+pub struct Minimap {
+    /// 最後に描いたフィールドの(x, z)方向のマス数
+    shape: (usize, usize),
+    /// RGBA。1行目が画像の上端
+    pixels: Vec<u8>,
+    /// `pixels`をテクスチャに送っていないかどうか
+    dirty: bool,
+    texture: Option<ImageLoadInfo<'static>>,
+}
+
+impl Default for Minimap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        Self {
+            shape: (0, 0),
+            pixels: Vec::new(),
+            dirty: true,
+            texture: None,
+        }
+    }
+
+    /// 画像の幅(ピクセル)
+    pub fn width(&self) -> usize {
+        self.shape.1 * CELL_PX
+    }
+
+    /// 画像の高さ(ピクセル)
+    pub fn height(&self) -> usize {
+        self.shape.0 * CELL_PX
+    }
+
+    /// 地図を描き直す
+    ///
+    /// マスの色は、そのときのフィールドで最も低いマスから最も高いマスまでの中での高さで決める。
+    /// 鬼、プレイヤーの順に上に重ね、最後に`viewport`の枠を描く。
+    pub fn update(
+        &mut self,
+        field: &FieldMatrix,
+        players: &[Player],
+        tagger: Option<&Tagger>,
+        own_uid: Option<Uuid>,
+        viewport: Option<&VisibleArea>,
+    ) {
+        if field.shape() != self.shape {
+            self.shape = field.shape();
+            self.pixels = vec![0; self.width() * self.height() * 4];
+        }
+        let min = field.min();
+        let max = field.max();
+        for x in 0..field.nrows() {
+            for z in 0..field.ncols() {
+                let t = if max > min {
+                    (field[(x, z)] - min) as f32 / (max - min) as f32
+                } else {
+                    0.5
+                };
+                let color = mix(COLOR_LOW, COLOR_HIGH, t);
+                self.fill_cell(&Point2i::new(x as i32, z as i32), 0, color);
+            }
+        }
+
+        if let Some(tagger) = tagger {
+            self.fill_cell(&tagger.pos, 1, COLOR_TAGGER);
+        }
+        for player in players {
+            let color = match (player.alive, Some(player.uid) == own_uid) {
+                (false, _) => COLOR_DEAD,
+                (true, true) => COLOR_OWN,
+                (true, false) => COLOR_OPPONENT,
+            };
+            self.fill_cell(&player.pos, 1, color);
+        }
+
+        if let Some(area) = viewport {
+            self.stroke_area(area, COLOR_VIEWPORT);
+        }
+        self.dirty = true;
+    }
+
+    /// 画像の左から`column`、上から`row`番目のピクセルの色
+    pub fn pixel(&self, column: usize, row: usize) -> [u8; 3] {
+        let i = (row * self.width() + column) * 4;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2]]
+    }
+
+    /// `update`で描いた画像のテクスチャ。初めて呼んだときにテクスチャを作る
+    pub fn texture(&mut self, gl: &Gl) -> &ImageLoadInfo<'static> {
+        let texture = self.texture.get_or_insert_with(|| {
+            let mut texture = 0;
+            unsafe {
+                gl.GenTextures(1, &mut texture);
+                gl.BindTexture(gl::TEXTURE_2D, texture);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+                // マスの境目をぼかさない
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+                gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+                gl.BindTexture(gl::TEXTURE_2D, 0);
+            }
+            ImageLoadInfo {
+                gl_id: texture,
+                id: "minimap",
+                width: 0,
+                height: 0,
+            }
+        });
+        if self.dirty {
+            texture.width = self.shape.1 as u32 * CELL_PX as u32;
+            texture.height = self.shape.0 as u32 * CELL_PX as u32;
+            unsafe {
+                gl.BindTexture(gl::TEXTURE_2D, texture.gl_id);
+                gl.TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGBA as i32,
+                    texture.width as i32,
+                    texture.height as i32,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    self.pixels.as_ptr() as *const c_void,
+                );
+                gl.BindTexture(gl::TEXTURE_2D, 0);
+            }
+            self.dirty = false;
+        }
+        texture
+    }
+
+    /// フィールドの`pos`のマスを、周りを`inset`ピクセル空けて塗る
+    fn fill_cell(&mut self, pos: &Point2i, inset: usize, color: [u8; 3]) {
+        let (rows, columns) = self.shape;
+        if !(0..rows as i32).contains(&pos.x) || !(0..columns as i32).contains(&pos.y) {
+            return;
+        }
+        // 画像の上端が+x方向の端
+        let top = (rows - 1 - pos.x as usize) * CELL_PX;
+        let left = pos.y as usize * CELL_PX;
+        for row in top + inset..top + CELL_PX - inset {
+            for column in left + inset..left + CELL_PX - inset {
+                self.set_pixel(column, row, color);
+            }
+        }
+    }
+
+    /// ワールド座標の範囲`area`の枠を、画像からはみ出す部分は切って描く
+    fn stroke_area(&mut self, area: &VisibleArea, color: [u8; 3]) {
+        let to_pixel = |value: f32| (value * CELL_PX as f32).round() as i32;
+        let rows = self.shape.0 as f32;
+        let top = to_pixel(rows - area.max_x);
+        let bottom = to_pixel(rows - area.min_x) - 1;
+        let left = to_pixel(area.min_z);
+        let right = to_pixel(area.max_z) - 1;
+        let (width, height) = (self.width() as i32, self.height() as i32);
+
+        for column in left.max(0)..=right.min(width - 1) {
+            for &row in &[top, bottom] {
+                if (0..height).contains(&row) {
+                    self.set_pixel(column as usize, row as usize, color);
+                }
+            }
+        }
+        for row in top.max(0)..=bottom.min(height - 1) {
+            for &column in &[left, right] {
+                if (0..width).contains(&column) {
+                    self.set_pixel(column as usize, row as usize, color);
+                }
+            }
+        }
+    }
+
+    fn set_pixel(&mut self, column: usize, row: usize, color: [u8; 3]) {
+        let i = (row * self.width() + column) * 4;
+        self.pixels[i..i + 4].copy_from_slice(&[color[0], color[1], color[2], 255]);
+    }
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], t: f32) -> [u8; 3] {
+    let channel = |i: usize| (a[i] as f32 + (b[i] as f32 - a[i] as f32) * t).round() as u8;
+    [channel(0), channel(1), channel(2)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FIELD_SIZE;
+
+    /// フィールドの(`x`, `z`)のマスの中央のピクセル
+    fn cell_center(minimap: &Minimap, x: usize, z: usize) -> [u8; 3] {
+        let row = (minimap.shape.0 - 1 - x) * CELL_PX + CELL_PX / 2;
+        minimap.pixel(z * CELL_PX + CELL_PX / 2, row)
+    }
+
+    #[test]
+    fn height_shading_and_markers() {
+        let mut field = FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 2);
+        field[(0, 0)] = 0;
+        field[(31, 31)] = 4;
+        let own = Player::new(Point2i::new(5, 6), Uuid::from_u128(1), "a".to_string());
+        let mut dead = Player::new(Point2i::new(7, 8), Uuid::from_u128(2), "b".to_string());
+        dead.alive = false;
+        let tagger = Tagger::new(Point2i::new(31, 0));
+
+        let mut minimap = Minimap::new();
+        minimap.update(
+            &field,
+            &[own, dead],
+            Some(&tagger),
+            Some(Uuid::from_u128(1)),
+            None,
+        );
+
+        assert_eq!(cell_center(&minimap, 0, 0), COLOR_LOW);
+        assert_eq!(cell_center(&minimap, 31, 31), COLOR_HIGH);
+        assert_eq!(cell_center(&minimap, 1, 1), mix(COLOR_LOW, COLOR_HIGH, 0.5));
+        assert_eq!(cell_center(&minimap, 5, 6), COLOR_OWN);
+        assert_eq!(cell_center(&minimap, 7, 8), COLOR_DEAD);
+        // +x方向の端は画像の上端
+        assert_eq!(minimap.pixel(CELL_PX / 2, CELL_PX / 2), COLOR_TAGGER);
+        // 印の周りにはマスの色が残る
+        assert_eq!(minimap.pixel(0, 0), mix(COLOR_LOW, COLOR_HIGH, 0.5));
+    }
+
+    #[test]
+    fn viewport_outline_is_clipped() {
+        let field = FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE);
+        let area = VisibleArea {
+            min_x: 28.0,
+            max_x: 40.0,
+            min_z: 2.0,
+            max_z: 10.0,
+        };
+        let mut minimap = Minimap::new();
+        minimap.update(&field, &[], None, None, Some(&area));
+
+        let flat = mix(COLOR_LOW, COLOR_HIGH, 0.5);
+        let bottom = 4 * CELL_PX - 1;
+        assert_eq!(minimap.pixel(2 * CELL_PX, 0), COLOR_VIEWPORT);
+        assert_eq!(minimap.pixel(10 * CELL_PX - 1, bottom), COLOR_VIEWPORT);
+        assert_eq!(minimap.pixel(5 * CELL_PX, bottom), COLOR_VIEWPORT);
+        // 上の辺は画像の外なので描かない
+        assert_eq!(minimap.pixel(5 * CELL_PX, 0), flat);
+        assert_eq!(minimap.pixel(5 * CELL_PX, bottom + 1), flat);
+    }
+
+    #[test]
+    fn image_follows_field_shape() {
+        let mut minimap = Minimap::new();
+        minimap.update(&FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE), &[], None, None, None);
+        assert_eq!(minimap.width(), FIELD_SIZE * CELL_PX);
+
+        // 縦に4マス、横に10マス
+        let tagger = Tagger::new(Point2i::new(3, 9));
+        minimap.update(&FieldMatrix::zeros(4, 10), &[], Some(&tagger), None, None);
+        assert_eq!((minimap.width(), minimap.height()), (10 * CELL_PX, 4 * CELL_PX));
+        assert_eq!(cell_center(&minimap, 3, 9), COLOR_TAGGER);
+        assert_eq!(
+            minimap.pixel(9 * CELL_PX + CELL_PX / 2, CELL_PX / 2),
+            COLOR_TAGGER
+        );
+    }
+}
@@ -0,0 +1,472 @@
+//! サーバーなしで遊ぶ1人用のゲーム
+//!
+//! `update-field`から読み取れるルール(高さのあるフィールド、鬼の追跡、段差からの落下、
+//! 鬼との衝突、時間切れ)をクライアントで再現し、`ApiClient`と同じ`ApiEvent`を`EventQueue`に積む。
+//! 鬼はAIが動かす。サーバーとは`GameTransport`を通してやり取りするので、画面の流れは通常と変わらない。
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use uuid::Uuid;
+
+use crate::api::client::{ApiEvent, RoomChoice, RoomSummary};
+use crate::api::event_queue::SharedEventQueue;
+use crate::api::json::DirectionJson;
+use crate::api::transport::GameTransport;
+use crate::bot::{is_safe_step, manhattan_distance, DIRECTIONS};
+use crate::player::{Player, Tagger};
+use crate::types::*;
+use crate::world::{in_field, moved};
+use crate::{GameFinishReason, FIELD_SIZE};
+
+/// 1tickの長さ
+pub const TICK_INTERVAL: Duration = Duration::from_millis(200);
+/// この数のtickが経過すると時間切れで終了する(1分)
+pub const TIME_LIMIT_TICKS: i32 = 300;
+/// 鬼は何tickごとに1マス動くか
+pub const TAGGER_MOVE_INTERVAL: i32 = 2;
+/// フィールドの高さの上限
+pub const MAX_HEIGHT: i32 = 8;
+
+const ROOM_ID: &str = "offline";
+const PLAYER_NAME: &str = "you";
+/// 自機と鬼は、フィールドの端からこの割合だけ内側の、向かい合う隅の近くから始める
+const START_MARGIN_RATIO: usize = 8;
+
+/// 1人用のゲーム
+///
+/// `GameTransport`として、サーバーの代わりに返事をする。
+/// ルームに入ると`poll`のたびに`TICK_INTERVAL`ごとにゲームを進めて`UpdateField`を積む。
+pub struct OfflineGame {
+    /// 接続していなければ`None`
+    queue: Option<SharedEventQueue>,
+    uid: Uuid,
+    rng: StdRng,
+    /// ルームに入っていなければ`None`
+    game: Option<Game>,
+    /// 最後にtickを進めてからの時間
+    elapsed: Duration,
+}
+
+impl OfflineGame {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            queue: None,
+            uid: Uuid::nil(),
+            rng: StdRng::seed_from_u64(seed),
+            game: None,
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    /// 新しいフィールドでゲームを始める
+    fn start_game(&mut self) {
+        self.game = Some(Game::new(generate_field(&mut self.rng)));
+        self.elapsed = Duration::from_secs(0);
+        self.push(vec![ApiEvent::RoomStateFulfilled {
+            room_id: ROOM_ID.to_string(),
+            room_name: ROOM_ID.to_string(),
+            should_start: true,
+        }]);
+    }
+
+    fn push(&self, events: Vec<ApiEvent>) {
+        if let Some(ref queue) = self.queue {
+            queue.lock().unwrap_or_log().extend(events);
+        }
+    }
+
+    fn queue(&self) -> Result<&SharedEventQueue, Box<dyn Error>> {
+        self.queue.as_ref().ok_or_else(|| "not connected".into())
+    }
+}
+
+impl GameTransport for OfflineGame {
+    fn connect(&mut self, queue: &SharedEventQueue) -> Result<(), Box<dyn Error>> {
+        self.queue = Some(Arc::clone(queue));
+        Ok(())
+    }
+
+    fn setup_uid(&mut self, uid: Uuid) -> Result<(), Box<dyn Error>> {
+        self.queue()?;
+        self.uid = uid;
+        self.push(vec![ApiEvent::UpdateUser {
+            uid,
+            name: PLAYER_NAME.to_string(),
+        }]);
+        Ok(())
+    }
+
+    fn find_available_rooms(&mut self) -> Result<(), Box<dyn Error>> {
+        self.queue()?;
+        self.push(vec![ApiEvent::AvailableRooms {
+            rooms: vec![RoomSummary {
+                room_id: ROOM_ID.to_string(),
+                room_name: ROOM_ID.to_string(),
+                member_count: 0,
+                max_member: 1,
+            }],
+        }]);
+        Ok(())
+    }
+
+    /// どのルームを選んでも1人用のゲームを始める
+    fn join_room(&mut self, _choice: &RoomChoice) -> Result<(), Box<dyn Error>> {
+        self.queue()?;
+        self.start_game();
+        Ok(())
+    }
+
+    /// 次のtickで動く
+    fn try_move(&mut self, direction: &DirectionJson) -> Result<(), Box<dyn Error>> {
+        let game = self.game.as_mut().ok_or("not in game")?;
+        game.pending_move = Some(*direction);
+        Ok(())
+    }
+
+    fn restart(&mut self) -> Result<(), Box<dyn Error>> {
+        self.game.as_ref().ok_or("not in room")?;
+        self.start_game();
+        Ok(())
+    }
+
+    /// ルームを出ると1人用のゲームは終わりなので、接続も切る
+    fn leave(&mut self) -> Result<(), Box<dyn Error>> {
+        self.queue()?;
+        self.push(vec![ApiEvent::RoomStateNotJoined]);
+        self.disconnect()
+    }
+
+    fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.queue()?;
+        self.queue = None;
+        self.game = None;
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    fn poll(&mut self, elapsed: Duration) {
+        let uid = self.uid;
+        let game = match self.game {
+            Some(ref mut game) if !game.finished => game,
+            _ => return,
+        };
+        self.elapsed += elapsed;
+        let mut events = Vec::new();
+        while self.elapsed >= TICK_INTERVAL && !game.finished {
+            self.elapsed -= TICK_INTERVAL;
+            events.extend(game.tick(uid));
+        }
+        self.push(events);
+    }
+}
+
+/// 段差のあるフィールドを作る。ところどころに落ちると危ない崖ができる
+fn generate_field(rng: &mut StdRng) -> FieldMatrix {
+    let mut field = FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE);
+    for _ in 0..16 {
+        let width = rng.gen_range(3..9);
+        let depth = rng.gen_range(3..9);
+        let x = rng.gen_range(0..FIELD_SIZE - width);
+        let z = rng.gen_range(0..FIELD_SIZE - depth);
+        for x in x..x + width {
+            for z in z..z + depth {
+                field[(x, z)] = (field[(x, z)] + 1).min(3);
+            }
+        }
+    }
+    field
+}
+
+/// 1ゲーム分の状態とルール
+struct Game {
+    tick: i32,
+    field: FieldMatrix,
+    player: Point2i,
+    tagger: Point2i,
+    /// 次のtickで動く方向
+    pending_move: Option<DirectionJson>,
+    finished: bool,
+}
+
+/// `field`での(自機, 鬼)の最初の位置
+fn start_positions(field: &FieldMatrix) -> (Point2i, Point2i) {
+    let (rows, columns) = field.shape();
+    let (x, z) = (rows / START_MARGIN_RATIO, columns / START_MARGIN_RATIO);
+    (
+        Point2i::new(x as i32, z as i32),
+        Point2i::new((rows - 1 - x) as i32, (columns - 1 - z) as i32),
+    )
+}
+
+impl Game {
+    fn new(field: FieldMatrix) -> Self {
+        let (player, tagger) = start_positions(&field);
+        Self {
+            tick: 0,
+            field,
+            player,
+            tagger,
+            pending_move: None,
+            finished: false,
+        }
+    }
+
+    /// 1tick進めて、`update-field`に当たるイベントを返す
+    fn tick(&mut self, uid: Uuid) -> Vec<ApiEvent> {
+        self.tick += 1;
+        let finish = self.move_player().or_else(|| {
+            if self.tick % TAGGER_MOVE_INTERVAL == 0 {
+                self.move_tagger();
+            }
+            if self.tagger == self.player {
+                Some(GameFinishReason::Collision { winner_uid: None })
+            } else if self.tick >= TIME_LIMIT_TICKS {
+                Some(GameFinishReason::Timeup)
+            } else {
+                None
+            }
+        });
+
+        let mut events = vec![ApiEvent::UpdateField {
+            tick_count: self.tick,
+            players: vec![Player::new(self.player, uid, PLAYER_NAME.to_string())],
+            tagger: Tagger::new(self.tagger),
+            field: self.field.clone(),
+        }];
+        if let Some(reason) = finish {
+            self.finished = true;
+            events.push(ApiEvent::GameFinished { reason });
+        }
+        events
+    }
+
+    /// 自機を動かす。元いたマスは1段高くなる。落下するか鬼にぶつかったら終了理由を返す
+    fn move_player(&mut self) -> Option<GameFinishReason> {
+        let direction = self.pending_move.take()?;
+        let next = moved(&self.player, direction);
+        if !in_field(&self.field, &next) {
+            return None;
+        }
+        if !is_safe_step(&self.field, &self.player, &next) {
+            self.player = next;
+            return Some(GameFinishReason::Fall { winner_uid: None });
+        }
+        let (x, z) = (self.player.x as usize, self.player.y as usize);
+        self.field[(x, z)] = (self.field[(x, z)] + 1).min(MAX_HEIGHT);
+        self.player = next;
+        if self.player == self.tagger {
+            return Some(GameFinishReason::Collision { winner_uid: None });
+        }
+        None
+    }
+
+    /// 鬼を自機に1マス近づける
+    ///
+    /// 落ちない段差だけを通る最短経路を探し、行き止まりなら高さを気にせず近づく
+    fn move_tagger(&mut self) {
+        let direction = self.path_to_player().or_else(|| {
+            DIRECTIONS
+                .iter()
+                .copied()
+                .filter(|&direction| in_field(&self.field, &moved(&self.tagger, direction)))
+                .min_by_key(|&direction| {
+                    manhattan_distance(&moved(&self.tagger, direction), &self.player)
+                })
+        });
+        if let Some(direction) = direction {
+            self.tagger = moved(&self.tagger, direction);
+        }
+    }
+
+    /// 幅優先探索で、自機へ向かう最初の一手を求める
+    fn path_to_player(&self) -> Option<DirectionJson> {
+        let mut visited = na::DMatrix::from_element(self.field.nrows(), self.field.ncols(), false);
+        visited[(self.tagger.x as usize, self.tagger.y as usize)] = true;
+        let mut queue = VecDeque::new();
+        queue.push_back((self.tagger, None));
+        while let Some((pos, first_move)) = queue.pop_front() {
+            if pos == self.player {
+                return first_move;
+            }
+            for &direction in DIRECTIONS.iter() {
+                let next = moved(&pos, direction);
+                if !is_safe_step(&self.field, &pos, &next) {
+                    continue;
+                }
+                let (x, z) = (next.x as usize, next.y as usize);
+                if visited[(x, z)] {
+                    continue;
+                }
+                visited[(x, z)] = true;
+                queue.push_back((next, first_move.or(Some(direction))));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_with(field: FieldMatrix, player: Point2i, tagger: Point2i) -> Game {
+        let mut game = Game::new(field);
+        game.player = player;
+        game.tagger = tagger;
+        game
+    }
+
+    fn finish_reason(events: &[ApiEvent]) -> Option<&GameFinishReason> {
+        events.iter().find_map(|event| match event {
+            ApiEvent::GameFinished { reason } => Some(reason),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn leaving_square_rises() {
+        let uid = Uuid::new_v4();
+        let mut game = game_with(
+            FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            Point2i::new(5, 5),
+            Point2i::new(30, 30),
+        );
+        game.pending_move = Some(DirectionJson::Up);
+        let events = game.tick(uid);
+
+        assert_eq!(game.player, Point2i::new(6, 5));
+        assert_eq!(game.field[(5, 5)], 1);
+        assert!(matches!(
+            events.as_slice(),
+            [ApiEvent::UpdateField { tick_count: 1, players, .. }]
+                if players[0].uid == uid && players[0].name == PLAYER_NAME
+        ));
+    }
+
+    #[test]
+    fn start_positions_fit_field() {
+        let field = FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE);
+        assert_eq!(
+            start_positions(&field),
+            (Point2i::new(4, 4), Point2i::new(27, 27))
+        );
+
+        // 小さいフィールドや長方形のフィールドでも、フィールドの中の別々のマスから始める
+        for &(rows, columns) in [(1, 2), (5, 3), (12, 40)].iter() {
+            let field = FieldMatrix::zeros(rows, columns);
+            let (player, tagger) = start_positions(&field);
+            assert!(in_field(&field, &player));
+            assert!(in_field(&field, &tagger));
+            assert_ne!(player, tagger);
+        }
+    }
+
+    #[test]
+    fn fall_from_high_step() {
+        let mut field = FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE);
+        field[(5, 6)] = 2;
+        let mut game = game_with(field, Point2i::new(5, 5), Point2i::new(30, 30));
+        game.pending_move = Some(DirectionJson::Right);
+        let events = game.tick(Uuid::new_v4());
+
+        assert_eq!(
+            finish_reason(&events),
+            Some(&GameFinishReason::Fall { winner_uid: None })
+        );
+        assert!(game.finished);
+    }
+
+    #[test]
+    fn tagger_chases_around_cliff() {
+        // 鬼と自機の間に崖があるので、回り込んで近づく
+        let field = FieldMatrix::from_fn(
+            FIELD_SIZE,
+            FIELD_SIZE,
+            |x, z| if z == 10 && x > 3 { 5 } else { 0 },
+        );
+        let mut game = game_with(field, Point2i::new(8, 12), Point2i::new(8, 8));
+        game.move_tagger();
+        assert_eq!(game.tagger, Point2i::new(7, 8));
+    }
+
+    #[test]
+    fn tagger_catches_player() {
+        let mut game = game_with(
+            FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            Point2i::new(5, 5),
+            Point2i::new(5, 7),
+        );
+        let mut events = Vec::new();
+        while !game.finished {
+            events = game.tick(Uuid::new_v4());
+        }
+        assert_eq!(
+            finish_reason(&events),
+            Some(&GameFinishReason::Collision { winner_uid: None })
+        );
+        assert_eq!(game.tick, TAGGER_MOVE_INTERVAL * 2);
+    }
+
+    #[test]
+    fn timeup() {
+        let mut game = game_with(
+            FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
+            Point2i::new(5, 5),
+            Point2i::new(5, 30),
+        );
+        // 鬼が近づいてこないように毎tick戻す
+        let mut events = Vec::new();
+        while !game.finished {
+            game.tagger = Point2i::new(5, 30);
+            events = game.tick(Uuid::new_v4());
+        }
+        assert_eq!(finish_reason(&events), Some(&GameFinishReason::Timeup));
+        assert_eq!(game.tick, TIME_LIMIT_TICKS);
+    }
+
+    #[test]
+    fn play_through_transport() {
+        let queue = crate::api::event_queue::EventQueue::new_shared();
+        let uid = Uuid::new_v4();
+        let mut offline_game = OfflineGame::new(0);
+        assert!(offline_game.setup_uid(uid).is_err());
+
+        offline_game.connect(&queue).unwrap();
+        offline_game.setup_uid(uid).unwrap();
+        // ルームに入るまではゲームが進まない
+        offline_game.poll(TICK_INTERVAL * 3);
+        offline_game.join_room(&RoomChoice::Any).unwrap();
+        offline_game.poll(TICK_INTERVAL * 3);
+
+        let events = queue.lock().unwrap().drain();
+        assert!(matches!(events[0], ApiEvent::UpdateUser { uid: id, .. } if id == uid));
+        assert!(matches!(
+            events[1],
+            ApiEvent::RoomStateFulfilled {
+                should_start: true,
+                ..
+            }
+        ));
+        // 溜まったupdate-fieldはキューで最新のものにまとめられる
+        assert!(matches!(
+            events[2..],
+            [ApiEvent::UpdateField { tick_count: 3, .. }]
+        ));
+
+        offline_game.leave().unwrap();
+        assert!(matches!(
+            queue.lock().unwrap().drain().as_slice(),
+            [ApiEvent::RoomStateNotJoined]
+        ));
+        assert!(!offline_game.is_connected());
+        assert!(offline_game.try_move(&DirectionJson::Up).is_err());
+    }
+}
@@ -1,20 +1,30 @@
 use crate::types::*;
 use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Player {
     pub pos: Point2i,
     pub uid: Uuid,
     pub name: String,
+    pub point: i32,
+    /// 落下したりつかまったりしていないかどうか
+    pub alive: bool,
 }
 
 impl Player {
+    /// 0点で生きているプレイヤー
     pub fn new(pos: Point2i, uid: Uuid, name: String) -> Self {
-        Self { pos, uid, name }
+        Self {
+            pos,
+            uid,
+            name,
+            point: 0,
+            alive: true,
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Tagger {
     pub pos: Point2i,
 }
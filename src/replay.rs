@@ -0,0 +1,449 @@
+//! 対戦の記録と再生
+//!
+//! サーバーから受け取ったペイロードを、受け取った時刻とイベント名と一緒にそのまま記録する。
+//! 再生するときは記録したペイロードを`decode::decode_event`に通して`ApiEvent`に戻すので、
+//! 通常のゲームと同じ経路で`World`などに反映される。
+//!
+//! ファイルはgzipで圧縮したテキストで、1行が1イベント。
+//!
+//! ```text
+//! # blocking-io replay v1
+//! <記録開始からのミリ秒>\t<イベント名>\t<ペイロードのJSON>
+//! ```
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::api::client::{event, ApiEvent};
+use crate::api::decode::decode_event;
+
+const HEADER: &str = "# blocking-io replay v1";
+
+/// 再生速度の段階
+const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+
+/// socket.ioのスレッドから書き込むための`Recorder`。記録していなければ`None`
+pub type SharedRecorder = Arc<Mutex<Option<Recorder>>>;
+
+/// 記録を保存するディレクトリ。exeと同じディレクトリの`replays`
+pub fn default_dir() -> io::Result<PathBuf> {
+    let mut dir = env::current_exe()?;
+    dir.pop();
+    dir.push("replays");
+    Ok(dir)
+}
+
+/// 受け取ったペイロードを記録する
+pub struct Recorder<W: Write = GzEncoder<BufWriter<File>>> {
+    writer: W,
+    started: Instant,
+}
+
+impl Recorder {
+    /// `dir`に現在時刻から名前を付けたファイルを作って記録を始める
+    pub fn create(dir: &Path) -> io::Result<(Self, PathBuf)> {
+        fs::create_dir_all(dir)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = dir.join(format!("{}.blkio-replay.gz", now));
+        let file = BufWriter::new(File::create(&path)?);
+        let recorder = Self::new(GzEncoder::new(file, Compression::default()))?;
+        Ok((recorder, path))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", HEADER)?;
+        Ok(Self {
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: &str, raw: &[u8]) -> io::Result<()> {
+        let t_ms = self.started.elapsed().as_millis();
+        // 1行に収まるように詰め直す。JSONでなければ改行とタブだけ潰す
+        let raw = match serde_json::from_slice::<serde_json::Value>(raw) {
+            Ok(json) => json.to_string(),
+            Err(_) => String::from_utf8_lossy(raw).replace(&['\n', '\r', '\t'][..], " "),
+        };
+        writeln!(self.writer, "{}\t{}\t{}", t_ms, event, raw)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Recorder<GzEncoder<W>> {
+    /// gzipの末尾まで書き出して記録を終える。途中で終了すると最後のほうの記録が失われる
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.writer.finish()?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+/// 記録された1イベント
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// 記録を始めてからのミリ秒
+    pub t_ms: u64,
+    pub event: String,
+    pub raw: String,
+}
+
+impl Entry {
+    fn decode(&self) -> Vec<ApiEvent> {
+        decode_event(&self.event, self.raw.as_bytes()).unwrap_or_else(|| {
+            tracing::warn!("unknown event in replay: {}", self.event);
+            Vec::new()
+        })
+    }
+
+    fn is_update_field(&self) -> bool {
+        self.event == event::UPDATE_FIELD
+    }
+}
+
+/// 記録ファイルの中身
+#[derive(Debug, Default)]
+pub struct Recording {
+    pub entries: Vec<Entry>,
+}
+
+impl Recording {
+    /// `Recorder::create`で作ったファイルを読み込む
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(BufReader::new(GzDecoder::new(File::open(path)?)))
+    }
+
+    /// 記録を読み込む。ファイルが途中で切れていたら、そこまでに読めたイベントを返す
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                    tracing::warn!("replay is truncated after line {}", number);
+                    break;
+                }
+                Err(error) => return Err(error),
+            };
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid replay line {}", number + 1),
+                )
+            };
+            let mut fields = line.splitn(3, '\t');
+            let t_ms = fields.next().and_then(|t| t.parse().ok()).ok_or_else(invalid)?;
+            let event = fields.next().ok_or_else(invalid)?.to_string();
+            let raw = fields.next().ok_or_else(invalid)?.to_string();
+            entries.push(Entry { t_ms, event, raw });
+        }
+        Ok(Self { entries })
+    }
+
+    /// 最後のイベントの時刻
+    pub fn duration_ms(&self) -> u64 {
+        self.entries.last().map_or(0, |entry| entry.t_ms)
+    }
+}
+
+/// 記録を再生する
+///
+/// 一時停止、コマ送り(`update-field`単位)、シーク、速度変更ができる。
+/// シークやコマ戻しで返すイベントは過去のtickに戻ることがあるので、
+/// `EventQueue`に積む前に`EventQueue::reset_ticks`を呼ぶこと。
+pub struct ReplayPlayer {
+    recording: Recording,
+    /// 次に再生するエントリ
+    next: usize,
+    /// 再生位置(ミリ秒)
+    position_ms: f64,
+    speed: usize,
+    paused: bool,
+}
+
+impl ReplayPlayer {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            next: 0,
+            position_ms: 0.0,
+            speed: NORMAL_SPEED,
+            paused: false,
+        }
+    }
+
+    /// 経過時間だけ再生位置を進め、その間に記録されていたイベントを返す
+    pub fn update(&mut self, elapsed: Duration) -> Vec<ApiEvent> {
+        if self.paused {
+            return Vec::new();
+        }
+        self.position_ms += elapsed.as_secs_f64() * 1000.0 * SPEEDS[self.speed];
+        let end = self.next
+            + self.recording.entries[self.next..]
+                .iter()
+                .take_while(|entry| entry.t_ms as f64 <= self.position_ms)
+                .count();
+        let events = self.decode_range(self.next, end);
+        self.next = end;
+        if self.is_finished() {
+            self.position_ms = self.duration_ms() as f64;
+        }
+        events
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    pub fn speed(&self) -> f64 {
+        SPEEDS[self.speed]
+    }
+
+    /// 一時停止して、次の`update-field`まで進める
+    pub fn step_forward(&mut self) -> Vec<ApiEvent> {
+        self.paused = true;
+        let target = self.recording.entries[self.next..]
+            .iter()
+            .position(Entry::is_update_field)
+            .map(|offset| self.next + offset);
+        match target {
+            Some(target) => {
+                let events = self.decode_range(self.next, target + 1);
+                self.next = target + 1;
+                self.position_ms = self.recording.entries[target].t_ms as f64;
+                events
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// 一時停止して、1つ前の`update-field`まで戻る
+    pub fn step_backward(&mut self) -> Vec<ApiEvent> {
+        self.paused = true;
+        let mut update_fields = self.recording.entries[..self.next]
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, entry)| entry.is_update_field())
+            .map(|(index, _)| index);
+        // 1つ目は表示中のもの
+        update_fields.next();
+        match update_fields.next() {
+            Some(target) => self.seek_to_entry(target),
+            None => Vec::new(),
+        }
+    }
+
+    /// 再生位置を変える。その時点の状態を表すイベントを返す
+    pub fn seek(&mut self, position_ms: f64) -> Vec<ApiEvent> {
+        let position_ms = position_ms.max(0.0).min(self.duration_ms() as f64);
+        let end = self
+            .recording
+            .entries
+            .iter()
+            .take_while(|entry| entry.t_ms as f64 <= position_ms)
+            .count();
+        let last_update_field = self.recording.entries[..end]
+            .iter()
+            .rposition(Entry::is_update_field);
+        let events = match last_update_field {
+            Some(target) => self.seek_to_entry(target),
+            None => Vec::new(),
+        };
+        self.next = end;
+        self.position_ms = position_ms;
+        events
+    }
+
+    /// `index`のエントリの直後に移動し、その`update-field`と直前のユーザー情報を返す
+    fn seek_to_entry(&mut self, index: usize) -> Vec<ApiEvent> {
+        let mut events = Vec::new();
+        if let Some(update_user) = self.recording.entries[..index]
+            .iter()
+            .rposition(|entry| entry.event == event::UPDATE_USER)
+        {
+            events.extend(self.recording.entries[update_user].decode());
+        }
+        events.extend(self.recording.entries[index].decode());
+        self.next = index + 1;
+        self.position_ms = self.recording.entries[index].t_ms as f64;
+        events
+    }
+
+    fn decode_range(&self, begin: usize, end: usize) -> Vec<ApiEvent> {
+        self.recording.entries[begin..end]
+            .iter()
+            .flat_map(Entry::decode)
+            .collect()
+    }
+
+    pub fn position_ms(&self) -> f64 {
+        self.position_ms
+    }
+
+    pub fn duration_ms(&self) -> u64 {
+        self.recording.duration_ms()
+    }
+
+    /// 再生位置の割合(0.0〜1.0)
+    pub fn progress(&self) -> f32 {
+        match self.duration_ms() {
+            0 => 1.0,
+            duration => (self.position_ms / duration as f64) as f32,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.recording.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FIELD_SIZE;
+
+    fn update_field(tick_count: i32) -> String {
+        let squares = vec![vec![serde_json::json!({ "height": 0 }); FIELD_SIZE]; FIELD_SIZE];
+        serde_json::json!({
+            "winner": null,
+            "tickCount": tick_count,
+            "battleField": { "length": FIELD_SIZE, "squares": squares },
+            "listOfPlayer": [],
+            "tagger": { "position": { "row": 0, "column": 0 }, "direction": "down", "name": "tagger" },
+            "state": "InGame",
+            "finishReason": null,
+        })
+        .to_string()
+    }
+
+    fn recording(ticks: i32) -> Recording {
+        let mut entries = vec![Entry {
+            t_ms: 0,
+            event: event::UPDATE_USER.to_string(),
+            raw: r#"{"name":"a","point":0,"uid":"7c9e6679-7425-40de-944b-e07fc1f90ae7","socketId":"s","requestingToStartGame":false}"#.to_string(),
+        }];
+        for tick in 1..=ticks {
+            entries.push(Entry {
+                t_ms: tick as u64 * 500,
+                event: event::UPDATE_FIELD.to_string(),
+                raw: update_field(tick),
+            });
+        }
+        Recording { entries }
+    }
+
+    fn ticks(events: &[ApiEvent]) -> Vec<i32> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                ApiEvent::UpdateField { tick_count, .. } => Some(*tick_count),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn record_and_read() {
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        recorder
+            .record(event::ROOM_STATE, b"{\n  \"state\": \"notJoining\"\n}")
+            .unwrap();
+        recorder.record(event::UPDATE_FIELD, b"not\tjson").unwrap();
+        let written = recorder.into_inner();
+
+        let recording = Recording::read(&written[..]).unwrap();
+        assert_eq!(recording.entries.len(), 2);
+        assert_eq!(recording.entries[0].event, event::ROOM_STATE);
+        assert_eq!(recording.entries[0].raw, r#"{"state":"notJoining"}"#);
+        assert_eq!(recording.entries[1].raw, "not json");
+    }
+
+    #[test]
+    fn read_truncated_file() {
+        let mut recorder = Recorder::new(GzEncoder::new(Vec::new(), Compression::default())).unwrap();
+        for tick in 1..=3 {
+            recorder
+                .record(event::UPDATE_FIELD, update_field(tick).as_bytes())
+                .unwrap();
+        }
+        let written = recorder.finish().unwrap();
+        let read = |bytes: &[u8]| Recording::read(BufReader::new(GzDecoder::new(bytes)));
+        assert_eq!(read(&written).unwrap().entries.len(), 3);
+
+        // gzipの末尾(CRCと長さの8バイト)がない
+        let recording = read(&written[..written.len() - 8]).unwrap();
+        assert_eq!(recording.entries.len(), 3);
+        // 圧縮されたデータも途中で切れている
+        let recording = read(&written[..written.len() / 2]).unwrap();
+        assert!(recording.entries.len() < 3);
+    }
+
+    #[test]
+    fn play_at_speed() {
+        let mut player = ReplayPlayer::new(recording(4));
+        let events = player.update(Duration::from_millis(600));
+        assert!(matches!(events[0], ApiEvent::UpdateUser { .. }));
+        assert_eq!(ticks(&events), vec![1]);
+
+        player.faster();
+        assert_eq!(ticks(&player.update(Duration::from_millis(500))), vec![2, 3]);
+
+        player.toggle_pause();
+        assert!(player.update(Duration::from_secs(10)).is_empty());
+        player.toggle_pause();
+        assert_eq!(ticks(&player.update(Duration::from_secs(10))), vec![4]);
+        assert!(player.is_finished());
+        assert_eq!(player.progress(), 1.0);
+    }
+
+    #[test]
+    fn step_and_seek() {
+        let mut player = ReplayPlayer::new(recording(5));
+        assert_eq!(ticks(&player.step_forward()), vec![1]);
+        assert_eq!(ticks(&player.step_forward()), vec![2]);
+        assert!(player.is_paused());
+        assert_eq!(ticks(&player.step_backward()), vec![1]);
+        assert!(ticks(&player.step_backward()).is_empty());
+
+        let events = player.seek(1800.0);
+        assert!(matches!(events[0], ApiEvent::UpdateUser { .. }));
+        assert_eq!(ticks(&events), vec![3]);
+        assert_eq!(ticks(&player.step_forward()), vec![4]);
+
+        assert_eq!(ticks(&player.seek(0.0)), Vec::<i32>::new());
+        assert_eq!(ticks(&player.seek(99999.0)), vec![5]);
+    }
+}
@@ -0,0 +1,178 @@
+//! ルーム選択画面の状態
+
+use crate::api::client::{RoomChoice, RoomSummary};
+
+/// 新しく作るルームの名前の最大文字数
+pub const NEW_ROOM_NAME_MAX_CHARS: usize = 16;
+
+/// ルーム選択画面
+///
+/// `find-available-room`で見つかったルームの行と、その下の「新しいルームを作る」行からなる。
+pub struct RoomBrowser {
+    rooms: Vec<RoomSummary>,
+    /// 選択中の行。`rooms.len()`なら新しいルームを作る行
+    selected: usize,
+    new_room_name: String,
+}
+
+impl RoomBrowser {
+    pub fn new() -> Self {
+        Self {
+            rooms: Vec::new(),
+            selected: 0,
+            new_room_name: String::new(),
+        }
+    }
+
+    /// ルームの一覧を更新する。選択中のルームがまだあれば、その行を選択したままにする
+    ///
+    /// 一覧が空だった間は新しいルームを作る行しかないので、名前を入力し始めていなければ
+    /// 先頭のルームを選択し直す。
+    pub fn set_rooms(&mut self, rooms: Vec<RoomSummary>) {
+        let selected_id = self.selected_room().map(|room| room.room_id.clone());
+        let creating = self.is_creating() && (!self.rooms.is_empty() || !self.new_room_name.is_empty());
+        self.rooms = rooms;
+        self.selected = match selected_id {
+            Some(id) => self.rooms.iter().position(|room| room.room_id == id).unwrap_or(0),
+            None if creating => self.rooms.len(),
+            None => 0,
+        };
+    }
+
+    pub fn rooms(&self) -> &[RoomSummary] {
+        &self.rooms
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_room(&self) -> Option<&RoomSummary> {
+        self.rooms.get(self.selected)
+    }
+
+    /// 新しいルームを作る行を選択しているかどうか
+    pub fn is_creating(&self) -> bool {
+        self.selected == self.rooms.len()
+    }
+
+    pub fn new_room_name(&self) -> &str {
+        &self.new_room_name
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1).min(self.rooms.len());
+    }
+
+    /// 新しいルームの名前に文字を足す。新しいルームを作る行を選択しているときだけ有効
+    pub fn input_text(&mut self, text: &str) {
+        if !self.is_creating() {
+            return;
+        }
+        for c in text.chars().filter(|c| !c.is_control()) {
+            if self.new_room_name.chars().count() >= NEW_ROOM_NAME_MAX_CHARS {
+                break;
+            }
+            self.new_room_name.push(c);
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.is_creating() {
+            self.new_room_name.pop();
+        }
+    }
+
+    /// 選択中の行に入る場合の`join-room`の内容。満員のルームを選んでいたら`None`
+    ///
+    /// 新しいルームの名前が空ならサーバーに任せる。
+    pub fn choice(&self) -> Option<RoomChoice> {
+        match self.selected_room() {
+            Some(room) if room.is_full() => None,
+            Some(room) => Some(RoomChoice::Existing {
+                room_id: room.room_id.clone(),
+            }),
+            None => {
+                let room_name = self.new_room_name.trim();
+                if room_name.is_empty() {
+                    Some(RoomChoice::Any)
+                } else {
+                    Some(RoomChoice::New {
+                        room_name: room_name.to_string(),
+                    })
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(id: &str, member_count: usize) -> RoomSummary {
+        RoomSummary {
+            room_id: id.to_string(),
+            room_name: format!("room {}", id),
+            member_count,
+            max_member: 2,
+        }
+    }
+
+    #[test]
+    fn keep_selection_across_updates() {
+        let mut browser = RoomBrowser::new();
+        browser.set_rooms(vec![room("a", 0), room("b", 1)]);
+        browser.select_next();
+        assert_eq!(browser.selected_room().unwrap().room_id, "b");
+
+        browser.set_rooms(vec![room("c", 0), room("b", 1)]);
+        assert_eq!(browser.selected_room().unwrap().room_id, "b");
+
+        browser.set_rooms(vec![room("c", 0)]);
+        assert_eq!(browser.selected(), 0);
+    }
+
+    #[test]
+    fn select_within_rows() {
+        let mut browser = RoomBrowser::new();
+        browser.set_rooms(vec![room("a", 0)]);
+        browser.select_previous();
+        assert_eq!(browser.selected(), 0);
+        browser.select_next();
+        browser.select_next();
+        assert!(browser.is_creating());
+    }
+
+    #[test]
+    fn type_new_room_name() {
+        let mut browser = RoomBrowser::new();
+        browser.set_rooms(vec![room("a", 0)]);
+        browser.input_text("x");
+        assert_eq!(browser.new_room_name(), "");
+
+        browser.select_next();
+        assert_eq!(browser.choice(), Some(RoomChoice::Any));
+        browser.input_text("部屋\u{8}");
+        browser.input_text(&"a".repeat(NEW_ROOM_NAME_MAX_CHARS));
+        assert_eq!(browser.new_room_name().chars().count(), NEW_ROOM_NAME_MAX_CHARS);
+        browser.backspace();
+        assert_eq!(
+            browser.choice(),
+            Some(RoomChoice::New {
+                room_name: format!("部屋{}", "a".repeat(NEW_ROOM_NAME_MAX_CHARS - 3)),
+            })
+        );
+    }
+
+    #[test]
+    fn cannot_join_full_room() {
+        let mut browser = RoomBrowser::new();
+        browser.set_rooms(vec![room("a", 2)]);
+        assert_eq!(browser.choice(), None);
+    }
+}
@@ -5,6 +5,7 @@ use std::fs;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 use tracing_unwrap::ResultExt;
@@ -12,13 +13,27 @@ use uuid::Uuid;
 
 use tracing::{info, warn};
 
+use crate::input::{Action, RepeatConfig};
+use crate::keybindings::{unbound_default_keys, KeyBindingTable, KeyBindings};
+use crate::locale::DEFAULT_LANGUAGE;
 use crate::types::*;
 
+/// 設定ファイルに`server`がないときに使うサーバー
+pub const DEFAULT_SERVER: &str = "http://13.114.119.94:80";
+
 #[derive(Deserialize, Serialize, Debug)]
 struct SettingToml {
     pub uuid: Option<Uuid>,
     pub server: Option<String>,
     pub fullscreen: Option<bool>,
+    pub minimap: Option<bool>,
+    pub record_replay: Option<bool>,
+    pub move_repeat_delay_ms: Option<u64>,
+    pub move_repeat_interval_ms: Option<u64>,
+    pub predict_movement: Option<bool>,
+    pub language: Option<String>,
+    /// TOMLではテーブルを値より後に書くので、最後に置く
+    pub keybindings: Option<KeyBindingTable>,
 }
 
 #[derive(Serialize)]
@@ -26,6 +41,19 @@ pub struct Setting {
     pub uuid: Uuid,
     pub server: String,
     pub fullscreen: bool,
+    /// ゲーム中に画面の隅の地図を出すかどうか
+    pub minimap: bool,
+    /// 対戦を`replays`ディレクトリに記録するかどうか
+    pub record_replay: bool,
+    /// 移動キーを押し続けたとき、繰り返し始めるまでの時間(ミリ秒)
+    pub move_repeat_delay_ms: u64,
+    /// 移動キーを押し続けたときの繰り返しの間隔(ミリ秒)
+    pub move_repeat_interval_ms: u64,
+    /// サーバーの返事を待たずに自機を動かして見せるかどうか
+    pub predict_movement: bool,
+    /// 画面に出す文字列の言語。`rsc_included/locales`にある言語の名前
+    pub language: String,
+    pub keybindings: KeyBindings,
 }
 
 impl Setting {
@@ -39,7 +67,11 @@ impl Setting {
             toml.save(&file_path)?;
         }
 
-        let setting: Setting = toml.try_into().unwrap_or_log();
+        let mut setting: Setting = toml.try_into().unwrap_or_log();
+        if let Err(conflict) = setting.keybindings.validate() {
+            warn!("キー割り当てが重複しているので、初期設定を使います: {}", conflict);
+            setting.keybindings = KeyBindings::default();
+        }
         Ok(setting)
     }
 
@@ -49,6 +81,14 @@ impl Setting {
         SettingToml::from(self).save(&file_path)
     }
 
+    pub fn move_repeat(&self) -> RepeatConfig {
+        RepeatConfig {
+            initial_delay: Duration::from_millis(self.move_repeat_delay_ms),
+            repeat_interval: Duration::from_millis(self.move_repeat_interval_ms),
+            ..RepeatConfig::default()
+        }
+    }
+
     fn file_path() -> Result<PathBuf, Box<dyn Error>> {
         let mut file_path = PathBuf::new();
         file_path.push(env::current_exe()?);
@@ -64,6 +104,13 @@ impl SettingToml {
             uuid: None,
             server: None,
             fullscreen: None,
+            minimap: None,
+            record_replay: None,
+            move_repeat_delay_ms: None,
+            move_repeat_interval_ms: None,
+            predict_movement: None,
+            language: None,
+            keybindings: None,
         }
     }
 
@@ -97,7 +144,20 @@ impl SettingToml {
     }
 
     fn has_empty_property(&self) -> bool {
-        self.uuid.is_none() || self.server.is_none() || self.fullscreen.is_none()
+        self.uuid.is_none()
+            || self.server.is_none()
+            || self.fullscreen.is_none()
+            || self.minimap.is_none()
+            || self.record_replay.is_none()
+            || self.move_repeat_delay_ms.is_none()
+            || self.move_repeat_interval_ms.is_none()
+            || self.predict_movement.is_none()
+            || self.language.is_none()
+            || self.keybindings.as_ref().map_or(true, |table| {
+                Action::ALL
+                    .iter()
+                    .any(|action| !table.contains_key(action.name()))
+            })
     }
 
     fn fill_empty_value(&mut self) {
@@ -105,11 +165,37 @@ impl SettingToml {
             self.uuid = Some(Uuid::new_v4());
         }
         if self.server.is_none() {
-            self.server = Some("http://13.114.119.94:80".to_string());
+            self.server = Some(DEFAULT_SERVER.to_string());
         }
         if self.fullscreen.is_none() {
             self.fullscreen = Some(false);
         }
+        if self.minimap.is_none() {
+            self.minimap = Some(true);
+        }
+        if self.record_replay.is_none() {
+            self.record_replay = Some(false);
+        }
+        let repeat = RepeatConfig::default();
+        if self.move_repeat_delay_ms.is_none() {
+            self.move_repeat_delay_ms = Some(repeat.initial_delay.as_millis() as u64);
+        }
+        if self.move_repeat_interval_ms.is_none() {
+            self.move_repeat_interval_ms = Some(repeat.repeat_interval.as_millis() as u64);
+        }
+        if self.predict_movement.is_none() {
+            self.predict_movement = Some(true);
+        }
+        if self.language.is_none() {
+            self.language = Some(DEFAULT_LANGUAGE.to_string());
+        }
+        let table = self.keybindings.get_or_insert_with(KeyBindingTable::new);
+        for action in Action::ALL.iter() {
+            if !table.contains_key(action.name()) {
+                let keys = unbound_default_keys(*action, table);
+                table.insert(action.name().to_string(), keys);
+            }
+        }
         debug_assert!(!self.has_empty_property());
     }
 }
@@ -125,6 +211,13 @@ impl TryFrom<SettingToml> for Setting {
                 uuid: value.uuid.unwrap_or_log(),
                 server: value.server.unwrap_or_log(),
                 fullscreen: value.fullscreen.unwrap_or_log(),
+                minimap: value.minimap.unwrap_or_log(),
+                record_replay: value.record_replay.unwrap_or_log(),
+                move_repeat_delay_ms: value.move_repeat_delay_ms.unwrap_or_log(),
+                move_repeat_interval_ms: value.move_repeat_interval_ms.unwrap_or_log(),
+                predict_movement: value.predict_movement.unwrap_or_log(),
+                language: value.language.unwrap_or_log(),
+                keybindings: KeyBindings::from_table(value.keybindings.as_ref().unwrap_or_log()),
             })
         }
     }
@@ -136,6 +229,50 @@ impl From<&Setting> for SettingToml {
             uuid: Some(setting.uuid),
             server: Some(setting.server.clone()),
             fullscreen: Some(setting.fullscreen),
+            minimap: Some(setting.minimap),
+            record_replay: Some(setting.record_replay),
+            move_repeat_delay_ms: Some(setting.move_repeat_delay_ms),
+            move_repeat_interval_ms: Some(setting.move_repeat_interval_ms),
+            predict_movement: Some(setting.predict_movement),
+            language: Some(setting.language.clone()),
+            keybindings: Some(setting.keybindings.to_table()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::json::DirectionJson;
+
+    #[test]
+    fn keybindings_round_trip() {
+        let mut toml = SettingToml::empty();
+        toml.fill_empty_value();
+        let text = toml::to_string(&toml).unwrap();
+        assert!(text.contains("[keybindings]"));
+
+        // 一部の操作しか書いていなくても、残りは初期設定で埋める
+        let text = text.replace("confirm = [\"Space\", \"Return\"]", "confirm = [\"Return\"]");
+        let mut toml: SettingToml = toml::from_str(&text).unwrap();
+        toml.keybindings.as_mut().unwrap().remove("back");
+        assert!(toml.has_empty_property());
+        toml.fill_empty_value();
+
+        let setting = Setting::try_from(toml).unwrap();
+        assert_eq!(setting.keybindings.keys(Action::Confirm), ["Return"]);
+        assert_eq!(setting.keybindings.keys(Action::Back), ["Escape"]);
+    }
+
+    #[test]
+    fn defaults_do_not_take_keys_bound_by_user() {
+        let mut toml: SettingToml = toml::from_str("[keybindings]\nmove_left = [\"O\"]\n").unwrap();
+        toml.fill_empty_value();
+
+        let setting = Setting::try_from(toml).unwrap();
+        assert!(setting.keybindings.validate().is_ok());
+        assert_eq!(setting.keybindings.keys(Action::Move(DirectionJson::Left)), ["O"]);
+        assert!(setting.keybindings.keys(Action::Offline).is_empty());
+        assert_eq!(setting.keybindings.keys(Action::Confirm), ["Space", "Return"]);
+    }
+}
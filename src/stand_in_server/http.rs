@@ -0,0 +1,183 @@
+//! Engine.IO v4のHTTPロングポーリングを最低限実装する
+//!
+//! WebSocketへのアップグレードはしない(ハンドシェイクで`upgrades`を空にする)。
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, trace};
+use uuid::Uuid;
+
+use super::Shared;
+
+/// Engine.IOのパケットの区切り文字
+const RECORD_SEPARATOR: char = '\x1e';
+/// 送るものがないときに、ロングポーリングのリクエストを保留しておく時間
+const PING_INTERVAL: Duration = Duration::from_secs(25);
+const PING_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct Request {
+    method: String,
+    query: HashMap<String, String>,
+    body: String,
+}
+
+impl Request {
+    fn read(stream: &TcpStream) -> io::Result<Option<Self>> {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(None);
+        }
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("");
+
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let query = target
+            .split_once('?')
+            .map(|(_, query)| query)
+            .unwrap_or("")
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        Ok(Some(Self {
+            method,
+            query,
+            body: String::from_utf8_lossy(&body).into_owned(),
+        }))
+    }
+}
+
+/// 1つのHTTPリクエストを処理する
+pub(super) fn handle_connection(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let request = match Request::read(&stream)? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+    let (status, body) = route(&request, shared);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+fn route(request: &Request, shared: &Shared) -> (&'static str, String) {
+    if request.query.get("transport").map(String::as_str) != Some("polling") {
+        return ("400 Bad Request", "transport unknown".to_string());
+    }
+    match (request.method.as_str(), request.query.get("sid")) {
+        ("GET", None) => ("200 OK", handshake(shared)),
+        ("GET", Some(sid)) => match poll(shared, sid) {
+            Some(body) => ("200 OK", body),
+            None => ("400 Bad Request", "session unknown".to_string()),
+        },
+        ("POST", Some(_))
+            if shared.rejecting_events.load(Ordering::SeqCst) && carries_event(&request.body) =>
+        {
+            ("503 Service Unavailable", "rejecting events".to_string())
+        }
+        ("POST", Some(sid)) => {
+            if receive(shared, sid, &request.body) {
+                ("200 OK", "ok".to_string())
+            } else {
+                ("400 Bad Request", "session unknown".to_string())
+            }
+        }
+        _ => ("400 Bad Request", "bad request".to_string()),
+    }
+}
+
+/// 新しいセッションを作り、Engine.IOのopenパケットを返す
+fn handshake(shared: &Shared) -> String {
+    let sid = Uuid::new_v4().to_simple().to_string();
+    shared.lock().open_session(&sid);
+    debug!("engine.io session opened: {}", sid);
+    format!(
+        "0{}",
+        serde_json::json!({
+            "sid": sid,
+            "upgrades": [],
+            "pingInterval": PING_INTERVAL.as_millis() as u64,
+            "pingTimeout": PING_TIMEOUT.as_millis() as u64,
+            "maxPayload": 1_000_000,
+        })
+    )
+}
+
+/// 送るべきパケットができるまで待ってから返す。しばらく何もなければpingを返す
+fn poll(shared: &Shared, sid: &str) -> Option<String> {
+    let deadline = Instant::now() + PING_INTERVAL;
+    let mut state = shared.lock();
+    loop {
+        let session = state.sessions.get_mut(sid)?;
+        if !session.outgoing.is_empty() {
+            let packets: Vec<String> = session.outgoing.drain(..).collect();
+            trace!("engine.io send to {}: {:?}", sid, packets);
+            return Some(packets.join(&RECORD_SEPARATOR.to_string()));
+        }
+        let now = Instant::now();
+        if now >= deadline || shared.shutdown.load(Ordering::SeqCst) {
+            return Some("2".to_string());
+        }
+        state = shared
+            .updated
+            .wait_timeout(state, deadline - now)
+            .unwrap_or_else(|error| error.into_inner())
+            .0;
+    }
+}
+
+/// socket.ioのイベント(Engine.IOのmessageパケットに入ったsocket.ioのeventパケット)を含むかどうか
+fn carries_event(body: &str) -> bool {
+    body.split(RECORD_SEPARATOR)
+        .any(|packet| packet.starts_with("42"))
+}
+
+/// クライアントから送られてきたパケットを処理する
+fn receive(shared: &Shared, sid: &str, body: &str) -> bool {
+    let mut state = shared.lock();
+    if !state.sessions.contains_key(sid) {
+        return false;
+    }
+    for packet in body.split(RECORD_SEPARATOR) {
+        trace!("engine.io receive from {}: {}", sid, packet);
+        let mut chars = packet.chars();
+        match chars.next() {
+            // close
+            Some('1') => state.close_session(sid),
+            // ping (EIO3のクライアントはクライアントからpingを送ってくる)
+            Some('2') => state.send_raw(sid, format!("3{}", chars.as_str())),
+            // message
+            Some('4') => state.on_socketio_packet(sid, chars.as_str()),
+            _ => {}
+        }
+    }
+    drop(state);
+    shared.updated.notify_all();
+    true
+}
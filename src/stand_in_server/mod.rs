@@ -0,0 +1,718 @@
+//! BlockingIO-apiの代役をするローカルサーバー
+//!
+//! ネットワークのない環境で開発したり、`ApiClient`を端から端までテストしたりするためのもの。
+//! socket.ioのプロトコルのうちEngine.IO v4のロングポーリングだけを実装し、
+//! `api::client::event`にあるイベントを話す。ゲームの進行は`Scenario`に従う。
+
+mod http;
+mod room;
+mod scenario;
+
+pub use scenario::{Scenario, ScenarioEnding};
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use serde_json::{json, Value};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use self::room::{Game, Member, Room};
+use crate::api::client::event;
+
+/// 代役サーバーの設定
+pub struct StandInConfig {
+    /// 待ち受けるアドレス。ポート番号を0にすると空いているポートを使う
+    pub addr: SocketAddr,
+    /// ゲームを始めるのに必要な人数
+    pub max_member: usize,
+    pub scenario: Scenario,
+}
+
+impl Default for StandInConfig {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            max_member: 2,
+            scenario: Scenario::timeup(),
+        }
+    }
+}
+
+/// 代役サーバーが受け取ったイベント。テストで検証するために記録しておく
+#[derive(Clone, Debug)]
+pub struct ReceivedEvent {
+    pub uid: Option<Uuid>,
+    pub event: String,
+    pub data: Value,
+}
+
+/// 起動中の代役サーバー。dropすると止まる
+pub struct StandInServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl StandInServer {
+    /// 別スレッドで待ち受けを始める
+    pub fn start(config: StandInConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(config.addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(ServerState::new(config)),
+            updated: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+            rejecting_events: AtomicBool::new(false),
+        });
+
+        {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || accept_loop(listener, shared));
+        }
+        {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || tick_loop(shared));
+        }
+
+        info!("stand-in server listening on {}", addr);
+        Ok(Self { addr, shared })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `ApiClient::new`に渡すURL
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub fn received_events(&self) -> Vec<ReceivedEvent> {
+        self.shared.lock().received.clone()
+    }
+
+    /// `true`にすると、接続は受け付けるがイベントを送ってきたリクエストはすべてエラーで返す
+    ///
+    /// 接続はできてもセッションを戻せないサーバーを再現するためのもの。
+    pub fn set_rejecting_events(&self, rejecting: bool) {
+        self.shared.rejecting_events.store(rejecting, Ordering::SeqCst);
+    }
+}
+
+impl Drop for StandInServer {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.shared.updated.notify_all();
+        // acceptで止まっているスレッドを起こす
+        TcpStream::connect(self.addr).ok();
+    }
+}
+
+struct Shared {
+    state: Mutex<ServerState>,
+    /// 送るべきパケットができたときに、ロングポーリング中のスレッドを起こす
+    updated: Condvar,
+    shutdown: AtomicBool,
+    /// `StandInServer::set_rejecting_events`で立てる
+    rejecting_events: AtomicBool,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, ServerState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+}
+
+fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        match stream {
+            Ok(stream) => {
+                let shared = Arc::clone(&shared);
+                thread::spawn(move || {
+                    if let Err(error) = http::handle_connection(stream, &shared) {
+                        debug!("http connection error: {}", error);
+                    }
+                });
+            }
+            Err(error) => warn!("accept failed: {}", error),
+        }
+    }
+}
+
+/// ゲーム中なら一定間隔でtickを進める
+fn tick_loop(shared: Arc<Shared>) {
+    while !shared.shutdown.load(Ordering::SeqCst) {
+        let tick_interval = {
+            let mut state = shared.lock();
+            state.tick();
+            state.scenario.tick_interval
+        };
+        shared.updated.notify_all();
+        thread::sleep(tick_interval);
+    }
+}
+
+/// Engine.IOのセッション。socket.ioのソケット1つに対応する
+struct Session {
+    /// 次のロングポーリングで送るEngine.IOパケット
+    outgoing: VecDeque<String>,
+    /// `setup-uid`で設定されたユーザー
+    user: Option<(Uuid, String)>,
+}
+
+struct ServerState {
+    sessions: HashMap<String, Session>,
+    rooms: Vec<Room>,
+    /// 新しく作るルームの定員
+    max_member: usize,
+    /// 次に作るルームの番号
+    next_room_id: u32,
+    scenario: Scenario,
+    received: Vec<ReceivedEvent>,
+}
+
+impl ServerState {
+    fn new(config: StandInConfig) -> Self {
+        let mut state = Self {
+            sessions: HashMap::new(),
+            rooms: Vec::new(),
+            max_member: config.max_member,
+            next_room_id: 1,
+            scenario: config.scenario,
+            received: Vec::new(),
+        };
+        state.create_room(None);
+        state
+    }
+
+    fn open_session(&mut self, sid: &str) {
+        self.sessions.insert(
+            sid.to_string(),
+            Session {
+                outgoing: VecDeque::new(),
+                user: None,
+            },
+        );
+    }
+
+    fn close_session(&mut self, sid: &str) {
+        debug!("engine.io session closed: {}", sid);
+        self.leave_room(sid);
+        self.sessions.remove(sid);
+    }
+
+    fn send_raw(&mut self, sid: &str, packet: String) {
+        if let Some(session) = self.sessions.get_mut(sid) {
+            session.outgoing.push_back(packet);
+        }
+    }
+
+    fn emit(&mut self, sid: &str, event: &str, data: &Value) {
+        self.send_raw(sid, format!("42{}", json!([event, data])));
+    }
+
+    fn emit_to_room(&mut self, room: usize, event: &str, data: &Value) {
+        let sids: Vec<String> = self.rooms[room]
+            .members
+            .iter()
+            .map(|member| member.sid.clone())
+            .collect();
+        for sid in sids {
+            self.emit(&sid, event, data);
+        }
+    }
+
+    /// `sid`のユーザーが入っているルーム
+    fn room_of(&self, sid: &str) -> Option<usize> {
+        self.rooms
+            .iter()
+            .position(|room| room.members.iter().any(|member| member.sid == sid))
+    }
+
+    /// 新しいルームを作る。名前がなければ番号から付ける
+    fn create_room(&mut self, name: Option<&str>) -> usize {
+        let id = self.next_room_id;
+        self.next_room_id += 1;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => format!("ルーム{}", id),
+        };
+        self.rooms.push(Room::new(
+            &format!("stand-in-room-{}", id),
+            &name,
+            self.max_member,
+        ));
+        self.rooms.len() - 1
+    }
+
+    /// Engine.IOのmessageパケットに入っているsocket.ioのパケットを処理する
+    fn on_socketio_packet(&mut self, sid: &str, packet: &str) {
+        let mut chars = packet.chars();
+        match chars.next() {
+            // connect
+            Some('0') => {
+                let socket_id = Uuid::new_v4().to_simple().to_string();
+                self.send_raw(sid, format!("40{}", json!({ "sid": socket_id })));
+            }
+            // disconnect
+            Some('1') => self.leave_room(sid),
+            // event
+            Some('2') => {
+                let mut rest = chars.as_str();
+                // 名前空間とack idは読み飛ばす
+                if rest.starts_with('/') {
+                    rest = rest.split_once(',').map(|(_, rest)| rest).unwrap_or("");
+                }
+                let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+                match serde_json::from_str::<Vec<Value>>(rest) {
+                    Ok(array) => {
+                        let mut array = array.into_iter();
+                        let name = array.next().and_then(|name| name.as_str().map(str::to_string));
+                        let data = array.next().map(unwrap_json_string).unwrap_or(Value::Null);
+                        match name {
+                            Some(name) => self.on_event(sid, &name, data),
+                            None => warn!("event without name: {}", packet),
+                        }
+                    }
+                    Err(error) => warn!("invalid socket.io event {}: {}", packet, error),
+                }
+            }
+            _ => debug!("ignored socket.io packet: {}", packet),
+        }
+    }
+
+    fn on_event(&mut self, sid: &str, name: &str, data: Value) {
+        debug!("{} event from {}: {}", name, sid, data);
+        let uid = self
+            .sessions
+            .get(sid)
+            .and_then(|session| session.user.as_ref())
+            .map(|user| user.0);
+        self.received.push(ReceivedEvent {
+            uid,
+            event: name.to_string(),
+            data: data.clone(),
+        });
+
+        match name {
+            event::SETUP_UID => {
+                let uid = data
+                    .get("user_id")
+                    .and_then(Value::as_str)
+                    .and_then(|uid| Uuid::parse_str(uid).ok());
+                match uid {
+                    Some(uid) => self.setup_uid(sid, uid),
+                    None => warn!("invalid setup-uid payload: {}", data),
+                }
+            }
+            event::FIND_AVAILABLE_ROOM => {
+                let rooms: Vec<Value> = self.rooms.iter().map(Room::state_json).collect();
+                self.emit(sid, event::FIND_AVAILABLE_ROOM, &Value::Array(rooms));
+            }
+            event::JOIN_ROOM => {
+                let room_id = data.get("roomId").and_then(Value::as_str).map(str::to_string);
+                let room_name = data.get("roomname").and_then(Value::as_str).map(str::to_string);
+                self.join_room(sid, room_id.as_deref(), room_name.as_deref());
+            }
+            event::TRY_MOVE => {
+                let room = self.room_of(sid);
+                let game = room.and_then(|room| self.rooms[room].game.as_mut());
+                if let (Some(game), Some(direction)) = (game, data.as_str()) {
+                    game.try_move(sid, direction);
+                }
+            }
+            event::REQUEST_AFTER_GAME => match data.as_str() {
+                Some("restart") => self.request_restart(sid),
+                Some("leave") => self.leave_room(sid),
+                _ => warn!("invalid request-after-game payload: {}", data),
+            },
+            _ => warn!("unknown event: {}", name),
+        }
+    }
+
+    fn setup_uid(&mut self, sid: &str, uid: Uuid) {
+        let name = "名無しユーザー".to_string();
+        if let Some(session) = self.sessions.get_mut(sid) {
+            session.user = Some((uid, name.clone()));
+        }
+        let data = json!({
+            "name": name,
+            "point": 0,
+            "uid": uid,
+            "socketId": sid,
+            "requestingToStartGame": false,
+        });
+        self.emit(sid, event::UPDATE_USER, &data);
+    }
+
+    /// `room_id`のルーム、`room_name`という名前のルーム(なければ作る)、
+    /// どちらもなければ空いているルーム(なければ作る)に入る
+    fn join_room(&mut self, sid: &str, room_id: Option<&str>, room_name: Option<&str>) {
+        let (uid, name) = match self.sessions.get(sid).and_then(|session| session.user.clone()) {
+            Some(user) => user,
+            None => {
+                warn!("join-room before setup-uid: {}", sid);
+                return;
+            }
+        };
+        if self.room_of(sid).is_some() {
+            return;
+        }
+
+        let room = match (room_id, room_name) {
+            (Some(room_id), _) => self.rooms.iter().position(|room| room.id == room_id),
+            (None, Some(room_name)) => Some(
+                self.rooms
+                    .iter()
+                    .position(|room| room.name == room_name)
+                    .unwrap_or_else(|| self.create_room(Some(room_name))),
+            ),
+            (None, None) => Some(
+                self.rooms
+                    .iter()
+                    .position(|room| !room.is_fulfilled())
+                    .unwrap_or_else(|| self.create_room(None)),
+            ),
+        };
+        let room = match room {
+            Some(room) if !self.rooms[room].is_fulfilled() => room,
+            _ => {
+                warn!("room not found or full");
+                self.emit(sid, event::ROOM_STATE, &Room::not_joining_json());
+                return;
+            }
+        };
+
+        self.rooms[room].members.push(Member {
+            sid: sid.to_string(),
+            uid,
+            name,
+            point: 0,
+            requesting_to_start: true,
+        });
+        self.broadcast_room_state_and_start(room);
+    }
+
+    fn request_restart(&mut self, sid: &str) {
+        let room = match self.room_of(sid) {
+            Some(room) => room,
+            None => return,
+        };
+        if let Some(member) = self.rooms[room]
+            .members
+            .iter_mut()
+            .find(|member| member.sid == sid)
+        {
+            member.requesting_to_start = true;
+        }
+        self.broadcast_room_state_and_start(room);
+    }
+
+    fn leave_room(&mut self, sid: &str) {
+        let room = match self.room_of(sid) {
+            Some(room) => room,
+            None => return,
+        };
+        self.rooms[room].members.retain(|member| member.sid != sid);
+        self.emit(sid, event::ROOM_STATE, &Room::not_joining_json());
+
+        // ゲーム中に抜けたら異常終了
+        if let Some(game) = self.rooms[room].game.as_mut() {
+            if !game.finished {
+                game.finished = true;
+                let data = json!({
+                    "winner": null,
+                    "tickCount": game.tick,
+                    "state": "AbnormalEnd",
+                });
+                let data = merge_json(game.to_json(&self.scenario, None), data);
+                self.emit_to_room(room, event::UPDATE_FIELD, &data);
+            }
+        }
+        let data = self.rooms[room].state_json();
+        self.emit_to_room(room, event::ROOM_STATE, &data);
+    }
+
+    fn broadcast_room_state_and_start(&mut self, room: usize) {
+        let data = self.rooms[room].state_json();
+        self.emit_to_room(room, event::ROOM_STATE, &data);
+        if self.rooms[room].should_start() {
+            let scenario = self.scenario.clone();
+            let id = self.rooms[room].start_game(&scenario);
+            info!(
+                "game {} started in {} with scenario {}",
+                id, self.rooms[room].name, scenario.name
+            );
+        }
+    }
+
+    /// ゲーム中のルームを1tick進めて、全員に`update-field`を送る
+    fn tick(&mut self) {
+        let scenario = self.scenario.clone();
+        for room in 0..self.rooms.len() {
+            let data = match self.rooms[room].game {
+                Some(ref mut game) if !game.finished => {
+                    let result = game.advance(&scenario);
+                    game.to_json(&scenario, result.as_ref())
+                }
+                _ => continue,
+            };
+            self.emit_to_room(room, event::UPDATE_FIELD, &data);
+            if let Some(Game {
+                finished: true, id, ..
+            }) = self.rooms[room].game
+            {
+                info!("game {} finished in {}", id, self.rooms[room].name);
+            }
+        }
+    }
+}
+
+/// `"{\"a\":1}"`のように文字列として送られてきたJSONを展開する
+fn unwrap_json_string(value: Value) -> Value {
+    match value {
+        Value::String(ref str) => serde_json::from_str(str).unwrap_or(value),
+        value => value,
+    }
+}
+
+/// `base`のオブジェクトのフィールドを`overwrite`で上書きする
+fn merge_json(mut base: Value, overwrite: Value) -> Value {
+    if let (Some(base), Value::Object(overwrite)) = (base.as_object_mut(), overwrite) {
+        for (key, value) in overwrite {
+            base.insert(key, value);
+        }
+    }
+    base
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::api::client::{ApiClient, ApiEvent, RoomChoice};
+    use crate::api::event_queue::{EventQueue, SharedEventQueue};
+    use crate::api::json::DirectionJson;
+    use crate::GameFinishReason;
+
+    /// `predicate`を満たすイベントが届くまで待つ。それまでに届いたイベントは捨てる
+    fn wait_for<F>(queue: &SharedEventQueue, predicate: F) -> ApiEvent
+    where
+        F: Fn(&ApiEvent) -> bool,
+    {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            let events = queue.lock().unwrap().drain();
+            if let Some(event) = events.into_iter().find(|event| predicate(event)) {
+                return event;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out");
+    }
+
+    fn start_server(scenario: Scenario) -> StandInServer {
+        StandInServer::start(StandInConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            max_member: 1,
+            scenario: scenario
+                .with_ticks(5)
+                .with_tick_interval(Duration::from_millis(20)),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn connect_join_play_finish_restart() {
+        let server = start_server(Scenario::timeup());
+        let queue = EventQueue::new_shared();
+        let mut api = ApiClient::new(&server.url());
+        let uid = Uuid::new_v4();
+
+        api.connect(&queue).unwrap();
+        api.setup_uid(uid).unwrap();
+        wait_for(
+            &queue,
+            |event| matches!(event, ApiEvent::UpdateUser { uid: u, .. } if *u == uid),
+        );
+
+        api.join_room(&RoomChoice::Any).unwrap();
+        wait_for(&queue, |event| {
+            matches!(
+                event,
+                ApiEvent::RoomStateFulfilled {
+                    should_start: true,
+                    ..
+                }
+            )
+        });
+        api.try_move(&DirectionJson::Up).unwrap();
+        wait_for(&queue, |event| matches!(event, ApiEvent::UpdateField { .. }));
+        wait_for(&queue, |event| {
+            matches!(
+                event,
+                ApiEvent::GameFinished {
+                    reason: GameFinishReason::Timeup
+                }
+            )
+        });
+
+        api.restart().unwrap();
+        wait_for(&queue, |event| {
+            matches!(
+                event,
+                ApiEvent::RoomStateFulfilled {
+                    should_start: true,
+                    ..
+                }
+            )
+        });
+        wait_for(&queue, |event| matches!(event, ApiEvent::GameFinished { .. }));
+        api.disconnect().unwrap();
+
+        let received = server.received_events();
+        let names: Vec<&str> = received.iter().map(|event| event.event.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                event::SETUP_UID,
+                event::JOIN_ROOM,
+                event::TRY_MOVE,
+                event::REQUEST_AFTER_GAME
+            ]
+        );
+        assert_eq!(received[2].uid, Some(uid));
+    }
+
+    #[test]
+    fn leave_after_game_and_join_again() {
+        let server = start_server(Scenario::timeup());
+        let queue = EventQueue::new_shared();
+        let mut api = ApiClient::new(&server.url());
+
+        api.connect(&queue).unwrap();
+        api.setup_uid(Uuid::new_v4()).unwrap();
+        api.join_room(&RoomChoice::Any).unwrap();
+        wait_for(&queue, |event| matches!(event, ApiEvent::GameFinished { .. }));
+
+        api.leave().unwrap();
+        wait_for(&queue, |event| matches!(event, ApiEvent::RoomStateNotJoined));
+        assert!(api.is_connected());
+
+        api.join_room(&RoomChoice::Any).unwrap();
+        wait_for(&queue, |event| {
+            matches!(
+                event,
+                ApiEvent::RoomStateFulfilled {
+                    should_start: true,
+                    ..
+                }
+            )
+        });
+        api.disconnect().unwrap();
+    }
+
+    #[test]
+    fn browse_and_create_room() {
+        let server = StandInServer::start(StandInConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            max_member: 2,
+            scenario: Scenario::timeup(),
+        })
+        .unwrap();
+        let queue = EventQueue::new_shared();
+        let mut api = ApiClient::new(&server.url());
+
+        api.connect(&queue).unwrap();
+        api.setup_uid(Uuid::new_v4()).unwrap();
+        api.find_available_rooms().unwrap();
+        let event = wait_for(&queue, |event| matches!(event, ApiEvent::AvailableRooms { .. }));
+        assert!(matches!(event, ApiEvent::AvailableRooms { ref rooms } if rooms.len() == 1));
+
+        api.join_room(&RoomChoice::New {
+            room_name: "新しい部屋".to_string(),
+        })
+        .unwrap();
+        let event = wait_for(&queue, |event| matches!(event, ApiEvent::RoomStateOpening { .. }));
+        assert!(
+            matches!(event, ApiEvent::RoomStateOpening { ref room_name, .. } if room_name == "新しい部屋")
+        );
+
+        api.find_available_rooms().unwrap();
+        match wait_for(&queue, |event| matches!(event, ApiEvent::AvailableRooms { .. })) {
+            ApiEvent::AvailableRooms { rooms } => {
+                assert_eq!(rooms.len(), 2);
+                assert_eq!(rooms[1].member_count, 1);
+            }
+            _ => unreachable!(),
+        }
+        api.disconnect().unwrap();
+    }
+
+    #[test]
+    fn rejected_resume_counts_as_failed_attempt() {
+        let server = StandInServer::start(StandInConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+            max_member: 2,
+            scenario: Scenario::timeup(),
+        })
+        .unwrap();
+        let queue = EventQueue::new_shared();
+        let mut api = ApiClient::new(&server.url());
+
+        api.connect(&queue).unwrap();
+        api.setup_uid(Uuid::new_v4()).unwrap();
+        api.join_room(&RoomChoice::Any).unwrap();
+        wait_for(&queue, |event| matches!(event, ApiEvent::RoomStateOpening { .. }));
+
+        // 送信に失敗すると切断されたとみなされ、再接続が始まる
+        server.set_rejecting_events(true);
+        assert!(api.find_available_rooms().is_err());
+
+        // 再接続はできても`setup-uid`が通らないので、試行の回数が進んでいく
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut attempts = Vec::new();
+        while attempts.len() < 3 {
+            assert!(Instant::now() < deadline, "timed out: {:?}", attempts);
+            api.poll_connection();
+            for event in queue.lock().unwrap().drain() {
+                match event {
+                    ApiEvent::Reconnecting { attempt, .. } => attempts.push(attempt),
+                    ApiEvent::Reconnected { .. } => panic!("resumed a rejected session"),
+                    _ => {}
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(attempts, vec![1, 2, 3]);
+        assert!(!api.is_connected());
+    }
+
+    #[test]
+    fn tagger_catches_player() {
+        let server = start_server(Scenario::collision().with_ticks(100));
+        let queue = EventQueue::new_shared();
+        let mut api = ApiClient::new(&server.url());
+        let uid = Uuid::new_v4();
+
+        api.connect(&queue).unwrap();
+        api.setup_uid(uid).unwrap();
+        api.join_room(&RoomChoice::Any).unwrap();
+        let event = wait_for(&queue, |event| matches!(event, ApiEvent::GameFinished { .. }));
+        assert!(matches!(
+            event,
+            ApiEvent::GameFinished {
+                reason: GameFinishReason::Collision { winner_uid: None }
+            }
+        ));
+        api.disconnect().unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+//! 代役サーバーのルームとゲームの状態
+
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use super::scenario::{Scenario, ScenarioEnding};
+use crate::FIELD_SIZE;
+
+/// ルームに入っているユーザー
+pub(super) struct Member {
+    pub sid: String,
+    pub uid: Uuid,
+    pub name: String,
+    pub point: i32,
+    pub requesting_to_start: bool,
+}
+
+impl Member {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "point": self.point,
+            "uid": self.uid,
+            "socketId": self.sid,
+            "requestingToStartGame": self.requesting_to_start,
+        })
+    }
+}
+
+pub(super) struct Room {
+    pub id: String,
+    pub name: String,
+    pub max_member: usize,
+    pub members: Vec<Member>,
+    pub game: Option<Game>,
+    /// 次に始めるゲームの番号。ログに出す
+    next_game_id: u64,
+}
+
+impl Room {
+    pub fn new(id: &str, name: &str, max_member: usize) -> Self {
+        Self {
+            id: id.to_string(),
+            name: name.to_string(),
+            max_member,
+            members: Vec::new(),
+            game: None,
+            next_game_id: 0,
+        }
+    }
+
+    pub fn is_fulfilled(&self) -> bool {
+        self.members.len() >= self.max_member
+    }
+
+    /// 全員がゲームの開始を希望していて、まだゲーム中でなければ`true`
+    pub fn should_start(&self) -> bool {
+        self.is_fulfilled()
+            && self.members.iter().all(|member| member.requesting_to_start)
+            && !matches!(self.game, Some(ref game) if !game.finished)
+    }
+
+    pub fn start_game(&mut self, scenario: &Scenario) -> u64 {
+        let id = self.next_game_id;
+        self.next_game_id += 1;
+        for member in &mut self.members {
+            member.requesting_to_start = false;
+        }
+        self.game = Some(Game::new(id, &self.members, scenario));
+        id
+    }
+
+    pub fn state_json(&self) -> Value {
+        let state = if self.members.is_empty() {
+            "Empty"
+        } else if self.is_fulfilled() {
+            "Fulfilled"
+        } else {
+            "Opening"
+        };
+        json!({
+            "maxMember": self.max_member,
+            "assignedUsers": self.members.iter().map(Member::to_json).collect::<Vec<_>>(),
+            "roomId": self.id,
+            "roomname": self.name,
+            "currentGame": null,
+            "state": state,
+        })
+    }
+
+    pub fn not_joining_json() -> Value {
+        json!({
+            "assignedUsers": [],
+            "state": "notJoining",
+        })
+    }
+}
+
+pub(super) struct GamePlayer {
+    pub sid: String,
+    pub uid: Uuid,
+    pub name: String,
+    pub row: i32,
+    pub column: i32,
+    pub direction: &'static str,
+    pub point: i32,
+    pub alive: bool,
+}
+
+impl GamePlayer {
+    fn to_json(&self) -> Value {
+        json!({
+            "position": { "row": self.row, "column": self.column },
+            "direction": self.direction,
+            "point": self.point,
+            "uid": self.uid,
+            "name": self.name,
+            "status": if self.alive { "alive" } else { "dead" },
+        })
+    }
+}
+
+/// ゲームの終了理由と勝者
+pub(super) struct GameResult {
+    /// `None`なら異常終了
+    pub reason: Option<&'static str>,
+    pub winner: Option<usize>,
+}
+
+pub(super) struct Game {
+    pub id: u64,
+    pub tick: i32,
+    pub players: Vec<GamePlayer>,
+    /// 鬼の位置(row, column)
+    pub tagger: (i32, i32),
+    pub finished: bool,
+}
+
+impl Game {
+    fn new(id: u64, members: &[Member], scenario: &Scenario) -> Self {
+        const STARTS: [(i32, i32); 4] = [(2, 2), (29, 29), (2, 29), (29, 2)];
+        let players = members
+            .iter()
+            .zip(STARTS.iter().cycle())
+            .map(|(member, &(row, column))| GamePlayer {
+                sid: member.sid.clone(),
+                uid: member.uid,
+                name: member.name.clone(),
+                row,
+                column,
+                direction: "up",
+                point: 0,
+                alive: true,
+            })
+            .collect();
+        Self {
+            id,
+            tick: 0,
+            players,
+            tagger: scenario.tagger_start,
+            finished: false,
+        }
+    }
+
+    /// `try-move`イベント。台本のゲームなので高さは気にせず動かす
+    pub fn try_move(&mut self, sid: &str, direction: &str) {
+        let player = match self.players.iter_mut().find(|player| player.sid == sid) {
+            Some(player) if player.alive && !self.finished => player,
+            _ => return,
+        };
+        let (d_row, d_column, direction) = match direction {
+            "up" => (-1, 0, "up"),
+            "down" => (1, 0, "down"),
+            "left" => (0, -1, "left"),
+            "right" => (0, 1, "right"),
+            _ => return,
+        };
+        let max = FIELD_SIZE as i32 - 1;
+        player.row = (player.row + d_row).clamp(0, max);
+        player.column = (player.column + d_column).clamp(0, max);
+        player.direction = direction;
+    }
+
+    /// 1tick進める。ゲームが終わったら結果を返す
+    pub fn advance(&mut self, scenario: &Scenario) -> Option<GameResult> {
+        self.tick += 1;
+        for player in self.players.iter_mut().filter(|player| player.alive) {
+            player.point += 1;
+        }
+
+        match scenario.ending {
+            ScenarioEnding::Collision => self.chase(),
+            ScenarioEnding::Fall { at } if self.tick >= at => {
+                return Some(self.lose(0, "Fall"));
+            }
+            ScenarioEnding::AbnormalEnd { at } if self.tick >= at => {
+                self.finished = true;
+                return Some(GameResult {
+                    reason: None,
+                    winner: None,
+                });
+            }
+            _ => {}
+        }
+
+        let tagger = self.tagger;
+        if let Some(caught) = self
+            .players
+            .iter()
+            .position(|player| player.alive && (player.row, player.column) == tagger)
+        {
+            return Some(self.lose(caught, "Collision"));
+        }
+
+        if self.tick >= scenario.ticks {
+            self.finished = true;
+            return Some(GameResult {
+                reason: Some("Timeup"),
+                winner: None,
+            });
+        }
+        None
+    }
+
+    /// 鬼を一番近いプレイヤーに1マス近づける
+    fn chase(&mut self) {
+        let (row, column) = self.tagger;
+        let target = self
+            .players
+            .iter()
+            .filter(|player| player.alive)
+            .min_by_key(|player| (player.row - row).abs() + (player.column - column).abs());
+        if let Some(target) = target {
+            if target.row != row {
+                self.tagger.0 += (target.row - row).signum();
+            } else {
+                self.tagger.1 += (target.column - column).signum();
+            }
+        }
+    }
+
+    fn lose(&mut self, loser: usize, reason: &'static str) -> GameResult {
+        self.finished = true;
+        if let Some(player) = self.players.get_mut(loser) {
+            player.alive = false;
+        }
+        GameResult {
+            reason: Some(reason),
+            winner: self.players.iter().position(|player| player.alive),
+        }
+    }
+
+    /// `update-field`イベントのペイロード
+    pub fn to_json(&self, scenario: &Scenario, result: Option<&GameResult>) -> Value {
+        let squares: Vec<Vec<Value>> = (0..FIELD_SIZE)
+            .map(|row| {
+                (0..FIELD_SIZE)
+                    .map(|column| json!({ "height": scenario.height(row, column) }))
+                    .collect()
+            })
+            .collect();
+        let (state, finish_reason, winner) = match result {
+            None => ("InGame", None, None),
+            Some(GameResult { reason: None, .. }) => ("AbnormalEnd", None, None),
+            Some(GameResult {
+                reason: Some(reason),
+                winner,
+            }) => (
+                "Finish",
+                Some(*reason),
+                winner.and_then(|winner| self.players.get(winner)),
+            ),
+        };
+        json!({
+            "winner": winner.map(GamePlayer::to_json),
+            "tickCount": self.tick,
+            "battleField": { "length": FIELD_SIZE, "squares": squares },
+            "listOfPlayer": self.players.iter().map(GamePlayer::to_json).collect::<Vec<_>>(),
+            "tagger": {
+                "position": { "row": self.tagger.0, "column": self.tagger.1 },
+                "direction": "down",
+                "name": "tagger",
+            },
+            "state": state,
+            "finishReason": finish_reason,
+        })
+    }
+}
@@ -0,0 +1,102 @@
+//! 代役サーバーで行うゲームの台本
+
+use std::time::Duration;
+
+/// ゲームの台本
+///
+/// 本物のサーバーのルールを再現するのではなく、クライアントの各画面を通るために必要な
+/// 終わり方を決め打ちで起こす。
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub name: &'static str,
+    /// 1tickの長さ
+    pub tick_interval: Duration,
+    /// この数のtickが経過すると時間切れで終了する
+    pub ticks: i32,
+    /// 鬼の初期位置(row, column)
+    pub tagger_start: (i32, i32),
+    pub ending: ScenarioEnding,
+}
+
+/// ゲームの終わり方
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScenarioEnding {
+    /// 鬼は動かず、時間切れで終了する
+    Timeup,
+    /// 鬼が一番近いプレイヤーを追いかけ、捕まえたら終了する
+    Collision,
+    /// `at`tick目に1人目のプレイヤーが落下して終了する
+    Fall { at: i32 },
+    /// `at`tick目に異常終了する
+    AbnormalEnd { at: i32 },
+}
+
+impl Scenario {
+    pub const NAMES: [&'static str; 4] = ["timeup", "collision", "fall", "abnormal-end"];
+
+    pub fn timeup() -> Self {
+        Self {
+            name: "timeup",
+            tick_interval: Duration::from_millis(500),
+            ticks: 60,
+            tagger_start: (16, 16),
+            ending: ScenarioEnding::Timeup,
+        }
+    }
+
+    pub fn collision() -> Self {
+        Self {
+            name: "collision",
+            tick_interval: Duration::from_millis(500),
+            ticks: 120,
+            tagger_start: (16, 16),
+            ending: ScenarioEnding::Collision,
+        }
+    }
+
+    pub fn fall() -> Self {
+        Self {
+            name: "fall",
+            tick_interval: Duration::from_millis(500),
+            ticks: 60,
+            tagger_start: (16, 16),
+            ending: ScenarioEnding::Fall { at: 10 },
+        }
+    }
+
+    pub fn abnormal_end() -> Self {
+        Self {
+            name: "abnormal-end",
+            tick_interval: Duration::from_millis(500),
+            ticks: 60,
+            tagger_start: (16, 16),
+            ending: ScenarioEnding::AbnormalEnd { at: 5 },
+        }
+    }
+
+    /// `NAMES`にある名前から台本を得る
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "timeup" => Some(Self::timeup()),
+            "collision" => Some(Self::collision()),
+            "fall" => Some(Self::fall()),
+            "abnormal-end" => Some(Self::abnormal_end()),
+            _ => None,
+        }
+    }
+
+    pub fn with_tick_interval(mut self, tick_interval: Duration) -> Self {
+        self.tick_interval = tick_interval;
+        self
+    }
+
+    pub fn with_ticks(mut self, ticks: i32) -> Self {
+        self.ticks = ticks;
+        self
+    }
+
+    /// フィールドの各マスの高さ
+    pub fn height(&self, row: usize, column: usize) -> u32 {
+        ((row / 4 + column / 4) % 2) as u32
+    }
+}
@@ -0,0 +1,37 @@
+//! フィールド上のマスの座標の計算。ワールド、ボット、オフライン対戦で共有する
+
+use crate::api::json::DirectionJson;
+use crate::types::*;
+
+/// `pos`から`direction`に1マス動いた位置
+///
+/// `Player::pos`は`(フィールドの行数 - 1 - row, column)`なので、上に動くとxが増える。
+pub fn moved(pos: &Point2i, direction: DirectionJson) -> Point2i {
+    match direction {
+        DirectionJson::Up => Point2i::new(pos.x + 1, pos.y),
+        DirectionJson::Down => Point2i::new(pos.x - 1, pos.y),
+        DirectionJson::Left => Point2i::new(pos.x, pos.y - 1),
+        DirectionJson::Right => Point2i::new(pos.x, pos.y + 1),
+    }
+}
+
+pub fn in_field(field: &FieldMatrix, pos: &Point2i) -> bool {
+    (0..field.nrows() as i32).contains(&pos.x) && (0..field.ncols() as i32).contains(&pos.y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_in_field_coordinates() {
+        let pos = Point2i::new(0, 0);
+        assert_eq!(moved(&pos, DirectionJson::Up), Point2i::new(1, 0));
+        assert_eq!(moved(&pos, DirectionJson::Right), Point2i::new(0, 1));
+        let field = FieldMatrix::zeros(3, 2);
+        assert!(!in_field(&field, &moved(&pos, DirectionJson::Down)));
+        assert!(!in_field(&field, &moved(&pos, DirectionJson::Left)));
+        assert!(in_field(&field, &Point2i::new(2, 1)));
+        assert!(!in_field(&field, &Point2i::new(1, 2)));
+    }
+}
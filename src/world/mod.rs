@@ -1,5 +1,8 @@
 mod world;
 pub use world::*;
 
+mod grid;
+pub use grid::*;
+
 mod renderer;
 mod vao_builder;
@@ -1,9 +1,17 @@
-use super::vao_builder::{VaoBuilderForField, VaoBuilderForPlayer};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::vao_builder::{
+    InstanceBuilderForField, InstanceBuilderForPlayer, VaoBuilderForField, VaoBuilderForPlayer,
+};
 use crate::player::{Player, Tagger};
 use crate::types::*;
-use crate::FIELD_SIZE;
+use re::interpolation::{Time, TimeSpan};
 use re::texture::texture_atlas::TextureAtlasPos;
-use re::vao::VaoBuffer;
+use re::vao::vao_buffer::VERTEX_SIZE;
+use re::vao::{InstanceBuffer, VaoBuffer};
+use re::Interpolation;
+use uuid::Uuid;
 
 pub const TEX_BLOCK_TOP: TextureAtlasPos = TextureAtlasPos::new(0, 0);
 pub const TEX_BLOCK_DANGER: TextureAtlasPos = TextureAtlasPos::new(0, 2);
@@ -12,101 +20,270 @@ pub const TEX_BLOCK_SAFE: TextureAtlasPos = TextureAtlasPos::new(0, 3);
 pub const TEX_PLAYER_TMP: TextureAtlasPos = TextureAtlasPos::new(0, 1);
 pub const TEX_TAGGER: TextureAtlasPos = TextureAtlasPos::new(1, 0);
 
-pub struct FieldRenderer<const X: usize, const Z: usize> {
+/// 1つの区画の1辺のマス数
+const CHUNK_SIZE: usize = super::vao_builder::CHUNK_SIZE;
+/// 1つの区画に割り当てる頂点の数。1つの面は6頂点から成る
+///
+/// 面を1つもまとめられないときの、マスごとの上面と4つの側面の分。
+/// 描かない面の分は面積0の三角形で埋め、どの区画も頂点の位置が変わらないようにする。
+const CHUNK_VERTICES: usize = 6 * 5 * CHUNK_SIZE * CHUNK_SIZE;
+
+/// フィールドのメッシュ
+///
+/// フィールドを`CHUNK_SIZE`四方の区画に分け、`vao_buffer`には区画ごとに`CHUNK_VERTICES`個ずつの頂点を
+/// (x, z)の順に並べる。`render`では、高さが変わったマスとその隣のマスを含む区画の頂点だけを書き換える。
+///
+/// フィールドの端に見える床は、`block_buffer`の1つの形を`blocks`のそれぞれの位置に描く。
+pub struct FieldRenderer {
     pub vao_buffer: VaoBuffer,
+    pub block_buffer: VaoBuffer,
+    pub blocks: InstanceBuffer,
     tex_block_top: TextureUV,
     tex_block_danger: TextureUV,
     tex_block_safe: TextureUV,
-    diff_up: na::SMatrix<i32, X, Z>,
-    diff_down: na::SMatrix<i32, X, Z>,
-    diff_left: na::SMatrix<i32, X, Z>,
-    diff_right: na::SMatrix<i32, X, Z>,
+    /// `vao_buffer`に区画を並べたフィールドの(x, z)方向のマス数
+    shape: (usize, usize),
+    /// 最後に`render`で描いたフィールド。まだ描いていなければ`None`
+    rendered: Option<FieldMatrix>,
+    /// 最後の`render`で書き換えた頂点の範囲
+    changed: Vec<Range<usize>>,
+    /// 最後の`render`でフィールドの大きさが変わり、`vao_buffer`の頂点の数が変わったかどうか
+    resized: bool,
 }
 
-impl FieldRenderer<FIELD_SIZE, FIELD_SIZE> {
+impl FieldRenderer {
     pub fn new() -> Self {
         let tex_block_top = TextureUV::of_atlas(&TEX_BLOCK_TOP);
-        let tex_block_danger = TextureUV::of_atlas(&TEX_BLOCK_DANGER);
-        let tex_block_safe = TextureUV::of_atlas(&TEX_BLOCK_SAFE);
-
-        let mut vao_buffer = VaoBuffer::with_num_vertex(36 * FIELD_SIZE * FIELD_SIZE); // 立方体は36頂点から成る
-        vao_buffer.add_floor(FIELD_SIZE, FIELD_SIZE, &tex_block_top);
-
+        let mut block_buffer = VaoBuffer::new_indexed();
+        block_buffer.add_floor_block_mesh(&tex_block_top);
         Self {
-            vao_buffer,
+            vao_buffer: VaoBuffer::new(),
+            block_buffer,
+            blocks: InstanceBuffer::new(),
             tex_block_top,
-            tex_block_danger,
-            tex_block_safe,
-            diff_up: na::SMatrix::<i32, FIELD_SIZE, FIELD_SIZE>::zeros(),
-            diff_down: na::SMatrix::<i32, FIELD_SIZE, FIELD_SIZE>::zeros(),
-            diff_left: na::SMatrix::<i32, FIELD_SIZE, FIELD_SIZE>::zeros(),
-            diff_right: na::SMatrix::<i32, FIELD_SIZE, FIELD_SIZE>::zeros(),
+            tex_block_danger: TextureUV::of_atlas(&TEX_BLOCK_DANGER),
+            tex_block_safe: TextureUV::of_atlas(&TEX_BLOCK_SAFE),
+            shape: (0, 0),
+            rendered: None,
+            changed: Vec::new(),
+            resized: false,
         }
     }
 
-    pub fn make_diff(&mut self, field: &FieldMatrix) {
-        let slide_upward = field.clone().remove_row(0).insert_row(FIELD_SIZE - 2, 0);
-        let slide_downward = field.clone().remove_row(FIELD_SIZE - 1).insert_row(0, 0);
-        let slide_left = field.clone().remove_column(0).insert_column(FIELD_SIZE - 2, 0);
-        let slide_right = field.clone().remove_column(FIELD_SIZE - 1).insert_column(0, 0);
-
-        self.diff_up = field - slide_upward;
-        self.diff_down = field - slide_downward;
-        self.diff_left = field - slide_right; // ←  slide_leftと間違えているわけではない。1つ右にスライドした行列との差を取ると、各要素について1つ左の要素との差が取れる
-        self.diff_right = field - slide_left;
-    }
-
+    /// `field`を描く。前に描いたときから変わらない区画は描き直さない
+    ///
+    /// 前に描いたときとフィールドの大きさが違えば、区画を並べ直してすべて描き直す。
     pub fn render(&mut self, field: &FieldMatrix) {
-        // 床以外削除
-        self.vao_buffer
-            .clear_preserving_first(36 * FIELD_SIZE * FIELD_SIZE);
-        for x in 0..FIELD_SIZE {
-            for z in 0..FIELD_SIZE {
-                self.vao_buffer.add_cell(
-                    x as i32,
-                    z as i32,
-                    field[(x, z)],
-                    self.diff_up[(x, z)],
-                    self.diff_down[(x, z)],
-                    self.diff_left[(x, z)],
-                    self.diff_right[(x, z)],
+        let (rows, columns) = field.shape();
+        let (chunks_x, chunks_z) = (rows.div_ceil(CHUNK_SIZE), columns.div_ceil(CHUNK_SIZE));
+        self.resized = field.shape() != self.shape;
+        if self.resized {
+            let num_vertex = CHUNK_VERTICES * chunks_x * chunks_z;
+            self.vao_buffer.clear();
+            self.vao_buffer.append(&mut vec![0.0; num_vertex * VERTEX_SIZE]);
+            self.blocks.clear();
+            self.blocks.add_floor_edges(rows, columns);
+            self.shape = field.shape();
+            self.rendered = None;
+        }
+
+        self.changed.clear();
+        let mut chunk = VaoBuffer::with_num_vertex(CHUNK_VERTICES);
+        for cx in 0..chunks_x {
+            for cz in 0..chunks_z {
+                let xs = cx * CHUNK_SIZE..((cx + 1) * CHUNK_SIZE).min(rows);
+                let zs = cz * CHUNK_SIZE..((cz + 1) * CHUNK_SIZE).min(columns);
+                if !self.needs_redraw(field, &xs, &zs) {
+                    continue;
+                }
+                chunk.clear();
+                chunk.add_field_chunk(
+                    field,
+                    xs,
+                    zs,
                     &self.tex_block_top,
                     &self.tex_block_danger,
                     &self.tex_block_safe,
                 );
+                let mut vertices = chunk.vertices(0..chunk.vertex_num()).to_vec();
+                vertices.resize(CHUNK_VERTICES * VERTEX_SIZE, 0.0);
+
+                let first = (cx * chunks_z + cz) * CHUNK_VERTICES;
+                self.vao_buffer.overwrite(first, &vertices);
+                match self.changed.last_mut() {
+                    Some(last) if last.end == first => last.end += CHUNK_VERTICES,
+                    _ => self.changed.push(first..first + CHUNK_VERTICES),
+                }
             }
         }
+        match self.rendered {
+            Some(ref mut rendered) => rendered.copy_from(field),
+            None => self.rendered = Some(field.clone()),
+        }
+    }
+
+    /// 最後の`render`で書き換えた頂点の範囲。隣り合う範囲はまとめてある
+    pub fn changed(&self) -> &[Range<usize>] {
+        &self.changed
+    }
+
+    /// 最後の`render`で`vao_buffer`の頂点の数が変わったかどうか。変わっていれば`Vao`を作り直し、
+    /// `blocks`も渡し直す
+    pub fn resized(&self) -> bool {
+        self.resized
+    }
+
+    /// 次の`render`で、すべての区画を描き直す
+    pub fn invalidate(&mut self) {
+        self.rendered = None;
+    }
+
+    /// 側面の大きさは隣のマスとの高さの差で決まるので、区画の外側の隣のマスが変わっても描き直す
+    fn needs_redraw(&self, field: &FieldMatrix, xs: &Range<usize>, zs: &Range<usize>) -> bool {
+        let rendered = match self.rendered {
+            Some(ref rendered) => rendered,
+            None => return true,
+        };
+        let xs = xs.start.saturating_sub(1)..(xs.end + 1).min(field.nrows());
+        let zs = zs.start.saturating_sub(1)..(zs.end + 1).min(field.ncols());
+        xs.flat_map(|x| zs.clone().map(move |z| (x, z)))
+            .any(|pos| field[pos] != rendered[pos])
+    }
+}
+
+/// 1マス動くのにかけるフレーム数
+const MOVE_FRAMES: u64 = 8;
+
+/// 三次関数のease-in/ease-out
+fn ease(t: f32) -> f32 {
+    -2.0 * t * t * t + 3.0 * t * t
+}
+
+/// プレイヤーや鬼を、前に描いた位置から新しい位置まで滑らかに動かす
+struct Motion {
+    /// 動いた先
+    target: Point3,
+    x: Interpolation<f32>,
+    y: Interpolation<f32>,
+    z: Interpolation<f32>,
+    /// 動き終わるフレーム
+    until: u64,
+}
+
+impl Motion {
+    fn new(pos: Point3) -> Self {
+        Self {
+            target: pos,
+            x: Interpolation::new_constant(pos.x),
+            y: Interpolation::new_constant(pos.y),
+            z: Interpolation::new_constant(pos.z),
+            until: 0,
+        }
+    }
+
+    fn pos(&self, frames: u64) -> Point3 {
+        let t = frames as Time;
+        Point3::new(self.x.value(t), self.y.value(t), self.z.value(t))
+    }
+
+    fn is_moving(&self, frames: u64) -> bool {
+        frames <= self.until
+    }
+
+    /// `frames`から`target`に向かって動き始める
+    fn move_to(&mut self, target: Point3, frames: u64) {
+        if target == self.target {
+            return;
+        }
+        let from = self.pos(frames);
+        self.target = target;
+        // リプレイを巻き戻したときなど、隣のマスより遠くへは瞬間移動する
+        if (target.x - from.x).abs() + (target.z - from.z).abs() > 1.5 {
+            *self = Self::new(target);
+            return;
+        }
+
+        let t_0 = frames as Time;
+        let t_total = MOVE_FRAMES as TimeSpan;
+        self.x = Interpolation::new_cubic_ease_in_out(from.x, target.x, t_0, t_total);
+        self.z = Interpolation::new_cubic_ease_in_out(from.z, target.z, t_0, t_total);
+        // 登るときは先に上がってから、降りるときは進んでから下がる。ブロックにめり込まないようにする
+        self.y = if target.y > from.y {
+            Interpolation::new(from.y, target.y, t_0, t_total, |t| ease((t * 2.0).min(1.0)))
+        } else {
+            Interpolation::new(from.y, target.y, t_0, t_total, |t| ease((t * 2.0 - 1.0).max(0.0)))
+        };
+        self.until = frames + MOVE_FRAMES;
     }
 }
 
+/// プレイヤーと鬼
+///
+/// `vao_buffer`の1つの形を、`instances`のそれぞれの位置に描く。
 pub struct PlayerRenderer {
     pub vao_buffer: VaoBuffer,
+    pub instances: InstanceBuffer,
     tex_player: TextureUV,
     tex_tagger: TextureUV,
     players: Vec<Player>,
     tagger: Option<Tagger>,
+    player_motions: HashMap<Uuid, Motion>,
+    tagger_motion: Option<Motion>,
+    prediction: Option<Prediction>,
+}
+
+/// サーバーの返事を待たずに動かした自機の位置
+struct Prediction {
+    uid: Uuid,
+    pos: Point2i,
+    /// 予測と違う位置が届いても、予測を使い続ける残りの回数
+    patience: u32,
 }
 
 impl PlayerRenderer {
     pub fn new() -> Self {
+        let tex_player = TextureUV::of_atlas(&TEX_PLAYER_TMP);
+        // 正八面体の頂点を共有して描く
+        let mut vao_buffer = VaoBuffer::new_indexed();
+        vao_buffer.add_player_mesh(&tex_player);
         Self {
-            vao_buffer: VaoBuffer::with_num_vertex(24 * 2), // 1プレイヤーの頂点数は24、1つのゲームには最大2プレイヤー
-            tex_player: TextureUV::of_atlas(&TEX_PLAYER_TMP),
+            vao_buffer,
+            instances: InstanceBuffer::new(),
+            tex_player,
             tex_tagger: TextureUV::of_atlas(&TEX_TAGGER),
             players: Vec::new(),
             tagger: None,
+            player_motions: HashMap::new(),
+            tagger_motion: None,
+            prediction: None,
         }
     }
 
+    /// `pos`のマスの上のワールド座標
+    ///
+    /// サーバーから届いた位置がフィールドの外なら、フィールドの中のいちばん近いマスに寄せる。
     pub fn calc_world_pos(pos: &Point2i, field: &FieldMatrix) -> Point3 {
-        Point3::new(
-            pos.x as f32 + 0.5,
-            field[(pos.x as usize, pos.y as usize)] as f32 + 1.5,
-            pos.y as f32 + 0.5,
-        )
+        let (rows, columns) = field.shape();
+        let x = pos.x.min(rows as i32 - 1).max(0);
+        let z = pos.y.min(columns as i32 - 1).max(0);
+        let height = field.get((x as usize, z as usize)).copied().unwrap_or(0);
+        Point3::new(x as f32 + 0.5, height as f32 + 1.5, z as f32 + 0.5)
     }
 
     pub fn set_players(&mut self, players: Vec<Player>) {
+        // サーバーが移動を反映するのは次のtickなので、予測と違っても1回は待つ
+        if let Some(mut prediction) = self.prediction.take() {
+            let reached = players
+                .iter()
+                .any(|player| player.uid == prediction.uid && player.pos == prediction.pos);
+            if !reached && prediction.patience > 0 {
+                prediction.patience -= 1;
+                self.prediction = Some(prediction);
+            }
+        }
+        // いなくなったプレイヤーは、次に現れたときに動かさずに描く
+        self.player_motions
+            .retain(|uid, _| players.iter().any(|player| player.uid == *uid));
         self.players = players;
     }
 
@@ -116,18 +293,251 @@ impl PlayerRenderer {
 
     pub fn set_no_tagger(&mut self) {
         self.tagger = None;
+        self.tagger_motion = None;
     }
 
-    pub fn render(&mut self, field: &FieldMatrix) {
-        self.vao_buffer.clear();
+    pub fn players(&self) -> &[Player] {
+        &self.players
+    }
+
+    pub fn tagger(&self) -> Option<&Tagger> {
+        self.tagger.as_ref()
+    }
+
+    /// `uid`のプレイヤーを、サーバーの返事を待たずに`pos`に動かしたことにする
+    ///
+    /// 次に`set_players`で届いた位置が予測と違えば、届いた位置に戻す。
+    pub fn predict(&mut self, uid: Uuid, pos: Point2i) {
+        self.prediction = Some(Prediction {
+            uid,
+            pos,
+            patience: 1,
+        });
+    }
+
+    /// `uid`のプレイヤーを描く位置。予測していればその位置
+    pub fn displayed_pos(&self, uid: Uuid) -> Option<Point2i> {
+        match self.prediction {
+            Some(ref prediction) if prediction.uid == uid => Some(prediction.pos),
+            _ => self
+                .players
+                .iter()
+                .find(|player| player.uid == uid)
+                .map(|player| player.pos),
+        }
+    }
+
+    /// プレイヤーと、`render`で描いている位置。まだ描いていないプレイヤーは含まない
+    pub fn player_positions(&self, frames: u64) -> Vec<(&Player, Point3)> {
+        self.players
+            .iter()
+            .filter_map(|player| {
+                let motion = self.player_motions.get(&player.uid)?;
+                Some((player, motion.pos(frames)))
+            })
+            .collect()
+    }
+
+    /// 動いている途中のプレイヤーや鬼がいるかどうか
+    pub fn is_moving(&self, frames: u64) -> bool {
+        self.player_motions
+            .values()
+            .chain(self.tagger_motion.iter())
+            .any(|motion| motion.is_moving(frames))
+    }
+
+    /// `frames`はゲーム開始からの総フレーム数
+    pub fn render(&mut self, field: &FieldMatrix, frames: u64) {
+        self.instances.clear();
         for player in &self.players {
-            let player_pos = Self::calc_world_pos(&player.pos, field);
-            self.vao_buffer
-                .add_player(&player_pos, &player.name, &self.tex_player);
+            let pos = match self.prediction {
+                Some(ref prediction) if prediction.uid == player.uid => prediction.pos,
+                _ => player.pos,
+            };
+            let target = Self::calc_world_pos(&pos, field);
+            let motion = self
+                .player_motions
+                .entry(player.uid)
+                .or_insert_with(|| Motion::new(target));
+            motion.move_to(target, frames);
+            self.instances.add_player(&motion.pos(frames));
         }
         if let Some(ref tagger) = self.tagger {
-            let tagger_pos = Self::calc_world_pos(&tagger.pos, field);
-            self.vao_buffer.add_tagger(&tagger_pos, &self.tex_tagger);
+            let target = Self::calc_world_pos(&tagger.pos, field);
+            let motion = self.tagger_motion.get_or_insert_with(|| Motion::new(target));
+            motion.move_to(target, frames);
+            self.instances
+                .add_tagger(&motion.pos(frames), &self.tex_player, &self.tex_tagger);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FIELD_SIZE;
+    use re::vao::instance_buffer::INSTANCE_SIZE;
+
+    #[test]
+    fn field_redraws_changed_cells() {
+        let mut field = FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 1);
+        let mut renderer = FieldRenderer::new();
+        renderer.render(&field);
+        let total = renderer.vao_buffer.vertex_num();
+        assert_eq!(renderer.changed().to_vec(), vec![0..total]);
+
+        field[(3, 4)] = 3;
+        field[(31, 0)] = 5;
+        // 区画の端のマスは、隣の区画の側面も変える
+        field[(8, 20)] = 2;
+        renderer.render(&field);
+        let chunks: usize = renderer.changed().iter().map(|range| range.len()).sum();
+        assert_eq!(chunks, CHUNK_VERTICES * (1 + 1 + 2));
+        assert_eq!(renderer.vao_buffer.vertex_num(), total);
+
+        // 初めから描いたものと同じになる
+        let mut full = FieldRenderer::new();
+        full.render(&field);
+        assert_eq!(
+            renderer.vao_buffer.vertices(0..total),
+            full.vao_buffer.vertices(0..total)
+        );
+
+        renderer.render(&field);
+        assert!(renderer.changed().is_empty());
+        assert!(!renderer.resized());
+    }
+
+    #[test]
+    fn field_resizes_to_new_shape() {
+        let mut renderer = FieldRenderer::new();
+        renderer.render(&FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 1));
+        assert!(renderer.resized());
+
+        // 端の区画は`CHUNK_SIZE`より小さい
+        let field = FieldMatrix::from_fn(20, 41, |x, z| ((x + z) % 4) as i32);
+        renderer.render(&field);
+        assert!(renderer.resized());
+        let total = CHUNK_VERTICES * 3 * 6;
+        assert_eq!(renderer.vao_buffer.vertex_num(), total);
+        assert_eq!(renderer.changed().to_vec(), vec![0..total]);
+
+        renderer.render(&field);
+        assert!(!renderer.resized());
+        assert!(renderer.changed().is_empty());
+    }
+
+    #[test]
+    fn floor_edges_are_instances_of_one_block() {
+        let mut renderer = FieldRenderer::new();
+        renderer.render(&FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 1));
+        // 4つの側面を、4つの頂点を共有して描く
+        assert_eq!(renderer.block_buffer.vertex_num(), 4 * 4);
+        assert_eq!(renderer.blocks.instance_num(), 4 * (FIELD_SIZE - 1));
+
+        let field = FieldMatrix::zeros(3, 5);
+        renderer.render(&field);
+        assert_eq!(renderer.blocks.instance_num(), 3 * 5 - 3);
+        let instance = |i: usize| &renderer.blocks.instances()[i * INSTANCE_SIZE..][..INSTANCE_SIZE];
+        assert_eq!(instance(0), &[0.5, 0.5, 0.5, 1.0, 1.0, 1.0, 0.0, 0.0]);
+        // 真ん中のマス(1, 1..4)は飛ばす
+        assert_eq!(&instance(6)[..3], &[1.5, 0.5, 4.5]);
+
+        // 高さが変わっても、ブロックは変わらない
+        renderer.render(&field.add_scalar(2));
+        assert_eq!(renderer.blocks.instance_num(), 3 * 5 - 3);
+    }
+
+    #[test]
+    fn motion_climbs_before_moving() {
+        let mut motion = Motion::new(Point3::new(0.5, 1.5, 0.5));
+        motion.move_to(Point3::new(1.5, 2.5, 0.5), 10);
+        assert!(motion.is_moving(10 + MOVE_FRAMES));
+        assert!(!motion.is_moving(11 + MOVE_FRAMES));
+
+        // 半分の時間で登り終わっている
+        let half = motion.pos(10 + MOVE_FRAMES / 2);
+        assert_eq!(half.y, 2.5);
+        assert!(half.x < 1.5);
+        assert_eq!(motion.pos(10 + MOVE_FRAMES), Point3::new(1.5, 2.5, 0.5));
+
+        // 遠くへは瞬間移動する
+        motion.move_to(Point3::new(9.5, 1.5, 9.5), 30);
+        assert_eq!(motion.pos(30), Point3::new(9.5, 1.5, 9.5));
+        assert!(!motion.is_moving(30));
+    }
+
+    #[test]
+    fn players_are_instances_of_one_mesh() {
+        let field = FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 2);
+        let players = (0..5)
+            .map(|i| Player::new(Point2i::new(i, 0), Uuid::from_u128(i as u128), i.to_string()))
+            .collect();
+        let mut renderer = PlayerRenderer::new();
+        renderer.set_players(players);
+        renderer.set_tagger(Tagger::new(Point2i::new(9, 9)));
+        renderer.render(&field, 0);
+
+        // 人数が増えても形は1つ
+        assert_eq!(renderer.vao_buffer.vertex_num(), 6);
+        assert_eq!(renderer.instances.instance_num(), 5 + 1);
+        let instance = |i: usize| &renderer.instances.instances()[i * INSTANCE_SIZE..][..INSTANCE_SIZE];
+        assert_eq!(instance(4), &[4.5, 3.5, 0.5, 1.0, 1.0, 1.0, 0.0, 0.0]);
+        // 鬼にはプレイヤーのテクスチャからずらして鬼のテクスチャを貼る
+        let (player, tagger) = (
+            TextureUV::of_atlas(&TEX_PLAYER_TMP),
+            TextureUV::of_atlas(&TEX_TAGGER),
+        );
+        assert_eq!(&instance(5)[..3], &[9.5, 3.5, 9.5]);
+        assert_eq!(player.begin_u + instance(5)[6], tagger.begin_u);
+        assert_eq!(player.begin_v + instance(5)[7], tagger.begin_v);
+    }
+
+    #[test]
+    fn positions_outside_field_are_clamped() {
+        let mut field = FieldMatrix::zeros(4, 6);
+        field[(0, 5)] = 2;
+        assert_eq!(
+            PlayerRenderer::calc_world_pos(&Point2i::new(-3, 9), &field),
+            Point3::new(0.5, 3.5, 5.5)
+        );
+        assert_eq!(
+            PlayerRenderer::calc_world_pos(&Point2i::new(2, 3), &FieldMatrix::zeros(0, 0)),
+            Point3::new(0.5, 1.5, 0.5)
+        );
+
+        // フィールドの外のプレイヤーや鬼が届いても描ける
+        let mut renderer = PlayerRenderer::new();
+        renderer.set_players(vec![Player::new(
+            Point2i::new(4, -1),
+            Uuid::new_v4(),
+            "outside".to_string(),
+        )]);
+        renderer.set_tagger(Tagger::new(Point2i::new(i32::MIN, i32::MAX)));
+        renderer.render(&field, 0);
+        let instances = renderer.instances.instances();
+        assert_eq!(&instances[..3], &[3.5, 1.5, 0.5]);
+        assert_eq!(&instances[INSTANCE_SIZE..][..3], &[0.5, 3.5, 5.5]);
+    }
+
+    #[test]
+    fn prediction_waits_one_update() {
+        let uid = Uuid::new_v4();
+        let player = |x, y| vec![Player::new(Point2i::new(x, y), uid, "me".to_string())];
+        let mut renderer = PlayerRenderer::new();
+        renderer.set_players(player(5, 5));
+
+        renderer.predict(uid, Point2i::new(6, 5));
+        // まだ移動が反映されていないフィールドが届いても、予測した位置に描く
+        renderer.set_players(player(5, 5));
+        assert_eq!(renderer.displayed_pos(uid), Some(Point2i::new(6, 5)));
+        // 2回続けて違えば、届いた位置に戻す
+        renderer.set_players(player(5, 5));
+        assert_eq!(renderer.displayed_pos(uid), Some(Point2i::new(5, 5)));
+
+        renderer.predict(uid, Point2i::new(5, 6));
+        renderer.set_players(player(5, 6));
+        renderer.set_players(player(5, 7));
+        assert_eq!(renderer.displayed_pos(uid), Some(Point2i::new(5, 7)));
+    }
+}
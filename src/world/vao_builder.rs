@@ -1,147 +1,552 @@
+use std::ops::Range;
+
 use crate::types::*;
-use re::vao::VaoBuffer;
+use re::vao::{InstanceBuffer, VaoBuffer};
+
+/// フィールドのメッシュのUV座標で、アトラスの1つのテクスチャに割り当てる幅。`field.frag`と同じ値にする
+///
+/// まとめた面ではテクスチャを繰り返すので、UV座標を「アトラスの何番目のテクスチャか × `TILE_STRIDE`
+/// + `TILE_OFFSET` + 繰り返しの中の位置」で表す。補間の誤差で隣の番号にはみ出さないよう、`TILE_OFFSET`ずらす。
+pub const TILE_STRIDE: f32 = 16.0;
+const TILE_OFFSET: f32 = 4.0;
+
+/// 面をまとめる範囲の1辺のマス数。これより大きくまとめるとUV座標が`TILE_STRIDE`に収まらない
+pub const CHUNK_SIZE: usize = 8;
+
+/// 側面の向き。上下左右はカメラから見た向き
+#[derive(Clone, Copy)]
+enum Side {
+    /// +x方向
+    Up,
+    /// -x方向
+    Down,
+    /// -z方向
+    Left,
+    /// +z方向
+    Right,
+}
+
+const SIDES: [Side; 4] = [Side::Up, Side::Down, Side::Left, Side::Right];
+
+impl Side {
+    /// 向いている方向の隣のマスへの(x, z)の差
+    fn offset(self) -> (i32, i32) {
+        match self {
+            Side::Up => (1, 0),
+            Side::Down => (-1, 0),
+            Side::Left => (0, -1),
+            Side::Right => (0, 1),
+        }
+    }
+
+    /// 面が並ぶ方向がzなら`true`
+    fn spans_z(self) -> bool {
+        matches!(self, Side::Up | Side::Down)
+    }
+}
 
 /// ReverieEngineのVaoBufferに、フィールド描画の機能を追加するためのトレイト
 pub trait VaoBuilderForField {
-    /// ステージの床を追加する
-    fn add_floor(&mut self, width: usize, height: usize, texture: &TextureUV);
-
-    fn add_cell(
+    /// `field`のうち、x座標が`xs`、z座標が`zs`の範囲のマスを追加する
+    ///
+    /// 同じ高さで隣り合う上面と、同じ高さと形で並ぶ側面は1つの長方形にまとめる。
+    /// 真上から見下ろすカメラには映らない面(底面、隣のマスに接する面、上面の下の床)は追加しない。
+    /// フィールドの端に見える床の側面は、`InstanceBuilderForField`で床のブロックとして描く。
+    ///
+    /// テクスチャのUV座標は`TILE_STRIDE`の形で書くので、`field.frag`で描く。
+    fn add_field_chunk(
         &mut self,
-        x: i32,
-        z: i32,
-        height: i32,
-        diff_up: i32,
-        diff_down: i32,
-        diff_left: i32,
-        diff_right: i32,
+        field: &FieldMatrix,
+        xs: Range<usize>,
+        zs: Range<usize>,
         tex_top: &TextureUV,
         tex_danger: &TextureUV,
         tex_safe: &TextureUV,
     );
+
+    /// 原点を中心とする1辺1の床のブロックの形を追加する。位置は`InstanceBuilderForField`で決める
+    ///
+    /// 上面はマスの上面と重なり、底面はカメラに映らないので、4つの側面だけを追加する。
+    fn add_floor_block_mesh(&mut self, tex_floor: &TextureUV);
 }
 
-fn add_block(
-    vao_builder: &mut VaoBuffer,
-    x: f32,
-    y: f32,
-    z: f32,
-    dx: f32,
-    dy: f32,
-    dz: f32,
-    textures: &CuboidTextures,
-) {
-    vao_builder.add_cuboid(
-        &Point3::new(x, y, z),
-        &Point3::new(x + dx, y + dy, z + dz),
-        &textures,
-    );
+/// フィールドの外の高さは0とする
+fn height(field: &FieldMatrix, x: i32, z: i32) -> i32 {
+    if 0 <= x && (x as usize) < field.nrows() && 0 <= z && (z as usize) < field.ncols() {
+        field[(x as usize, z as usize)]
+    } else {
+        0
+    }
 }
 
-impl VaoBuilderForField for VaoBuffer {
-    fn add_floor(&mut self, width: usize, height: usize, texture: &TextureUV) {
-        let textures = CuboidTextures {
-            top: texture,
-            bottom: texture,
-            south: texture,
-            north: texture,
-            west: texture,
-            east: texture,
-        };
-        for x in 0..width as i32 {
-            for z in 0..height as i32 {
-                add_block(self, x as f32, 0.0, z as f32, 1.0, 1.0, 1.0, &textures);
-            }
-        }
+/// (`x`, `z`)のマスの`side`側の側面の(上端, 下端)。隣のマスより高くなければ`None`
+fn wall(field: &FieldMatrix, side: Side, x: usize, z: usize) -> Option<(i32, i32)> {
+    let (dx, dz) = side.offset();
+    let own = field[(x, z)];
+    let neighbor = height(field, x as i32 + dx, z as i32 + dz);
+    if own > neighbor {
+        Some((own + 1, neighbor + 1))
+    } else {
+        None
     }
+}
 
-    /// マス目を描画
-    ///
-    /// * `diff_up` - 1つ上のマス目に比べて、描画するマス目がどれだけ高いか
-    /// * `diff_down` - 1つ下のマス目に比べて、描画するマス目がどれだけ高いか
-    fn add_cell(
+/// 面を追加する。`add_face`と同じ順に頂点を並べ、テクスチャを`p1`→`p4`の向きに`repeat.0`回、
+/// `p1`→`p2`の向きに`repeat.1`回繰り返す
+fn add_tiled_face(
+    buffer: &mut VaoBuffer,
+    p1: &Point3,
+    p2: &Point3,
+    p3: &Point3,
+    p4: &Point3,
+    uv: &TextureUV,
+    (repeat_u, repeat_v): (f32, f32),
+) {
+    debug_assert!(repeat_u.max(repeat_v) <= TILE_STRIDE - TILE_OFFSET * 2.0);
+    let normal = (p3 - p1).cross(&(p2 - p4)).normalize();
+    let begin_u = (uv.begin_u / (uv.end_u - uv.begin_u)).round() * TILE_STRIDE + TILE_OFFSET;
+    let begin_v = (uv.begin_v / (uv.end_v - uv.begin_v)).round() * TILE_STRIDE + TILE_OFFSET;
+    let end_u = begin_u + repeat_u;
+    let end_v = begin_v + repeat_v;
+    #[rustfmt::skip]
+    let mut v: Vec<f32> = vec![
+        p1.x, p1.y, p1.z, normal.x, normal.y, normal.z, begin_u, end_v,
+        p2.x, p2.y, p2.z, normal.x, normal.y, normal.z, begin_u, begin_v,
+        p3.x, p3.y, p3.z, normal.x, normal.y, normal.z, end_u, begin_v,
+
+        p1.x, p1.y, p1.z, normal.x, normal.y, normal.z, begin_u, end_v,
+        p3.x, p3.y, p3.z, normal.x, normal.y, normal.z, end_u, begin_v,
+        p4.x, p4.y, p4.z, normal.x, normal.y, normal.z, end_u, end_v,
+    ];
+    buffer.append(&mut v);
+}
+
+/// `side`を向いた側面を追加する。面は`side`側の境界`plane`にあり、`span`の範囲に並ぶ
+///
+/// テクスチャは横にはマスごとに繰り返し、縦には伸ばす。
+fn add_wall(
+    buffer: &mut VaoBuffer,
+    side: Side,
+    plane: usize,
+    span: Range<usize>,
+    top: i32,
+    bottom: i32,
+    uv: &TextureUV,
+) {
+    let (begin, end) = (span.start as f32, span.end as f32);
+    let (p, top, bottom) = (plane as f32, top as f32, bottom as f32);
+    let (p1, p2, p3, p4) = match side {
+        Side::Up => (
+            Point3::new(p, top, end),
+            Point3::new(p, bottom, end),
+            Point3::new(p, bottom, begin),
+            Point3::new(p, top, begin),
+        ),
+        Side::Down => (
+            Point3::new(p, top, begin),
+            Point3::new(p, bottom, begin),
+            Point3::new(p, bottom, end),
+            Point3::new(p, top, end),
+        ),
+        Side::Left => (
+            Point3::new(end, top, p),
+            Point3::new(end, bottom, p),
+            Point3::new(begin, bottom, p),
+            Point3::new(begin, top, p),
+        ),
+        Side::Right => (
+            Point3::new(begin, top, p),
+            Point3::new(begin, bottom, p),
+            Point3::new(end, bottom, p),
+            Point3::new(end, top, p),
+        ),
+    };
+    add_tiled_face(buffer, &p1, &p2, &p3, &p4, uv, (span.len() as f32, 1.0));
+}
+
+impl VaoBuilderForField for VaoBuffer {
+    fn add_field_chunk(
         &mut self,
-        x: i32,
-        z: i32,
-        height: i32,
-        diff_up: i32,
-        diff_down: i32,
-        diff_left: i32,
-        diff_right: i32,
+        field: &FieldMatrix,
+        xs: Range<usize>,
+        zs: Range<usize>,
         tex_top: &TextureUV,
         tex_danger: &TextureUV,
         tex_safe: &TextureUV,
     ) {
-        let p_lu = Point3::new((x + 1) as f32, (height + 1) as f32, z as f32); // 上面の左上の点
-        let p_ld = Point3::new(x as f32, (height + 1) as f32, z as f32); // 左下
-        let p_rd = Point3::new(x as f32, (height + 1) as f32, (z + 1) as f32); // 右下
-        let p_ru = Point3::new((x + 1) as f32, (height + 1) as f32, (z + 1) as f32); // 右上
+        // 上面。まだまとめていないマスから+z方向、次に+x方向へ、同じ高さのマスを広げる
+        let index = |x: usize, z: usize| (x - xs.start) * zs.len() + (z - zs.start);
+        let mut merged = vec![false; xs.len() * zs.len()];
+        for x in xs.clone() {
+            for z in zs.clone() {
+                if merged[index(x, z)] {
+                    continue;
+                }
+                let h = field[(x, z)];
+                let same =
+                    |merged: &[bool], x: usize, z: usize| field[(x, z)] == h && !merged[index(x, z)];
+                let mut end_z = z + 1;
+                while end_z < zs.end && same(&merged, x, end_z) {
+                    end_z += 1;
+                }
+                let mut end_x = x + 1;
+                while end_x < xs.end && (z..end_z).all(|z| same(&merged, end_x, z)) {
+                    end_x += 1;
+                }
+                for x in x..end_x {
+                    for z in z..end_z {
+                        merged[index(x, z)] = true;
+                    }
+                }
 
-        // 上面(カメラから見て正面にある面)
-        self.add_face(&p_lu, &p_ld, &p_rd, &p_ru, tex_top);
-
-        // 上の面(カメラから見て上にある面)
-        if diff_up > 0 {
-            let diff_up_vec = Vector3::new(0.0, diff_up as f32, 0.0);
-            self.add_face(
-                &p_ru,
-                &(p_ru - diff_up_vec),
-                &(p_lu - diff_up_vec),
-                &p_lu,
-                if diff_up == 1 { tex_safe } else { tex_danger },
-            );
+                let y = (h + 1) as f32;
+                let (x0, x1, z0, z1) = (x as f32, end_x as f32, z as f32, end_z as f32);
+                add_tiled_face(
+                    self,
+                    &Point3::new(x1, y, z0),
+                    &Point3::new(x0, y, z0),
+                    &Point3::new(x0, y, z1),
+                    &Point3::new(x1, y, z1),
+                    tex_top,
+                    ((end_z - z) as f32, (end_x - x) as f32),
+                );
+            }
         }
 
-        // 下の面
-        if diff_down > 0 {
-            let diff_down_vec = Vector3::new(0.0, diff_down as f32, 0.0);
-            self.add_face(
-                &p_ld,
-                &(p_ld - diff_down_vec),
-                &(p_rd - diff_down_vec),
-                &p_rd,
-                if diff_down == 1 { tex_safe } else { tex_danger },
-            );
+        // 側面。隣のマスより高い部分だけを、同じ上端と下端の面が続く間まとめる
+        for &side in SIDES.iter() {
+            let (lines, spans) = if side.spans_z() {
+                (xs.clone(), zs.clone())
+            } else {
+                (zs.clone(), xs.clone())
+            };
+            let (dx, dz) = side.offset();
+            for line in lines {
+                // 面が`side`側の境界にあるので、+x, +z向きなら1つ先
+                let plane = (line as i32 + dx.max(0) + dz.max(0)) as usize;
+                let mut run: Option<(usize, (i32, i32))> = None;
+                for s in spans.start..=spans.end {
+                    let current = if s < spans.end {
+                        let (x, z) = if side.spans_z() { (line, s) } else { (s, line) };
+                        wall(field, side, x, z)
+                    } else {
+                        None
+                    };
+                    if let Some((start, (top, bottom))) = run {
+                        if current == Some((top, bottom)) {
+                            continue;
+                        }
+                        let tex = if top - bottom == 1 { tex_safe } else { tex_danger };
+                        add_wall(self, side, plane, start..s, top, bottom, tex);
+                    }
+                    run = current.map(|wall| (s, wall));
+                }
+            }
         }
+    }
 
-        // 左の面
-        if diff_left > 0 {
-            let diff_left_vec = Vector3::new(0.0, diff_left as f32, 0.0);
+    fn add_floor_block_mesh(&mut self, tex_floor: &TextureUV) {
+        // `add_cuboid`の側面と同じ向きに、上端の2つの角から下ろす
+        let corners = [
+            (Point3::new(-0.5, 0.5, -0.5), Point3::new(-0.5, 0.5, 0.5)),
+            (Point3::new(0.5, 0.5, 0.5), Point3::new(0.5, 0.5, -0.5)),
+            (Point3::new(0.5, 0.5, -0.5), Point3::new(-0.5, 0.5, -0.5)),
+            (Point3::new(-0.5, 0.5, 0.5), Point3::new(0.5, 0.5, 0.5)),
+        ];
+        let down = Vector3::new(0.0, 1.0, 0.0);
+        for (top_left, top_right) in corners.iter() {
             self.add_face(
-                &p_lu,
-                &(p_lu - diff_left_vec),
-                &(p_ld - diff_left_vec),
-                &p_ld,
-                if diff_left == 1 { tex_safe } else { tex_danger },
+                top_left,
+                &(top_left - down),
+                &(top_right - down),
+                top_right,
+                tex_floor,
             );
         }
+    }
+}
 
-        // 右の面
-        if diff_right > 0 {
-            let diff_right_vec = Vector3::new(0.0, diff_right as f32, 0.0);
-            self.add_face(
-                &p_rd,
-                &(p_rd - diff_right_vec),
-                &(p_ru - diff_right_vec),
-                &p_ru,
-                if diff_right == 1 { tex_safe } else { tex_danger },
-            );
+/// ReverieEngineのInstanceBufferに、フィールド描画の機能を追加するためのトレイト
+pub trait InstanceBuilderForField {
+    /// `rows`×`columns`のフィールドの、端のマスの床のブロックを追加する。形は`add_floor_block_mesh`で作る
+    fn add_floor_edges(&mut self, rows: usize, columns: usize);
+}
+
+impl InstanceBuilderForField for InstanceBuffer {
+    fn add_floor_edges(&mut self, rows: usize, columns: usize) {
+        for x in 0..rows {
+            for z in 0..columns {
+                if x == 0 || x + 1 == rows || z == 0 || z + 1 == columns {
+                    let translation = Vector3::new(x as f32 + 0.5, 0.5, z as f32 + 0.5);
+                    self.add(&translation, &Vector3::new(1.0, 1.0, 1.0), (0.0, 0.0));
+                }
+            }
         }
     }
 }
 
 /// ReverieEngineのVaoBufferに、プレイヤー描画の機能を追加するためのトレイト
 pub trait VaoBuilderForPlayer {
-    fn add_player(&mut self, player_pos: &Point3, player_name: &str, tex_player: &TextureUV);
-    fn add_tagger(&mut self, tagger_pos: &Point3, tex_tagger: &TextureUV);
+    /// 原点を中心とするプレイヤーの形を追加する。位置は`InstanceBuilderForPlayer`で決める
+    fn add_player_mesh(&mut self, tex_player: &TextureUV);
 }
 
 impl VaoBuilderForPlayer for VaoBuffer {
-    fn add_player(&mut self, player_pos: &Point3, _player_name: &str, tex_player: &TextureUV) {
-        self.add_octahedron(&player_pos, 0.5, tex_player)
+    fn add_player_mesh(&mut self, tex_player: &TextureUV) {
+        self.add_octahedron(&Point3::origin(), 0.5, tex_player)
+    }
+}
+
+/// ReverieEngineのInstanceBufferに、プレイヤー描画の機能を追加するためのトレイト
+pub trait InstanceBuilderForPlayer {
+    fn add_player(&mut self, player_pos: &Point3);
+    /// 鬼はプレイヤーと同じ形で、`tex_player`の代わりに`tex_tagger`を貼る
+    fn add_tagger(&mut self, tagger_pos: &Point3, tex_player: &TextureUV, tex_tagger: &TextureUV);
+}
+
+impl InstanceBuilderForPlayer for InstanceBuffer {
+    fn add_player(&mut self, player_pos: &Point3) {
+        self.add(&player_pos.coords, &Vector3::new(1.0, 1.0, 1.0), (0.0, 0.0))
+    }
+
+    fn add_tagger(&mut self, tagger_pos: &Point3, tex_player: &TextureUV, tex_tagger: &TextureUV) {
+        let tex_offset = (
+            tex_tagger.begin_u - tex_player.begin_u,
+            tex_tagger.begin_v - tex_player.begin_v,
+        );
+        self.add(&tagger_pos.coords, &Vector3::new(1.0, 1.0, 1.0), tex_offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::{FIELD_SIZE, TEX_ATLAS_H, TEX_ATLAS_W, TEX_H, TEX_W};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use re::texture::texture_atlas::TextureAtlasPos;
+    use re::vao::instance_buffer::INSTANCE_SIZE;
+    use re::vao::vao_buffer::VERTEX_SIZE;
+
+    /// 面の1マス分。(中心の座標の2倍, 法線, アトラスの何番目のテクスチャか)
+    type Sample = ([i32; 3], [i32; 3], (i32, i32));
+
+    fn textures() -> [TextureUV; 3] {
+        [
+            TextureUV::of_atlas(&TextureAtlasPos::new(0, 0)),
+            TextureUV::of_atlas(&TextureAtlasPos::new(0, 2)),
+            TextureUV::of_atlas(&TextureAtlasPos::new(0, 3)),
+        ]
+    }
+
+    /// 面をまとめず、マスごとに床の立方体と上面と側面を追加していたときのメッシュ
+    fn naive(field: &FieldMatrix) -> VaoBuffer {
+        let [top, danger, safe] = textures();
+        let floor = CuboidTextures {
+            top: &top,
+            bottom: &top,
+            south: &top,
+            north: &top,
+            west: &top,
+            east: &top,
+        };
+        let mut buffer = VaoBuffer::new();
+        for x in 0..field.nrows() {
+            for z in 0..field.ncols() {
+                let (x0, z0) = (x as f32, z as f32);
+                buffer.add_cuboid(
+                    &Point3::new(x0, 0.0, z0),
+                    &Point3::new(x0 + 1.0, 1.0, z0 + 1.0),
+                    &floor,
+                );
+            }
+        }
+        for x in 0..field.nrows() {
+            for z in 0..field.ncols() {
+                let h = field[(x, z)];
+                let diff = |dx: i32, dz: i32| h - height(field, x as i32 + dx, z as i32 + dz);
+                let y = (h + 1) as f32;
+                let (x0, z0) = (x as f32, z as f32);
+                let p_lu = Point3::new(x0 + 1.0, y, z0);
+                let p_ld = Point3::new(x0, y, z0);
+                let p_rd = Point3::new(x0, y, z0 + 1.0);
+                let p_ru = Point3::new(x0 + 1.0, y, z0 + 1.0);
+                buffer.add_face(&p_lu, &p_ld, &p_rd, &p_ru, &top);
+
+                let sides = [
+                    (diff(1, 0), p_ru, p_lu),
+                    (diff(-1, 0), p_ld, p_rd),
+                    (diff(0, -1), p_lu, p_ld),
+                    (diff(0, 1), p_rd, p_ru),
+                ];
+                for &(d, a, b) in sides.iter() {
+                    if d > 0 {
+                        let down = Vector3::new(0.0, d as f32, 0.0);
+                        let tex = if d == 1 { &safe } else { &danger };
+                        buffer.add_face(&a, &(a - down), &(b - down), &b, tex);
+                    }
+                }
+            }
+        }
+        buffer
+    }
+
+    fn greedy(field: &FieldMatrix) -> VaoBuffer {
+        let [top, danger, safe] = textures();
+        let mut buffer = VaoBuffer::new();
+        let (rows, columns) = field.shape();
+        for x in (0..rows).step_by(CHUNK_SIZE) {
+            for z in (0..columns).step_by(CHUNK_SIZE) {
+                buffer.add_field_chunk(
+                    field,
+                    x..(x + CHUNK_SIZE).min(rows),
+                    z..(z + CHUNK_SIZE).min(columns),
+                    &top,
+                    &danger,
+                    &safe,
+                );
+            }
+        }
+        buffer
+    }
+
+    /// 端のマスの床のブロックを、インスタンスごとに動かして1つのメッシュに並べる
+    fn floor_edges(field: &FieldMatrix) -> VaoBuffer {
+        let [top, _, _] = textures();
+        let mut mesh = VaoBuffer::new();
+        mesh.add_floor_block_mesh(&top);
+        let mut instances = InstanceBuffer::new();
+        instances.add_floor_edges(field.nrows(), field.ncols());
+
+        let mesh = mesh.vertices(0..mesh.vertex_num());
+        let mut buffer = VaoBuffer::new();
+        for instance in instances.instances().chunks(INSTANCE_SIZE) {
+            for vertex in mesh.chunks(VERTEX_SIZE) {
+                let mut vertex = vertex.to_vec();
+                for i in 0..3 {
+                    vertex[i] = vertex[i] * instance[3 + i] + instance[i];
+                }
+                vertex[6] += instance[6];
+                vertex[7] += instance[7];
+                buffer.append(&mut vertex);
+            }
+        }
+        buffer
+    }
+
+    /// メッシュの面を1マスずつに分ける。`tile`はUV座標の最小値からテクスチャの番号を求める
+    fn samples(buffer: &VaoBuffer, tile: impl Fn(f32, f32) -> (i32, i32)) -> Vec<Sample> {
+        let vertices = buffer.vertices(0..buffer.vertex_num());
+        let mut samples = Vec::new();
+        for face in vertices.chunks(VERTEX_SIZE * 6) {
+            let vertex = |i: usize| &face[i * VERTEX_SIZE..(i + 1) * VERTEX_SIZE];
+            let point = |i: usize| Point3::new(vertex(i)[0], vertex(i)[1], vertex(i)[2]);
+            let (p1, p2, p4) = (point(0), point(1), point(5));
+            let (a, b) = (p2 - p1, p4 - p1);
+            let normal = [vertex(0)[3], vertex(0)[4], vertex(0)[5]].map(|n| n.round() as i32);
+            let min_u = (0..6).map(|i| vertex(i)[6]).fold(f32::MAX, f32::min);
+            let min_v = (0..6).map(|i| vertex(i)[7]).fold(f32::MAX, f32::min);
+            let tile = tile(min_u, min_v);
+            for i in 0..a.norm().round() as usize {
+                for j in 0..b.norm().round() as usize {
+                    let center =
+                        p1 + a.normalize() * (i as f32 + 0.5) + b.normalize() * (j as f32 + 0.5);
+                    let center = [center.x, center.y, center.z].map(|c| (c * 2.0).round() as i32);
+                    samples.push((center, normal, tile));
+                }
+            }
+        }
+        samples
+    }
+
+    /// 真上からのカメラに映る面
+    ///
+    /// 底面と、向かい合って重なる面(隣り合う床の立方体の間)を除き、上面はそれぞれの(x, z)で最も高いものだけを残す。
+    /// `add_face`の法線は面の裏側を向くので、上面の法線は-y方向。
+    fn visible(samples: Vec<Sample>) -> BTreeSet<Sample> {
+        let all: BTreeSet<([i32; 3], [i32; 3])> = samples.iter().map(|s| (s.0, s.1)).collect();
+        let mut highest = std::collections::HashMap::new();
+        for (center, normal, _) in &samples {
+            if normal[1] == -1 {
+                let y = highest.entry((center[0], center[2])).or_insert(center[1]);
+                *y = (*y).max(center[1]);
+            }
+        }
+        samples
+            .into_iter()
+            .filter(|(center, normal, _)| match normal[1] {
+                1 => false,
+                -1 => highest[&(center[0], center[2])] == center[1],
+                _ => !all.contains(&(*center, normal.map(|n| -n))),
+            })
+            .collect()
+    }
+
+    fn assert_same_surface(field: &FieldMatrix) -> (usize, usize) {
+        let tile_w = TEX_W as f32 / TEX_ATLAS_W as f32;
+        let tile_h = TEX_H as f32 / TEX_ATLAS_H as f32;
+        let naive_buffer = naive(field);
+        let expected = visible(samples(&naive_buffer, |u, v| {
+            ((u / tile_w).round() as i32, (v / tile_h).round() as i32)
+        }));
+
+        let greedy_buffer = greedy(field);
+        let mut actual = samples(&greedy_buffer, |u, v| {
+            ((u / TILE_STRIDE).floor() as i32, (v / TILE_STRIDE).floor() as i32)
+        });
+        // 床のブロックは側面をすべて描くので、フィールドの外を向く面だけを比べる
+        let (rows, columns) = (field.nrows() as i32, field.ncols() as i32);
+        let edges = samples(&floor_edges(field), |u, v| {
+            ((u / tile_w).round() as i32, (v / tile_h).round() as i32)
+        });
+        actual.extend(
+            edges
+                .into_iter()
+                .filter(|([x, _, z], _, _)| *x == 0 || *x == rows * 2 || *z == 0 || *z == columns * 2),
+        );
+        // 隠れる面を作らず、同じ場所に2つの面を作らない
+        assert_eq!(actual.len(), expected.len());
+        assert_eq!(actual.into_iter().collect::<BTreeSet<_>>(), expected);
+        (naive_buffer.vertex_num(), greedy_buffer.vertex_num())
     }
 
-    fn add_tagger(&mut self, tagger_pos: &Point3, tex_tagger: &TextureUV) {
-        self.add_octahedron(&tagger_pos, 0.5, tex_tagger)
+    #[test]
+    fn flat_field_is_one_face_per_chunk() {
+        let (naive, greedy) = assert_same_surface(&FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE));
+        let chunks = (FIELD_SIZE / CHUNK_SIZE).pow(2);
+        // 区画ごとに上面が1つ
+        assert_eq!(greedy, 6 * chunks);
+        assert!(greedy * 100 < naive);
+    }
+
+    #[test]
+    fn steps_and_towers() {
+        let mut field = FieldMatrix::repeat(FIELD_SIZE, FIELD_SIZE, 1);
+        for z in 0..FIELD_SIZE {
+            field[(10, z)] = 2;
+            field[(11, z)] = 4;
+        }
+        field[(0, 0)] = 8;
+        field[(FIELD_SIZE - 1, FIELD_SIZE - 1)] = 3;
+        field[(7, 8)] = 0;
+        assert_same_surface(&field);
+    }
+
+    #[test]
+    fn random_fields() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for i in 0..20 {
+            // 区画の大きさで割り切れない、長方形のフィールドも試す
+            let (rows, columns) = if i < 10 {
+                (FIELD_SIZE, FIELD_SIZE)
+            } else {
+                (rng.gen_range(1..=40), rng.gen_range(1..=40))
+            };
+            let field = FieldMatrix::from_fn(rows, columns, |_, _| rng.gen_range(0..=8));
+            let (naive, greedy) = assert_same_surface(&field);
+            assert!(greedy < naive);
+        }
     }
 }
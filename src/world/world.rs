@@ -1,12 +1,16 @@
+use super::grid::{in_field, moved};
 use super::renderer::{FieldRenderer, PlayerRenderer};
 use crate::{
+    api::json::DirectionJson,
     player::{Player, Tagger},
     types::*,
     FIELD_SIZE,
 };
-use re::vao::VaoBuffer;
+use re::vao::{InstanceBuffer, Vao, VaoBuffer};
+use std::ops::Range;
+use uuid::Uuid;
 
-pub struct World<const X: usize, const Z: usize> {
+pub struct World {
     /// 各地点のブロックの高さを保持する
     ///
     /// ^ X軸  \
@@ -14,19 +18,20 @@ pub struct World<const X: usize, const Z: usize> {
     /// |  \
     /// |  \
     /// +---------> Z軸
-    field: na::SMatrix<i32, X, Z>,
+    field: FieldMatrix,
     /// 最後にフィールドを描画したときからフィールドに変更があったかどうか
     field_updated: bool,
     /// 最後にプレイヤーを描画したときからプレイヤーに変更があったかどうか
     players_updated: bool,
-    field_renderer: FieldRenderer<X, Z>,
+    field_renderer: FieldRenderer,
     player_renderer: PlayerRenderer,
 }
 
-impl World<FIELD_SIZE, FIELD_SIZE> {
+impl World {
+    /// サーバーからフィールドが届くまでは、`FIELD_SIZE`四方の平らなフィールドにする
     pub fn new() -> Self {
         Self {
-            field: FieldMatrix::zeros(),
+            field: FieldMatrix::zeros(FIELD_SIZE, FIELD_SIZE),
             field_updated: true,
             players_updated: true,
             field_renderer: FieldRenderer::new(),
@@ -34,13 +39,17 @@ impl World<FIELD_SIZE, FIELD_SIZE> {
         }
     }
 
+    /// プレイヤーを描くワールド座標。移動を予測していれば予測した位置
     pub fn player_world_pos(&self, player: &Player) -> Point3 {
-        PlayerRenderer::calc_world_pos(&player.pos, &self.field)
+        let pos = self
+            .player_renderer
+            .displayed_pos(player.uid)
+            .unwrap_or(player.pos);
+        PlayerRenderer::calc_world_pos(&pos, &self.field)
     }
 
     pub fn update(&mut self, height_map: FieldMatrix) {
         self.field = height_map;
-        self.field_renderer.make_diff(&self.field);
         self.field_updated = true;
     }
 
@@ -59,18 +68,106 @@ impl World<FIELD_SIZE, FIELD_SIZE> {
         self.players_updated = true;
     }
 
+    pub fn field(&self) -> &FieldMatrix {
+        &self.field
+    }
+
+    /// フィールドの真ん中の床の上のワールド座標
+    pub fn center(&self) -> Point3 {
+        Point3::new(
+            self.field.nrows() as f32 / 2.0,
+            0.0,
+            self.field.ncols() as f32 / 2.0,
+        )
+    }
+
+    pub fn players(&self) -> &[Player] {
+        self.player_renderer.players()
+    }
+
+    pub fn tagger(&self) -> Option<&Tagger> {
+        self.player_renderer.tagger()
+    }
+
     pub fn render_field(&mut self) -> &VaoBuffer {
         self.field_updated = false;
         self.field_renderer.render(&self.field);
         &self.field_renderer.vao_buffer
     }
 
-    pub fn render_players(&mut self) -> &VaoBuffer {
-        self.players_updated = false;
-        self.player_renderer.render(&self.field);
+    /// `render_field`の`build_dynamic`で作った`vao`を、変わった区画の頂点だけ書き換える
+    ///
+    /// フィールドの大きさが変わって頂点の数が変わったときは、何もせずに`false`を返す。
+    /// そのときは`render_field`から`Vao`を作り直す。
+    pub fn update_field_vao(&mut self, vao: &Vao) -> bool {
+        self.render_field();
+        if self.field_renderer.resized() {
+            return false;
+        }
+        for range in self.field_renderer.changed() {
+            self.field_renderer.vao_buffer.update_vao(vao, range.clone());
+        }
+        true
+    }
+
+    /// 最後の`render_field`で書き換えた頂点の範囲
+    pub fn changed_field_vertices(&self) -> &[Range<usize>] {
+        self.field_renderer.changed()
+    }
+
+    /// 次の`render_field`で、フィールドのすべてのマスを描き直す
+    pub fn invalidate_field(&mut self) {
+        self.field_renderer.invalidate();
+        self.field_updated = true;
+    }
+
+    /// フィールドの端の床のブロックの形。`field_blocks`で求めたそれぞれの位置に描く
+    pub fn block_mesh(&self) -> &VaoBuffer {
+        &self.field_renderer.block_buffer
+    }
+
+    /// 最後の`render_field`で描いたフィールドの、端の床のブロックの位置
+    ///
+    /// フィールドの大きさが変わったときだけ変わるので、`Vao`を作り直したときに渡し直す。
+    pub fn field_blocks(&self) -> &InstanceBuffer {
+        &self.field_renderer.blocks
+    }
+
+    /// プレイヤーと鬼の形。`render_players`で求めたそれぞれの位置に描く
+    pub fn player_mesh(&self) -> &VaoBuffer {
         &self.player_renderer.vao_buffer
     }
 
+    /// `frames`はゲーム開始からの総フレーム数。動いている途中のプレイヤーはその途中の位置に描く
+    pub fn render_players(&mut self, frames: u64) -> &InstanceBuffer {
+        self.players_updated = false;
+        self.player_renderer.render(&self.field, frames);
+        &self.player_renderer.instances
+    }
+
+    /// 名札を描くために、各プレイヤーと今描いている位置を返す
+    pub fn player_positions(&self, frames: u64) -> Vec<(&Player, Point3)> {
+        self.player_renderer.player_positions(frames)
+    }
+
+    /// 動いている途中のプレイヤーや鬼がいて、描き直す必要があるかどうか
+    pub fn players_moving(&self, frames: u64) -> bool {
+        self.player_renderer.is_moving(frames)
+    }
+
+    /// 自機を、サーバーの返事を待たずに`direction`へ動かす。動かした先のワールド座標を返す
+    ///
+    /// 次の`set_players`で届いた位置が予測と違えば、届いた位置に戻る。
+    pub fn predict_move(&mut self, uid: Uuid, direction: DirectionJson) -> Option<Point3> {
+        let pos = moved(&self.player_renderer.displayed_pos(uid)?, direction);
+        if !in_field(&self.field, &pos) {
+            return None;
+        }
+        self.player_renderer.predict(uid, pos);
+        self.players_updated = true;
+        Some(PlayerRenderer::calc_world_pos(&pos, &self.field))
+    }
+
     pub fn field_updated(&self) -> bool {
         self.field_updated
     }